@@ -0,0 +1,304 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::Rng;
+
+use crate::bitcoin::peer_address::PeerAddress;
+
+/// Number of buckets for addresses which were never connected to
+pub const NEW_BUCKET_COUNT: usize = 1024;
+/// Number of buckets for addresses which completed a handshake
+pub const TRIED_BUCKET_COUNT: usize = 256;
+/// Number of addresses in a single bucket
+pub const BUCKET_SIZE: usize = 64;
+/// Number of new buckets the addresses from a single source group can
+/// be spread over
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+/// Number of tried buckets the addresses of a single group can be spread over
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+/// Failed attempts after which an address that never succeeded is terrible
+const RETRIES: u32 = 3;
+/// Failed attempts in a row after which an address is terrible
+const MAX_FAILURES: u32 = 10;
+/// Time without success after which [MAX_FAILURES] makes an address terrible
+const MIN_FAIL_SECS: u64 = 7 * 24 * 60 * 60;
+/// Time after an attempt during which the address is rarely selected again
+const RECENT_TRY_SECS: u64 = 10 * 60;
+
+/// What the address manager knows about a single address
+#[derive(Debug, Clone)]
+pub struct AddrInfo {
+    pub address: PeerAddress,
+    /// Node the address was learned from
+    pub source: PeerAddress,
+    /// Number of attempts since the last successful handshake
+    pub attempts: u32,
+    /// Unix time of the last attempt in seconds
+    pub last_try: Option<u64>,
+    /// Unix time of the last successful handshake in seconds
+    pub last_success: Option<u64>,
+    /// Whether the address is in the tried table
+    pub tried: bool,
+    bucket: usize,
+}
+
+impl AddrInfo {
+    /// Returns whether the address is not worth keeping, because it never
+    /// succeeded in a few attempts or kept failing for a long time.
+    pub fn is_terrible(&self, now: u64) -> bool {
+        match self.last_success {
+            None => self.attempts >= RETRIES,
+            Some(last_success) => {
+                self.attempts >= MAX_FAILURES && now.saturating_sub(last_success) > MIN_FAIL_SECS
+            }
+        }
+    }
+
+    /// Returns the relative chance of the address being selected, which
+    /// drops with every failed attempt and right after any attempt.
+    pub fn chance(&self, now: u64) -> f64 {
+        let mut chance = 1.0;
+        if self
+            .last_try
+            .is_some_and(|last_try| now.saturating_sub(last_try) < RECENT_TRY_SECS)
+        {
+            chance *= 0.01;
+        }
+        chance * 0.66f64.powi(self.attempts.min(8) as i32)
+    }
+}
+
+/// Address manager modelled on the one of Bitcoin Core. Addresses learned
+/// from other nodes are kept in "new" buckets chosen by the network group
+/// of the node they came from, so a single source can only fill a small
+/// part of the table. Addresses which complete a handshake are moved to
+/// "tried" buckets. When a bucket is full, a new address only replaces a
+/// terrible one, and a newly tried address pushes the one with the oldest
+/// success back to the new table. Selection is biased toward reliable
+/// addresses.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::{addrman::AddrMan, peer_address::PeerAddress};
+///
+/// let mut addrman = AddrMan::new();
+/// let source: PeerAddress = "198.51.100.1".parse().unwrap();
+/// let address: PeerAddress = "203.0.113.7".parse().unwrap();
+/// assert!(addrman.add(address.clone(), &source));
+/// addrman.attempt(&address);
+/// addrman.good(&address);
+/// assert_eq!(addrman.tried_count(), 1);
+/// assert_eq!(addrman.select(), Some(address));
+/// ```
+#[derive(Debug)]
+pub struct AddrMan {
+    hasher: RandomState,
+    bucket_size: usize,
+    new: Vec<Vec<PeerAddress>>,
+    tried: Vec<Vec<PeerAddress>>,
+    entries: HashMap<PeerAddress, AddrInfo>,
+}
+
+impl Default for AddrMan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AddrMan {
+    /// Creates an empty address manager with the table sizes of Bitcoin Core
+    pub fn new() -> AddrMan {
+        AddrMan::with_capacity(NEW_BUCKET_COUNT, TRIED_BUCKET_COUNT, BUCKET_SIZE)
+    }
+
+    /// Creates an empty address manager with the provided number of new and
+    /// tried buckets, each holding up to `bucket_size` addresses.
+    pub fn with_capacity(new_buckets: usize, tried_buckets: usize, bucket_size: usize) -> AddrMan {
+        AddrMan {
+            hasher: RandomState::new(),
+            bucket_size,
+            new: vec![Vec::new(); new_buckets.max(1)],
+            tried: vec![Vec::new(); tried_buckets.max(1)],
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Adds the address learned from `source` to the new table. Returns
+    /// false if the address is already known or its bucket is full of
+    /// addresses which are still worth keeping.
+    pub fn add(&mut self, address: PeerAddress, source: &PeerAddress) -> bool {
+        if self.entries.contains_key(&address) {
+            return false;
+        }
+        let info = AddrInfo {
+            bucket: self.new_bucket(&address, source),
+            address,
+            source: source.clone(),
+            attempts: 0,
+            last_try: None,
+            last_success: None,
+            tried: false,
+        };
+        self.insert_new(info)
+    }
+
+    /// Records an attempt to connect to the address
+    pub fn attempt(&mut self, address: &PeerAddress) {
+        if let Some(info) = self.entries.get_mut(address) {
+            info.attempts += 1;
+            info.last_try = Some(unix_time());
+        }
+    }
+
+    /// Records a successful handshake with the address and moves it to the
+    /// tried table.
+    pub fn good(&mut self, address: &PeerAddress) {
+        let now = unix_time();
+        let bucket = self.tried_bucket(address);
+        let Some(info) = self.entries.get_mut(address) else {
+            return;
+        };
+        info.attempts = 0;
+        info.last_try = Some(now);
+        info.last_success = Some(now);
+        if info.tried {
+            return;
+        }
+        let old_bucket = std::mem::replace(&mut info.bucket, bucket);
+        info.tried = true;
+        self.new[old_bucket].retain(|a| a != address);
+
+        if self.tried[bucket].len() >= self.bucket_size {
+            let evicted = self.tried[bucket]
+                .iter()
+                .min_by_key(|a| self.entries[*a].last_success)
+                .cloned()
+                .expect("full bucket is not empty");
+            self.tried[bucket].retain(|a| a != &evicted);
+            let mut info = self
+                .entries
+                .remove(&evicted)
+                .expect("bucket entry is known");
+            info.tried = false;
+            info.bucket = self.new_bucket(&info.address, &info.source);
+            self.insert_new(info);
+        }
+        self.tried[bucket].push(address.clone());
+    }
+
+    /// Selects an address to connect to, see [select_where]
+    ///
+    /// [select_where]: AddrMan::select_where
+    pub fn select(&self) -> Option<PeerAddress> {
+        self.select_where(|_| true)
+    }
+
+    /// Selects a random address accepted by the filter. The tried and new
+    /// tables are picked with equal odds, and within the table addresses
+    /// are weighted by their [chance].
+    ///
+    /// [chance]: AddrInfo::chance
+    pub fn select_where<F>(&self, filter: F) -> Option<PeerAddress>
+    where
+        F: Fn(&PeerAddress) -> bool,
+    {
+        let (tried, new): (Vec<&AddrInfo>, Vec<&AddrInfo>) = self
+            .entries
+            .values()
+            .filter(|info| filter(&info.address))
+            .partition(|info| info.tried);
+        let mut rng = rand::thread_rng();
+        let table = match (tried.is_empty(), new.is_empty()) {
+            (true, true) => return None,
+            (false, true) => tried,
+            (true, false) => new,
+            (false, false) if rng.gen_bool(0.5) => tried,
+            (false, false) => new,
+        };
+        let now = unix_time();
+        let total: f64 = table.iter().map(|info| info.chance(now)).sum();
+        let mut target = rng.gen_range(0.0..total);
+        for info in &table {
+            target -= info.chance(now);
+            if target < 0.0 {
+                return Some(info.address.clone());
+            }
+        }
+        table.last().map(|info| info.address.clone())
+    }
+
+    /// Returns what is known about the address
+    pub fn get(&self, address: &PeerAddress) -> Option<&AddrInfo> {
+        self.entries.get(address)
+    }
+
+    /// Returns whether the address is in any of the tables
+    pub fn contains(&self, address: &PeerAddress) -> bool {
+        self.entries.contains_key(address)
+    }
+
+    /// Returns the number of known addresses
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether no address is known
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the number of addresses in the new table
+    pub fn new_count(&self) -> usize {
+        self.new.iter().map(Vec::len).sum()
+    }
+
+    /// Returns the number of addresses in the tried table
+    pub fn tried_count(&self) -> usize {
+        self.tried.iter().map(Vec::len).sum()
+    }
+
+    fn insert_new(&mut self, info: AddrInfo) -> bool {
+        let bucket = info.bucket;
+        if self.new[bucket].len() >= self.bucket_size {
+            let now = unix_time();
+            let Some(position) = self.new[bucket]
+                .iter()
+                .position(|a| self.entries[a].is_terrible(now))
+            else {
+                return false;
+            };
+            let terrible = self.new[bucket].remove(position);
+            self.entries.remove(&terrible);
+        }
+        self.new[bucket].push(info.address.clone());
+        self.entries.insert(info.address.clone(), info);
+        true
+    }
+
+    fn new_bucket(&self, address: &PeerAddress, source: &PeerAddress) -> usize {
+        let source_group = source.group();
+        let slot =
+            self.hash(("new", address.group(), &source_group)) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        (self.hash(("new", &source_group, slot)) % self.new.len() as u64) as usize
+    }
+
+    fn tried_bucket(&self, address: &PeerAddress) -> usize {
+        let slot = self.hash(("tried", address)) % TRIED_BUCKETS_PER_GROUP;
+        (self.hash(("tried", address.group(), slot)) % self.tried.len() as u64) as usize
+    }
+
+    fn hash<T: Hash>(&self, value: T) -> u64 {
+        self.hasher.hash_one(value)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+
+use bitcoin::{Block, BlockHash};
+
+/// Error enumeration of the reasons a block received from a node is not
+/// accepted
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum BlockError {
+    #[error("Block {received} was received instead of {requested}")]
+    WrongBlock {
+        requested: BlockHash,
+        received: BlockHash,
+    },
+    #[error("Block {hash} has no transactions")]
+    NoTransactions { hash: BlockHash },
+    #[error("Block {hash} contains a transaction more than once")]
+    DuplicateTransaction { hash: BlockHash },
+    #[error("Block {hash} does not match its merkle root")]
+    BadMerkleRoot { hash: BlockHash },
+    #[error("Block {hash} does not match its witness commitment")]
+    BadWitnessCommitment { hash: BlockHash },
+}
+
+/// Checks that the block is the requested one and that its transactions
+/// are the ones committed to by its header. Proof of work is not checked,
+/// the header is expected to be validated as part of a [HeaderChain].
+///
+/// [HeaderChain]: crate::bitcoin::headers::HeaderChain
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::{blockdata::constants::genesis_block, Network};
+/// use p2p_handshake_bitcoin::bitcoin::blocks::validate_block;
+///
+/// let genesis = genesis_block(Network::Bitcoin);
+/// assert!(validate_block(&genesis, genesis.block_hash()).is_ok());
+/// ```
+pub fn validate_block(block: &Block, requested: BlockHash) -> Result<(), BlockError> {
+    let hash = block.block_hash();
+    if hash != requested {
+        return Err(BlockError::WrongBlock {
+            requested,
+            received: hash,
+        });
+    }
+    if block.txdata.is_empty() {
+        return Err(BlockError::NoTransactions { hash });
+    }
+    // A merkle tree duplicates the last hash of odd levels, so a block
+    // which repeats its last transactions has the same root as the block
+    // without them
+    let mut txids = HashSet::with_capacity(block.txdata.len());
+    if !block.txdata.iter().all(|tx| txids.insert(tx.txid())) {
+        return Err(BlockError::DuplicateTransaction { hash });
+    }
+    if !block.check_merkle_root() {
+        return Err(BlockError::BadMerkleRoot { hash });
+    }
+    if !block.check_witness_commitment() {
+        return Err(BlockError::BadWitnessCommitment { hash });
+    }
+    Ok(())
+}
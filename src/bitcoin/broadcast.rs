@@ -0,0 +1,433 @@
+use std::{io::Write, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use bitcoin::{
+    consensus::deserialize,
+    hex::FromHex,
+    p2p::{message_blockdata::Inventory, message_network::RejectReason},
+    Transaction, Txid, Wtxid,
+};
+use serde::Serialize;
+
+use crate::{
+    bitcoin::{
+        client_pool::{
+            session_channel, BitcoinClientPool, NodeOutcome, PeerSession, PoolConfig,
+            SessionFuture, SessionSender, TcpBitcoinClient,
+        },
+        peer_address::PeerAddress,
+    },
+    output::{write_columns, ReportFormat},
+};
+
+/// Settings of a transaction broadcast
+#[derive(Debug, Clone)]
+pub struct BroadcastConfig {
+    /// Number of nodes the transaction is announced to. Nodes which fail
+    /// the handshake are replaced by the next ones, if there are any.
+    pub peers: usize,
+    /// How long to wait for a node to request the transaction after it
+    /// was announced, and for reject messages
+    pub wait: Duration,
+    /// Whether to keep listening for reject messages until the wait is
+    /// over, instead of moving on once the transaction was requested
+    pub collect_rejects: bool,
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        Self {
+            peers: 8,
+            wait: Duration::from_secs(10),
+            collect_rejects: false,
+        }
+    }
+}
+
+/// Request of an announced transaction by a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxRequest {
+    /// Inventory item the transaction was requested with
+    pub inventory: Inventory,
+    /// Time from the announcement to the request
+    pub after: Duration,
+}
+
+impl TxRequest {
+    /// Returns the name of the inventory type the transaction was
+    /// requested with, as used in reports
+    pub fn kind(&self) -> &'static str {
+        match self.inventory {
+            Inventory::WTx(_) => "wtx",
+            Inventory::WitnessTransaction(_) => "witness_tx",
+            _ => "tx",
+        }
+    }
+}
+
+/// Reject message a node sent about the transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxReject {
+    pub code: RejectReason,
+    pub reason: String,
+}
+
+impl TxReject {
+    /// Returns the name of the reject code, as used in reports
+    pub fn code_name(&self) -> &'static str {
+        match self.code {
+            RejectReason::Malformed => "malformed",
+            RejectReason::Invalid => "invalid",
+            RejectReason::Obsolete => "obsolete",
+            RejectReason::Duplicate => "duplicate",
+            RejectReason::NonStandard => "nonstandard",
+            RejectReason::Dust => "dust",
+            RejectReason::Fee => "fee",
+            RejectReason::Checkpoint => "checkpoint",
+        }
+    }
+}
+
+/// What happened to a transaction announced to a node
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxAnnouncement {
+    /// First request of the transaction, if the node asked for it
+    pub request: Option<TxRequest>,
+    /// Reject messages about the transaction
+    pub rejects: Vec<TxReject>,
+}
+
+/// Returns the transaction in the hex string, as printed by
+/// `getrawtransaction` or `createrawtransaction`
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::{blockdata::constants::genesis_block, consensus::serialize, hex::DisplayHex, Network};
+/// use p2p_handshake_bitcoin::bitcoin::broadcast::parse_transaction;
+///
+/// let coinbase = &genesis_block(Network::Bitcoin).txdata[0];
+/// let hex = serialize(coinbase).to_lower_hex_string();
+/// assert_eq!(&parse_transaction(&hex).unwrap(), coinbase);
+/// assert!(parse_transaction("00").is_err());
+/// ```
+pub fn parse_transaction(hex: &str) -> anyhow::Result<Transaction> {
+    let bytes = Vec::<u8>::from_hex(hex.trim()).context("Transaction is not valid hex")?;
+    deserialize(&bytes).context("Transaction could not be decoded")
+}
+
+/// Transaction announced to a single node
+#[derive(Debug, Clone)]
+pub struct PeerBroadcast {
+    pub node: PeerAddress,
+    pub announcement: TxAnnouncement,
+    /// Error which ended the session before the wait was over
+    pub error: Option<String>,
+}
+
+/// Result of broadcasting a transaction
+#[derive(Debug, Clone)]
+pub struct BroadcastReport {
+    pub txid: Txid,
+    pub wtxid: Wtxid,
+    /// Number of nodes handshakes were attempted with
+    pub attempted: usize,
+    /// Nodes which completed the handshake and the transaction was
+    /// announced to
+    pub peers: Vec<PeerBroadcast>,
+}
+
+/// Session which announces the transaction to the node and passes on
+/// what happened to it together with the address of the node.
+struct AnnounceSession {
+    transaction: Transaction,
+    config: BroadcastConfig,
+    sender: SessionSender<PeerBroadcast>,
+}
+
+impl PeerSession for AnnounceSession {
+    fn run<'a>(
+        &'a self,
+        node: &'a PeerAddress,
+        client: &'a mut TcpBitcoinClient,
+    ) -> SessionFuture<'a> {
+        Box::pin(async move {
+            let result = client
+                .announce_transaction(
+                    &self.transaction,
+                    self.config.wait,
+                    self.config.collect_rejects,
+                )
+                .await;
+            let (announcement, error) = match &result {
+                Ok(announcement) => (announcement.clone(), None),
+                Err(e) => (TxAnnouncement::default(), Some(e.to_string())),
+            };
+            self.sender.send(PeerBroadcast {
+                node: node.clone(),
+                announcement,
+                error,
+            });
+            result?;
+            Ok(())
+        })
+    }
+}
+
+/// Broadcast which announces a transaction to a number of nodes with inv,
+/// serves it to the nodes which request it with getdata and reports which
+/// of them did.
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::{blockdata::constants::genesis_block, Network};
+/// use p2p_handshake_bitcoin::bitcoin::broadcast::{BroadcastConfig, TxBroadcast};
+/// use p2p_handshake_bitcoin::bitcoin::client_pool::PoolConfig;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let transaction = genesis_block(Network::Bitcoin).txdata[0].clone();
+///     let broadcast = TxBroadcast::new(BroadcastConfig::default(), PoolConfig::default(), transaction);
+///     let nodes = vec!["127.0.0.1:1".parse().unwrap()];
+///     let report = broadcast.run(nodes, |_| Ok(())).await.unwrap();
+///     assert_eq!(report.attempted, 1);
+///     assert!(report.peers.is_empty());
+/// }
+/// ```
+pub struct TxBroadcast {
+    config: BroadcastConfig,
+    pool_config: PoolConfig,
+    transaction: Transaction,
+}
+
+impl TxBroadcast {
+    /// Creates a broadcast of the transaction. Any session in the pool
+    /// configuration is replaced by the announcement.
+    pub fn new(
+        config: BroadcastConfig,
+        pool_config: PoolConfig,
+        transaction: Transaction,
+    ) -> TxBroadcast {
+        TxBroadcast {
+            config,
+            pool_config,
+            transaction,
+        }
+    }
+
+    /// Handshakes with the nodes, in order, until the transaction was
+    /// announced to the configured number of them. Every node outcome is
+    /// passed to `on_outcome` as soon as it is known.
+    pub async fn run<F>(
+        self,
+        nodes: Vec<PeerAddress>,
+        mut on_outcome: F,
+    ) -> Result<BroadcastReport, anyhow::Error>
+    where
+        F: FnMut(&NodeOutcome) -> Result<(), anyhow::Error>,
+    {
+        let txid = self.transaction.txid();
+        let wtxid = self.transaction.wtxid();
+        let (sender, mut receiver) = session_channel();
+        let pool_config = PoolConfig {
+            session: Some(Arc::new(AnnounceSession {
+                transaction: self.transaction,
+                config: self.config.clone(),
+                sender,
+            })),
+            ..self.pool_config
+        };
+        let mut pool = BitcoinClientPool::with_config(Vec::new(), pool_config);
+        let mut candidates = nodes.into_iter();
+        let mut report = BroadcastReport {
+            txid,
+            wtxid,
+            attempted: 0,
+            peers: Vec::new(),
+        };
+        let mut connected = 0;
+        loop {
+            while connected + pool.len() < self.config.peers {
+                let Some(node) = candidates.next() else {
+                    break;
+                };
+                pool.spawn(node);
+                report.attempted += 1;
+            }
+            let Some(outcome) = pool.next_outcome().await else {
+                break;
+            };
+            on_outcome(&outcome)?;
+            if outcome.result.is_ok() {
+                connected += 1;
+            }
+            for peer in receiver.drain() {
+                match &peer.announcement.request {
+                    Some(request) => tracing::info!(
+                        tx.inventory = request.kind(),
+                        tx.requested_after_ms = request.after.as_millis() as u64,
+                        "Node {} requested transaction {}",
+                        peer.node,
+                        txid
+                    ),
+                    None => {
+                        tracing::info!("Node {} did not request transaction {}", peer.node, txid)
+                    }
+                }
+                for reject in &peer.announcement.rejects {
+                    tracing::warn!(
+                        reject.code = reject.code_name(),
+                        reject.reason = %reject.reason,
+                        "Node {} rejected transaction {}",
+                        peer.node,
+                        txid
+                    );
+                }
+                report.peers.push(peer);
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl BroadcastReport {
+    /// Returns the nodes which requested the transaction
+    pub fn requested(&self) -> impl Iterator<Item = &PeerBroadcast> {
+        self.peers
+            .iter()
+            .filter(|peer| peer.announcement.request.is_some())
+    }
+
+    /// Returns the nodes which sent reject messages about the transaction
+    pub fn rejected(&self) -> impl Iterator<Item = &PeerBroadcast> {
+        self.peers
+            .iter()
+            .filter(|peer| !peer.announcement.rejects.is_empty())
+    }
+
+    /// Writes the report in the selected format
+    pub fn write<W: Write>(&self, writer: &mut W, format: ReportFormat) -> anyhow::Result<()> {
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut *writer, &BroadcastReportRecord::from(self))?;
+                writeln!(writer)?;
+            }
+            ReportFormat::Report => self.write_report(writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a readable report with the transaction and a row per node
+    fn write_report<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "Transaction: {} (wtxid {})", self.txid, self.wtxid)?;
+        writeln!(
+            writer,
+            "Nodes: {}, announced to: {}, requested by: {}, rejected by: {}",
+            self.attempted,
+            self.peers.len(),
+            self.requested().count(),
+            self.rejected().count()
+        )?;
+        if self.peers.is_empty() {
+            return Ok(());
+        }
+        let mut peers: Vec<&PeerBroadcast> = self.peers.iter().collect();
+        peers.sort_by_key(|peer| peer.node.to_string());
+        let rows: Vec<Vec<String>> = peers
+            .iter()
+            .map(|peer| {
+                let (status, after) = match (&peer.announcement.request, &peer.error) {
+                    (Some(request), _) => (
+                        format!("requested as {}", request.kind()),
+                        format!("{} ms", request.after.as_millis()),
+                    ),
+                    (None, Some(error)) => (format!("failed: {}", error), "-".to_string()),
+                    (None, None) => ("not requested".to_string(), "-".to_string()),
+                };
+                let rejects = peer
+                    .announcement
+                    .rejects
+                    .iter()
+                    .map(|reject| format!("{}: {}", reject.code_name(), reject.reason))
+                    .collect::<Vec<_>>();
+                vec![
+                    peer.node.to_string(),
+                    status,
+                    after,
+                    if rejects.is_empty() {
+                        "-".to_string()
+                    } else {
+                        rejects.join("; ")
+                    },
+                ]
+            })
+            .collect();
+        writeln!(writer)?;
+        write_columns(writer, &["NODE", "STATUS", "AFTER", "REJECTS"], &rows)
+    }
+}
+
+/// Serializable form of a [BroadcastReport]
+#[derive(Serialize)]
+struct BroadcastReportRecord {
+    txid: String,
+    wtxid: String,
+    attempted: usize,
+    peers: Vec<PeerBroadcastRecord>,
+}
+
+/// Serializable form of a [PeerBroadcast]
+#[derive(Serialize)]
+struct PeerBroadcastRecord {
+    node: String,
+    requested: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inventory: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requested_after_ms: Option<u64>,
+    rejects: Vec<TxRejectRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Serializable form of a [TxReject]
+#[derive(Serialize)]
+struct TxRejectRecord {
+    code: &'static str,
+    reason: String,
+}
+
+impl From<&BroadcastReport> for BroadcastReportRecord {
+    fn from(report: &BroadcastReport) -> Self {
+        BroadcastReportRecord {
+            txid: report.txid.to_string(),
+            wtxid: report.wtxid.to_string(),
+            attempted: report.attempted,
+            peers: report
+                .peers
+                .iter()
+                .map(|peer| {
+                    let request = peer.announcement.request.as_ref();
+                    PeerBroadcastRecord {
+                        node: peer.node.to_string(),
+                        requested: request.is_some(),
+                        inventory: request.map(TxRequest::kind),
+                        requested_after_ms: request.map(|r| r.after.as_millis() as u64),
+                        rejects: peer
+                            .announcement
+                            .rejects
+                            .iter()
+                            .map(|reject| TxRejectRecord {
+                                code: reject.code_name(),
+                                reason: reject.reason.clone(),
+                            })
+                            .collect(),
+                        error: peer.error.clone(),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+
+use crate::bitcoin::{connection::Tap, peer_address::PeerAddress};
+
+/// Link type of packets which start with an IPv4 or IPv6 header
+const LINKTYPE_RAW: u32 = 101;
+/// Largest TCP payload put into a single synthesised packet
+const MAX_SEGMENT: usize = 65_000;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Writer of packets in the pcap format, which Wireshark and tcpdump read
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::capture::PcapWriter;
+///
+/// let mut writer = PcapWriter::new(Vec::new()).unwrap();
+/// writer.write_packet(std::time::SystemTime::now(), &[0x45; 20]).unwrap();
+/// assert_eq!(writer.into_inner().len(), 24 + 16 + 20);
+/// ```
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Creates a pcap writer and writes the file header
+    pub fn new(mut writer: W) -> std::io::Result<PcapWriter<W>> {
+        writer.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&4u16.to_le_bytes())?;
+        // Time zone offset and timestamp accuracy
+        writer.write_all(&[0; 8])?;
+        writer.write_all(&(u16::MAX as u32).to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        Ok(PcapWriter { writer })
+    }
+
+    /// Creates a pcap writer which appends packets to a file that already
+    /// has its header
+    pub fn appending(writer: W) -> PcapWriter<W> {
+        PcapWriter { writer }
+    }
+
+    /// Writes an IP packet captured at the time and flushes it
+    pub fn write_packet(&mut self, time: SystemTime, packet: &[u8]) -> std::io::Result<()> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let length = packet.len() as u32;
+        self.writer
+            .write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(packet)?;
+        self.writer.flush()
+    }
+
+    /// Returns the inner writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+type SharedPcapWriter = Arc<Mutex<PcapWriter<BufWriter<File>>>>;
+
+enum CaptureTarget {
+    /// One file per node in the directory, which is only open while a
+    /// connection to the node is. Repeated attempts append to the file
+    /// created by the first one.
+    PerNode {
+        directory: PathBuf,
+        created: Mutex<HashSet<PeerAddress>>,
+    },
+    /// Single file with the connections to all nodes
+    Merged(SharedPcapWriter),
+}
+
+/// Capture of the traffic with the nodes into pcap files, either one file
+/// per node or a single merged one. TCP/IP headers are synthesised from the
+/// addresses of the connections, so tools like Wireshark can dissect the
+/// Bitcoin messages.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::capture::PcapCapture;
+/// use p2p_handshake_bitcoin::bitcoin::connection::Tap;
+///
+/// let path = std::env::temp_dir().join("p2p_handshake_bitcoin_doc.pcap");
+/// let capture = PcapCapture::merged(&path).unwrap();
+/// let node = "127.0.0.1:8333".parse().unwrap();
+/// let mut stream = capture
+///     .open(&node, "127.0.0.1:50000".parse().unwrap(), "127.0.0.1:8333".parse().unwrap())
+///     .unwrap();
+/// stream.sent(b"version");
+/// stream.received(b"version");
+/// ```
+pub struct PcapCapture {
+    target: CaptureTarget,
+}
+
+impl PcapCapture {
+    /// Creates a capture which writes one `<address>.pcap` file per node
+    /// into the directory, creating the directory if needed
+    pub fn per_node(directory: impl AsRef<Path>) -> anyhow::Result<PcapCapture> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+        Ok(PcapCapture {
+            target: CaptureTarget::PerNode {
+                directory: directory.to_path_buf(),
+                created: Mutex::new(HashSet::new()),
+            },
+        })
+    }
+
+    /// Creates a capture which writes the traffic with all nodes into a
+    /// single file
+    pub fn merged(path: impl AsRef<Path>) -> anyhow::Result<PcapCapture> {
+        Ok(PcapCapture {
+            target: CaptureTarget::Merged(create_pcap(path.as_ref())?),
+        })
+    }
+
+    /// Returns the name of the file the traffic with the node is written to
+    /// when capturing per node, e.g. `2001_db8__1_8333.pcap`
+    pub fn file_name(node: &PeerAddress) -> String {
+        format!("{}.pcap", node.file_stem())
+    }
+
+    /// Starts capturing a connection to the node, which is recorded as
+    /// opened by a TCP handshake between the addresses
+    pub fn open(
+        &self,
+        node: &PeerAddress,
+        local: SocketAddr,
+        remote: SocketAddr,
+    ) -> anyhow::Result<CaptureStream> {
+        let writer = match &self.target {
+            CaptureTarget::Merged(writer) => writer.clone(),
+            CaptureTarget::PerNode { directory, created } => {
+                let path = directory.join(PcapCapture::file_name(node));
+                let mut created = created.lock().expect("Capture files lock is poisoned");
+                if created.contains(node) {
+                    append_pcap(&path)?
+                } else {
+                    let writer = create_pcap(&path)?;
+                    created.insert(node.clone());
+                    writer
+                }
+            }
+        };
+        let mut stream = CaptureStream {
+            writer: Some(writer),
+            local,
+            remote,
+            local_sequence: rand::random(),
+            remote_sequence: rand::random(),
+        };
+        stream.open();
+        Ok(stream)
+    }
+}
+
+fn create_pcap(path: &Path) -> anyhow::Result<SharedPcapWriter> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let writer = PcapWriter::new(BufWriter::new(file))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(Arc::new(Mutex::new(writer)))
+}
+
+fn append_pcap(path: &Path) -> anyhow::Result<SharedPcapWriter> {
+    let file = OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(Arc::new(Mutex::new(PcapWriter::appending(BufWriter::new(
+        file,
+    )))))
+}
+
+/// Capture of a single connection, which turns the bytes sent and received
+/// into TCP segments with consecutive sequence numbers. Errors writing the
+/// capture are logged once, after which the connection is not captured.
+pub struct CaptureStream {
+    writer: Option<SharedPcapWriter>,
+    local: SocketAddr,
+    remote: SocketAddr,
+    local_sequence: u32,
+    remote_sequence: u32,
+}
+
+impl Tap for CaptureStream {
+    /// Records bytes sent to the node
+    fn sent(&mut self, data: &[u8]) {
+        for segment in data.chunks(MAX_SEGMENT) {
+            self.write(true, TCP_PSH | TCP_ACK, segment);
+            self.local_sequence = self.local_sequence.wrapping_add(segment.len() as u32);
+        }
+    }
+
+    /// Records bytes received from the node
+    fn received(&mut self, data: &[u8]) {
+        for segment in data.chunks(MAX_SEGMENT) {
+            self.write(false, TCP_PSH | TCP_ACK, segment);
+            self.remote_sequence = self.remote_sequence.wrapping_add(segment.len() as u32);
+        }
+    }
+}
+
+impl CaptureStream {
+    /// Records the TCP handshake opening the connection
+    fn open(&mut self) {
+        let local_isn = self.local_sequence;
+        self.write(true, TCP_SYN, &[]);
+        self.local_sequence = local_isn.wrapping_add(1);
+        // The SYN-ACK acknowledges the SYN, but has the initial sequence
+        // number of the node
+        let remote_isn = self.remote_sequence;
+        self.write(false, TCP_SYN | TCP_ACK, &[]);
+        self.remote_sequence = remote_isn.wrapping_add(1);
+        self.write(true, TCP_ACK, &[]);
+    }
+
+    fn write(&mut self, outgoing: bool, flags: u8, payload: &[u8]) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let (source, destination, sequence, acknowledgment) = if outgoing {
+            (
+                self.local,
+                self.remote,
+                self.local_sequence,
+                self.remote_sequence,
+            )
+        } else {
+            (
+                self.remote,
+                self.local,
+                self.remote_sequence,
+                self.local_sequence,
+            )
+        };
+        // Only the SYN carries no acknowledgment
+        let acknowledgment = if flags & TCP_ACK != 0 {
+            acknowledgment
+        } else {
+            0
+        };
+        let packet = ip_packet(
+            source,
+            destination,
+            &tcp_segment(
+                source,
+                destination,
+                sequence,
+                acknowledgment,
+                flags,
+                payload,
+            ),
+        );
+        let written = writer
+            .lock()
+            .map_err(|_| std::io::Error::other("Capture lock is poisoned"))
+            .and_then(|mut writer| writer.write_packet(SystemTime::now(), &packet));
+        if let Err(e) = written {
+            tracing::warn!(error.message = %e, "Failed to capture traffic with {}", self.remote);
+            self.writer = None;
+        }
+    }
+}
+
+/// Addresses of both ends as IPv4 if both are, otherwise as IPv6
+fn ip_pair(source: SocketAddr, destination: SocketAddr) -> (IpAddr, IpAddr) {
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => (IpAddr::V4(s), IpAddr::V4(d)),
+        (s, d) => (IpAddr::V6(to_ipv6(s)), IpAddr::V6(to_ipv6(d))),
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn tcp_segment(
+    source: SocketAddr,
+    destination: SocketAddr,
+    sequence: u32,
+    acknowledgment: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&source.port().to_be_bytes());
+    segment.extend_from_slice(&destination.port().to_be_bytes());
+    segment.extend_from_slice(&sequence.to_be_bytes());
+    segment.extend_from_slice(&acknowledgment.to_be_bytes());
+    // Header length of five 32-bit words, without options
+    segment.push(5 << 4);
+    segment.push(flags);
+    segment.extend_from_slice(&u16::MAX.to_be_bytes());
+    // Checksum, filled in below, and urgent pointer
+    segment.extend_from_slice(&[0; 4]);
+    segment.extend_from_slice(payload);
+
+    let (source, destination) = ip_pair(source, destination);
+    let mut pseudo_header = Vec::new();
+    match (source, destination) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            pseudo_header.extend_from_slice(&s.octets());
+            pseudo_header.extend_from_slice(&d.octets());
+            pseudo_header.extend_from_slice(&[0, 6]);
+            pseudo_header.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+        }
+        (s, d) => {
+            pseudo_header.extend_from_slice(&to_ipv6(s).octets());
+            pseudo_header.extend_from_slice(&to_ipv6(d).octets());
+            pseudo_header.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            pseudo_header.extend_from_slice(&[0, 0, 0, 6]);
+        }
+    }
+    let checksum = internet_checksum(&[&pseudo_header, &segment]);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+fn ip_packet(source: SocketAddr, destination: SocketAddr, segment: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(40 + segment.len());
+    match ip_pair(source, destination) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+            // Identification, don't fragment, time to live and TCP
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6]);
+            packet.extend_from_slice(&[0, 0]);
+            packet.extend_from_slice(&s.octets());
+            packet.extend_from_slice(&d.octets());
+            let checksum = internet_checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        (s, d) => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+            // TCP and hop limit
+            packet.extend_from_slice(&[6, 64]);
+            packet.extend_from_slice(&to_ipv6(s).octets());
+            packet.extend_from_slice(&to_ipv6(d).octets());
+        }
+    }
+    packet.extend_from_slice(segment);
+    packet
+}
+
+/// One's complement sum of the 16-bit words of the parts, as used by IP
+/// and TCP. Every part but the last has to be of even length.
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for word in part.chunks(2) {
+            let high = word[0] as u32;
+            let low = word.get(1).copied().unwrap_or(0) as u32;
+            sum += (high << 8) | low;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -1,13 +1,43 @@
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
 use bitcoin::{
+    bip158::BlockFilter,
+    block::Header,
     consensus::serialize,
-    p2p::message::{NetworkMessage, RawNetworkMessage},
+    hashes::Hash,
+    p2p::{
+        message::{NetworkMessage, RawNetworkMessage},
+        message_blockdata::{GetHeadersMessage, Inventory},
+        message_filter::{GetCFCheckpt, GetCFHeaders, GetCFilters},
+        message_network::VersionMessage,
+        ServiceFlags,
+    },
+    Block, BlockHash, FilterHeader, Network, ScriptBuf, Transaction,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::error::Elapsed,
 };
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::Instrument;
 
-use crate::{bitcoin::connection::Connection, bitcoin::message::BitcoinMessage};
+use crate::{
+    bitcoin::blocks::{validate_block, BlockError},
+    bitcoin::broadcast::{TxAnnouncement, TxReject, TxRequest},
+    bitcoin::connection::{Connection, Tap},
+    bitcoin::filters::{
+        FilterError, FilterHeaderChain, VerifiedFilter, BASIC_FILTER, CHECKPOINT_INTERVAL,
+        MAX_FILTERS_PER_MESSAGE, MAX_FILTER_HEADERS_PER_MESSAGE,
+    },
+    bitcoin::headers::{ChainTip, HeaderChain, HeaderError, MAX_HEADERS_PER_MESSAGE},
+    bitcoin::message::BitcoinMessage,
+    bitcoin::peer_address::PeerAddress,
+    bitcoin::timing::{Phase, PhaseTimings},
+};
 
 /// Client that is used to establish communication with the remote node.
 pub struct BitcoinClient<Reader, Writer>
@@ -16,6 +46,10 @@ where
     Writer: AsyncWriteExt + Unpin,
 {
     connection: Connection<Reader, Writer>,
+    network: Network,
+    timings: PhaseTimings,
+    relay: bool,
+    services: ServiceFlags,
 }
 
 /// Error enumeration to represent higher abstraction level of errors.
@@ -25,6 +59,16 @@ pub enum BitcoinClientError {
     CommunicationError,
     #[error("Message error: Returned message content is not valid")]
     MessageError,
+    #[error("Message error: Protocol version {0} is too old")]
+    VersionTooOld(u32),
+    #[error("Message error: {0}")]
+    InvalidHeader(#[from] HeaderError),
+    #[error("Message error: Block {0} was not found")]
+    BlockNotFound(BlockHash),
+    #[error("Message error: {0}")]
+    InvalidBlock(#[from] BlockError),
+    #[error("Message error: {0}")]
+    InvalidFilter(#[from] FilterError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -53,13 +97,98 @@ where
     /// };
     /// ```
     pub fn new(rx_stream: Reader, tx_stream: Writer) -> BitcoinClient<Reader, Writer> {
+        BitcoinClient::with_network(rx_stream, tx_stream, Network::Bitcoin)
+    }
+
+    /// Creates a Bitcoin client which talks to a node on the provided network.
+    /// Messages are sent with, and expected to arrive with, the magic bytes
+    /// of the network.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bitcoin::Network;
+    /// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    ///
+    /// async {
+    ///     let stream = Stream::new("127.0.0.1:18333", 200).await.unwrap();
+    ///     let bitcoin_client = BitcoinClient::with_network(stream.rx, stream.tx, Network::Testnet);
+    /// };
+    /// ```
+    pub fn with_network(
+        rx_stream: Reader,
+        tx_stream: Writer,
+        network: Network,
+    ) -> BitcoinClient<Reader, Writer> {
         let connection = Connection::new(rx_stream, tx_stream);
-        BitcoinClient { connection }
+        BitcoinClient {
+            connection,
+            network,
+            timings: PhaseTimings::start(),
+            relay: false,
+            services: ServiceFlags::NONE,
+        }
+    }
+
+    /// Continues measuring the phases of the attempt which connected the
+    /// streams, e.g. with the timings recorded by [Stream::connect].
+    /// Otherwise they are measured from the creation of the client.
+    ///
+    /// [Stream::connect]: crate::bitcoin::stream::Stream::connect
+    pub fn with_timings(mut self, timings: PhaseTimings) -> BitcoinClient<Reader, Writer> {
+        self.timings = timings;
+        self
+    }
+
+    /// Sets the relay flag of the version message, which asks the node to
+    /// announce the transactions entering its mempool with inv (BIP 37).
+    /// It is not set by default.
+    pub fn with_relay(mut self, relay: bool) -> BitcoinClient<Reader, Writer> {
+        self.relay = relay;
+        self
+    }
+
+    /// Passes the traffic with the node to the tap, e.g. a capture
+    pub fn with_tap(mut self, tap: impl Tap + 'static) -> BitcoinClient<Reader, Writer> {
+        self.connection.add_tap(Box::new(tap));
+        self
+    }
+
+    /// Returns when the phases of the handshake ended
+    pub fn timings(&self) -> PhaseTimings {
+        let mut timings = self.timings;
+        if let Some(instant) = self.connection.first_byte_at() {
+            timings.mark_at(Phase::FirstByte, instant);
+        }
+        timings
+    }
+
+    /// Returns the network the client talks to
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Returns the services the node advertised in its version message,
+    /// none before the handshake
+    pub fn services(&self) -> ServiceFlags {
+        self.services
+    }
+
+    /// Returns the number of bytes sent to the node
+    pub fn bytes_sent(&self) -> u64 {
+        self.connection.bytes_sent()
+    }
+
+    /// Returns the number of bytes received from the node
+    pub fn bytes_received(&self) -> u64 {
+        self.connection.bytes_received()
     }
 
     /// Bitcoin client performs handshake with the remote node provided in
     /// It sends the version message, accepts the version message, sends back
-    /// verack message and the accepts verack message.
+    /// verack message and the accepts verack message. Returns the version
+    /// message received from the remote node.
     /// Use [Stream] module as the basis.
     /// Example shows localhost ip address, instead use real bitcoin node ip.
     ///
@@ -79,22 +208,36 @@ where
     ///     let result = bitcoin_client.handshake().await;
     /// };
     /// ```
-    pub async fn handshake(&mut self) -> Result<(), BitcoinClientError> {
-        let bitcoin_version_message = BitcoinMessage::version_message();
-        let (message, count) = self
-            .handle_message(bitcoin_version_message)
-            .await
-            .context("Failed to handle version message")?;
-        self.verify_version_message(message, count)
-            .context("Failed to verify version message")?;
-        let bitcoin_verack_message = BitcoinMessage::verack_message();
-        let (message, count) = self
-            .handle_message(bitcoin_verack_message)
-            .await
-            .context("Failed to handle verack message")?;
-        self.verify_verack_message(message, count)
-            .context("Failed to verify verack message")?;
-        Ok(())
+    pub async fn handshake(&mut self) -> Result<VersionMessage, BitcoinClientError> {
+        let mut version = BitcoinMessage::get_bitcoin_version_message();
+        version.relay = self.relay;
+        let bitcoin_version_message =
+            BitcoinMessage::message_for(self.network, NetworkMessage::Version(version));
+        let version_message = async {
+            let (message, count) = self
+                .handle_message(bitcoin_version_message)
+                .await
+                .context("Failed to handle version message")?;
+            self.timings.mark(Phase::Version);
+            self.verify_version_message(message, count)
+                .context("Failed to verify version message")
+        }
+        .instrument(tracing::info_span!("Exchanging version"))
+        .await?;
+        let bitcoin_verack_message = BitcoinMessage::verack_message_for(self.network);
+        async {
+            let (message, count) = self
+                .handle_message(bitcoin_verack_message)
+                .await
+                .context("Failed to handle verack message")?;
+            self.timings.mark(Phase::Verack);
+            self.verify_verack_message(message, count)
+                .context("Failed to verify verack message")
+        }
+        .instrument(tracing::info_span!("Exchanging verack"))
+        .await?;
+        self.services = version_message.services;
+        Ok(version_message)
     }
 
     /// Bitcoin client with handle message sends message, receives response and
@@ -143,23 +286,645 @@ where
         }
     }
 
+    /// Sends a message with the provided payload to the remote node.
+    pub async fn send(&mut self, payload: NetworkMessage) -> Result<(), BitcoinClientError> {
+        let message = BitcoinMessage::message_for(self.network, payload);
+        self.connection
+            .write(serialize(&message).as_slice())
+            .await?;
+        Ok(())
+    }
+
+    /// Receives the next message from the remote node and returns its
+    /// payload. Pings are answered on the way and not returned.
+    pub async fn receive(&mut self) -> Result<NetworkMessage, BitcoinClientError> {
+        loop {
+            let message = match self.connection.read::<RawNetworkMessage>().await {
+                Ok(Some((message, _))) => message,
+                Ok(None) => {
+                    return Err(BitcoinClientError::UnexpectedError(anyhow::anyhow!(
+                        "Connection closed by remote node"
+                    )))
+                }
+                Err(_) => return Err(BitcoinClientError::CommunicationError),
+            };
+            if *message.magic() != self.network.magic() {
+                return Err(BitcoinClientError::MessageError);
+            }
+            match message.payload() {
+                NetworkMessage::Ping(nonce) => self.send(NetworkMessage::Pong(*nonce)).await?,
+                payload => return Ok(payload.clone()),
+            }
+        }
+    }
+
+    /// Receives messages from the remote node until `matching` picks one,
+    /// skipping the others, and returns what it picked. Like
+    /// [tokio::time::timeout], fails with [Elapsed] if none is picked
+    /// within `wait`.
+    async fn receive_matching<T, F>(
+        &mut self,
+        wait: Duration,
+        mut matching: F,
+    ) -> Result<Result<T, BitcoinClientError>, Elapsed>
+    where
+        F: FnMut(NetworkMessage) -> Option<T>,
+    {
+        tokio::time::timeout(wait, async {
+            loop {
+                if let Some(picked) = matching(self.receive().await?) {
+                    return Ok(picked);
+                }
+            }
+        })
+        .await
+    }
+
+    /// Asks the remote node for addresses of other nodes it knows about
+    /// and collects them for at most `wait`. Nodes often announce their own
+    /// address on their own, so collecting stops at the first reply with
+    /// more than one address. Only IPv4 and IPv6 addresses are returned.
+    pub async fn get_addresses(
+        &mut self,
+        wait: Duration,
+    ) -> Result<Vec<PeerAddress>, BitcoinClientError> {
+        self.send(NetworkMessage::GetAddr).await?;
+        let mut addresses = Vec::new();
+        let collected = self
+            .receive_matching(wait, |message| {
+                let received: Vec<SocketAddr> = match message {
+                    NetworkMessage::Addr(entries) => entries
+                        .iter()
+                        .filter_map(|(_, address)| address.socket_addr().ok())
+                        .collect(),
+                    NetworkMessage::AddrV2(entries) => entries
+                        .iter()
+                        .filter_map(|entry| entry.socket_addr().ok())
+                        .collect(),
+                    _ => return None,
+                };
+                let count = received.len();
+                addresses.extend(received.into_iter().map(PeerAddress::from));
+                (count > 1).then_some(())
+            })
+            .await;
+        match collected {
+            Ok(Err(e)) if addresses.is_empty() => Err(e),
+            _ => Ok(addresses),
+        }
+    }
+
+    /// Downloads the headers of the remote node's best chain on top of the
+    /// chain, validating each of them, and returns the new tip of the chain.
+    /// Headers are requested with getheaders until a batch is not full;
+    /// each batch must arrive within `wait`. Headers received before an
+    /// invalid one are kept in the chain.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use bitcoin::Network;
+    /// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+    /// use p2p_handshake_bitcoin::bitcoin::headers::HeaderChain;
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    ///
+    /// async {
+    ///     let stream = Stream::new("127.0.0.1:8333", 200).await.unwrap();
+    ///     let mut bitcoin_client = BitcoinClient::new(stream.rx, stream.tx);
+    ///     bitcoin_client.handshake().await.unwrap();
+    ///     let mut chain = HeaderChain::new(Network::Bitcoin);
+    ///     let tip = bitcoin_client
+    ///         .sync_headers(&mut chain, Duration::from_secs(10))
+    ///         .await
+    ///         .unwrap();
+    ///     println!("{} at height {}", tip.hash, tip.height);
+    /// };
+    /// ```
+    pub async fn sync_headers(
+        &mut self,
+        chain: &mut HeaderChain,
+        wait: Duration,
+    ) -> Result<ChainTip, BitcoinClientError> {
+        loop {
+            let headers = self.get_headers(chain.locator(), wait).await?;
+            let appended = chain.extend(&headers)?;
+            tracing::debug!(
+                headers.received = headers.len(),
+                headers.appended = appended,
+                headers.height = chain.height(),
+                "Received headers"
+            );
+            if headers.len() < MAX_HEADERS_PER_MESSAGE || appended == 0 {
+                return Ok(chain.tip());
+            }
+        }
+    }
+
+    /// Requests the headers following the first hash of the locator the
+    /// remote node has in its best chain, and returns them as received,
+    /// at most [MAX_HEADERS_PER_MESSAGE] of them. The headers must arrive
+    /// within `wait`.
+    pub async fn get_headers(
+        &mut self,
+        locator: Vec<BlockHash>,
+        wait: Duration,
+    ) -> Result<Vec<Header>, BitcoinClientError> {
+        self.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(
+            locator,
+            BlockHash::all_zeros(),
+        )))
+        .await?;
+        let headers = self
+            .receive_matching(wait, |message| match message {
+                NetworkMessage::Headers(headers) => Some(headers),
+                _ => None,
+            })
+            .await
+            .context("Timed out waiting for headers")??;
+        Ok(headers)
+    }
+
+    /// Downloads the block with the hash, including the witnesses of its
+    /// transactions, and returns it once it is verified to have the
+    /// transactions committed to by its header. Other blocks the node sends
+    /// in the meantime are skipped. The block must arrive within `wait`; a
+    /// node which does not have it answers with notfound.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use bitcoin::{blockdata::constants::genesis_block, Network};
+    /// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    ///
+    /// async {
+    ///     let stream = Stream::new("127.0.0.1:8333", 200).await.unwrap();
+    ///     let mut bitcoin_client = BitcoinClient::new(stream.rx, stream.tx);
+    ///     bitcoin_client.handshake().await.unwrap();
+    ///     let hash = genesis_block(Network::Bitcoin).block_hash();
+    ///     let block = bitcoin_client
+    ///         .get_block(hash, Duration::from_secs(10))
+    ///         .await
+    ///         .unwrap();
+    ///     println!("{} has {} transactions", hash, block.txdata.len());
+    /// };
+    /// ```
+    pub async fn get_block(
+        &mut self,
+        hash: BlockHash,
+        wait: Duration,
+    ) -> Result<Block, BitcoinClientError> {
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(hash)]))
+            .await?;
+        let requested = |item: &Inventory| match item {
+            Inventory::Block(h) | Inventory::WitnessBlock(h) => *h == hash,
+            _ => false,
+        };
+        let block = self
+            .receive_matching(wait, |message| match message {
+                NetworkMessage::Block(block) if block.block_hash() == hash => Some(Some(block)),
+                NetworkMessage::NotFound(inventory) if inventory.iter().any(requested) => {
+                    Some(None)
+                }
+                _ => None,
+            })
+            .await
+            .context("Timed out waiting for block")??
+            .ok_or(BitcoinClientError::BlockNotFound(hash))?;
+        validate_block(&block, hash)?;
+        tracing::debug!(
+            block.hash = %hash,
+            block.transactions = block.txdata.len(),
+            block.size = block.total_size(),
+            "Received block"
+        );
+        Ok(block)
+    }
+
+    /// Requests the filter headers at every multiple of the checkpoint
+    /// interval up to the block with the stop hash, with cfcheckpt. They
+    /// are meant to be compared across nodes, as a node which serves wrong
+    /// filters also serves its own checkpoints. The node must advertise
+    /// compact filters (BIP 157) and answer within `wait`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use bitcoin::{blockdata::constants::genesis_block, Network};
+    /// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    ///
+    /// async {
+    ///     let stream = Stream::new("127.0.0.1:8333", 200).await.unwrap();
+    ///     let mut bitcoin_client = BitcoinClient::new(stream.rx, stream.tx);
+    ///     bitcoin_client.handshake().await.unwrap();
+    ///     let hash = genesis_block(Network::Bitcoin).block_hash();
+    ///     let checkpoints = bitcoin_client
+    ///         .get_filter_checkpoints(hash, Duration::from_secs(10))
+    ///         .await
+    ///         .unwrap();
+    ///     println!("{} checkpoints", checkpoints.len());
+    /// };
+    /// ```
+    pub async fn get_filter_checkpoints(
+        &mut self,
+        stop_hash: BlockHash,
+        wait: Duration,
+    ) -> Result<Vec<FilterHeader>, BitcoinClientError> {
+        self.require_filters()?;
+        self.send(NetworkMessage::GetCFCheckpt(GetCFCheckpt {
+            filter_type: BASIC_FILTER,
+            stop_hash,
+        }))
+        .await?;
+        let checkpoints = self
+            .receive_matching(wait, |message| match message {
+                NetworkMessage::CFCheckpt(checkpoint)
+                    if checkpoint.filter_type == BASIC_FILTER
+                        && checkpoint.stop_hash == stop_hash =>
+                {
+                    Some(checkpoint.filter_headers)
+                }
+                _ => None,
+            })
+            .await
+            .context("Timed out waiting for filter checkpoints")??;
+        Ok(checkpoints)
+    }
+
+    /// Downloads the filter headers of the blocks in the header chain which
+    /// follow the tip of the filter header chain, with cfheaders, and
+    /// appends them to it. Every batch of headers must connect to the tip
+    /// and arrive within `wait`, and the headers must match the checkpoints
+    /// the node sends with cfcheckpt. Returns the height of the last filter
+    /// header. Headers appended before an invalid batch are kept.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use bitcoin::Network;
+    /// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+    /// use p2p_handshake_bitcoin::bitcoin::filters::FilterHeaderChain;
+    /// use p2p_handshake_bitcoin::bitcoin::headers::HeaderChain;
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    ///
+    /// async {
+    ///     let stream = Stream::new("127.0.0.1:8333", 200).await.unwrap();
+    ///     let mut bitcoin_client = BitcoinClient::new(stream.rx, stream.tx);
+    ///     bitcoin_client.handshake().await.unwrap();
+    ///     let wait = Duration::from_secs(10);
+    ///     let mut chain = HeaderChain::new(Network::Bitcoin);
+    ///     bitcoin_client.sync_headers(&mut chain, wait).await.unwrap();
+    ///     let mut filters = FilterHeaderChain::new();
+    ///     let height = bitcoin_client
+    ///         .sync_filter_headers(&chain, &mut filters, wait)
+    ///         .await
+    ///         .unwrap();
+    ///     println!("Filter headers up to height {}", height);
+    /// };
+    /// ```
+    pub async fn sync_filter_headers(
+        &mut self,
+        chain: &HeaderChain,
+        filters: &mut FilterHeaderChain,
+        wait: Duration,
+    ) -> Result<u32, BitcoinClientError> {
+        let tip = chain.tip();
+        let checkpoints = self.get_filter_checkpoints(tip.hash, wait).await?;
+        let expected = (tip.height / CHECKPOINT_INTERVAL) as usize;
+        if checkpoints.len() != expected {
+            return Err(FilterError::WrongCount {
+                expected,
+                actual: checkpoints.len(),
+            }
+            .into());
+        }
+        while filters.next_height() <= tip.height {
+            let start_height = filters.next_height();
+            let stop_height = tip
+                .height
+                .min(start_height + MAX_FILTER_HEADERS_PER_MESSAGE - 1);
+            // Both ends of the batch must be in the header chain, which
+            // may start after the filter header chain
+            Self::hash_at(chain, start_height)?;
+            let stop_hash = Self::hash_at(chain, stop_height)?;
+            self.send(NetworkMessage::GetCFHeaders(GetCFHeaders {
+                filter_type: BASIC_FILTER,
+                start_height,
+                stop_hash,
+            }))
+            .await?;
+            let headers = self
+                .receive_matching(wait, |message| match message {
+                    NetworkMessage::CFHeaders(headers)
+                        if headers.filter_type == BASIC_FILTER
+                            && headers.stop_hash == stop_hash =>
+                    {
+                        Some(headers)
+                    }
+                    _ => None,
+                })
+                .await
+                .context("Timed out waiting for filter headers")??;
+            let expected = (stop_height - start_height + 1) as usize;
+            if headers.filter_hashes.len() != expected {
+                return Err(FilterError::WrongCount {
+                    expected,
+                    actual: headers.filter_hashes.len(),
+                }
+                .into());
+            }
+            filters.extend(
+                start_height,
+                headers.previous_filter_header,
+                &headers.filter_hashes,
+            )?;
+            filters.check_checkpoints(&checkpoints, start_height..=stop_height)?;
+            tracing::debug!(
+                filters.received = headers.filter_hashes.len(),
+                filters.height = stop_height,
+                "Received filter headers"
+            );
+        }
+        Ok(tip.height)
+    }
+
+    /// Downloads the basic filters of the blocks in the header chain from
+    /// the start to the stop height, with getcfilters, and checks each of
+    /// them against its filter header. Filters of other blocks are skipped.
+    /// Every batch of filters must arrive within `wait`. All heights must
+    /// be in the filter header chain.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use bitcoin::Network;
+    /// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+    /// use p2p_handshake_bitcoin::bitcoin::filters::FilterHeaderChain;
+    /// use p2p_handshake_bitcoin::bitcoin::headers::HeaderChain;
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    ///
+    /// async {
+    ///     let stream = Stream::new("127.0.0.1:8333", 200).await.unwrap();
+    ///     let mut bitcoin_client = BitcoinClient::new(stream.rx, stream.tx);
+    ///     bitcoin_client.handshake().await.unwrap();
+    ///     let wait = Duration::from_secs(10);
+    ///     let mut chain = HeaderChain::new(Network::Bitcoin);
+    ///     bitcoin_client.sync_headers(&mut chain, wait).await.unwrap();
+    ///     let mut filters = FilterHeaderChain::new();
+    ///     bitcoin_client
+    ///         .sync_filter_headers(&chain, &mut filters, wait)
+    ///         .await
+    ///         .unwrap();
+    ///     let verified = bitcoin_client
+    ///         .get_filters(&chain, &filters, 0, 99, wait)
+    ///         .await
+    ///         .unwrap();
+    ///     println!("{} filters", verified.len());
+    /// };
+    /// ```
+    pub async fn get_filters(
+        &mut self,
+        chain: &HeaderChain,
+        filters: &FilterHeaderChain,
+        start_height: u32,
+        stop_height: u32,
+        wait: Duration,
+    ) -> Result<Vec<VerifiedFilter>, BitcoinClientError> {
+        self.require_filters()?;
+        let heights = start_height..=stop_height;
+        if !heights.is_empty() && !filters.contains(&heights) {
+            return Err(FilterError::OutsideChain {
+                start: start_height,
+                stop: stop_height,
+            }
+            .into());
+        }
+        let mut verified = Vec::new();
+        let mut batch_start = start_height;
+        while batch_start <= stop_height {
+            let batch_stop = stop_height.min(batch_start + MAX_FILTERS_PER_MESSAGE - 1);
+            let stop_hash = Self::hash_at(chain, batch_stop)?;
+            self.send(NetworkMessage::GetCFilters(GetCFilters {
+                filter_type: BASIC_FILTER,
+                start_height: batch_start,
+                stop_hash,
+            }))
+            .await?;
+            let deadline = Instant::now() + wait;
+            for height in batch_start..=batch_stop {
+                let expected = Self::hash_at(chain, height)?;
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let filter = self
+                    .receive_matching(remaining, |message| match message {
+                        NetworkMessage::CFilter(filter)
+                            if filter.filter_type == BASIC_FILTER
+                                && filter.block_hash == expected =>
+                        {
+                            Some(filter.filter)
+                        }
+                        _ => None,
+                    })
+                    .await
+                    .context("Timed out waiting for filters")??;
+                verified.push(filters.verify(height, expected, BlockFilter::new(&filter))?);
+            }
+            tracing::debug!(
+                filters.start = batch_start,
+                filters.stop = batch_stop,
+                "Received filters"
+            );
+            batch_start = batch_stop + 1;
+        }
+        Ok(verified)
+    }
+
+    /// Scans the filters of the blocks from the start height up to the tip
+    /// of the filter header chain for the scripts of a watchlist, and
+    /// returns the filters of the blocks which may spend from or pay to any
+    /// of them. Only the filters of one batch are kept at a time.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use bitcoin::{Network, ScriptBuf};
+    /// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+    /// use p2p_handshake_bitcoin::bitcoin::filters::FilterHeaderChain;
+    /// use p2p_handshake_bitcoin::bitcoin::headers::HeaderChain;
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    ///
+    /// async {
+    ///     let stream = Stream::new("127.0.0.1:8333", 200).await.unwrap();
+    ///     let mut bitcoin_client = BitcoinClient::new(stream.rx, stream.tx);
+    ///     bitcoin_client.handshake().await.unwrap();
+    ///     let wait = Duration::from_secs(10);
+    ///     let mut chain = HeaderChain::new(Network::Bitcoin);
+    ///     bitcoin_client.sync_headers(&mut chain, wait).await.unwrap();
+    ///     let mut filters = FilterHeaderChain::new();
+    ///     bitcoin_client
+    ///         .sync_filter_headers(&chain, &mut filters, wait)
+    ///         .await
+    ///         .unwrap();
+    ///     let watchlist = vec![ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()];
+    ///     for matched in bitcoin_client
+    ///         .scan_filters(&chain, &filters, &watchlist, 0, wait)
+    ///         .await
+    ///         .unwrap()
+    ///     {
+    ///         println!("Block {} at height {} matches", matched.block_hash, matched.height);
+    ///     }
+    /// };
+    /// ```
+    pub async fn scan_filters(
+        &mut self,
+        chain: &HeaderChain,
+        filters: &FilterHeaderChain,
+        scripts: &[ScriptBuf],
+        start_height: u32,
+        wait: Duration,
+    ) -> Result<Vec<VerifiedFilter>, BitcoinClientError> {
+        let mut matches = Vec::new();
+        let Some(stop_height) = filters.height() else {
+            return Ok(matches);
+        };
+        let mut batch_start = start_height;
+        while batch_start <= stop_height {
+            let batch_stop = stop_height.min(batch_start + MAX_FILTERS_PER_MESSAGE - 1);
+            for filter in self
+                .get_filters(chain, filters, batch_start, batch_stop, wait)
+                .await?
+            {
+                if filter.matches_any(scripts)? {
+                    matches.push(filter);
+                }
+            }
+            batch_start = batch_stop + 1;
+        }
+        Ok(matches)
+    }
+
+    /// Announces the transaction to the remote node with inv and serves it
+    /// when the node requests it with getdata, for at most `wait`. Unless
+    /// `collect_rejects` is set, it returns as soon as the transaction was
+    /// served, otherwise it keeps collecting reject messages about it until
+    /// the wait is over. The handshake does not negotiate wtxid relay, so
+    /// the transaction is announced by its txid; requests by wtxid are
+    /// served as well.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use bitcoin::{blockdata::constants::genesis_block, Network};
+    /// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    ///
+    /// async {
+    ///     let stream = Stream::new("127.0.0.1:8333", 200).await.unwrap();
+    ///     let mut bitcoin_client = BitcoinClient::new(stream.rx, stream.tx);
+    ///     bitcoin_client.handshake().await.unwrap();
+    ///     let transaction = genesis_block(Network::Bitcoin).txdata[0].clone();
+    ///     let announcement = bitcoin_client
+    ///         .announce_transaction(&transaction, Duration::from_secs(10), false)
+    ///         .await
+    ///         .unwrap();
+    ///     println!("Requested: {}", announcement.request.is_some());
+    /// };
+    /// ```
+    pub async fn announce_transaction(
+        &mut self,
+        transaction: &Transaction,
+        wait: Duration,
+        collect_rejects: bool,
+    ) -> Result<TxAnnouncement, BitcoinClientError> {
+        let txid = transaction.txid();
+        let wtxid = transaction.wtxid();
+        let announced = Instant::now();
+        self.send(NetworkMessage::Inv(vec![Inventory::Transaction(txid)]))
+            .await?;
+        let mut announcement = TxAnnouncement::default();
+        let deadline = tokio::time::Instant::from_std(announced + wait);
+        loop {
+            let message = match tokio::time::timeout_at(deadline, self.receive()).await {
+                Ok(Ok(message)) => message,
+                // Nodes may hang up once they have the transaction
+                Ok(Err(e)) if announcement.request.is_none() => return Err(e),
+                Ok(Err(_)) | Err(_) => break,
+            };
+            match message {
+                NetworkMessage::GetData(inventory) => {
+                    let Some(item) = inventory.into_iter().find(|item| match item {
+                        Inventory::Transaction(id) | Inventory::WitnessTransaction(id) => {
+                            *id == txid
+                        }
+                        Inventory::WTx(id) => *id == wtxid,
+                        _ => false,
+                    }) else {
+                        continue;
+                    };
+                    self.send(NetworkMessage::Tx(transaction.clone())).await?;
+                    tracing::debug!(tx.txid = %txid, "Served transaction");
+                    if announcement.request.is_none() {
+                        announcement.request = Some(TxRequest {
+                            inventory: item,
+                            after: announced.elapsed(),
+                        });
+                    }
+                    if !collect_rejects {
+                        break;
+                    }
+                }
+                NetworkMessage::Reject(reject) if reject.hash == txid.to_raw_hash() => {
+                    announcement.rejects.push(TxReject {
+                        code: reject.ccode,
+                        reason: reject.reason.to_string(),
+                    });
+                }
+                _ => continue,
+            }
+        }
+        Ok(announcement)
+    }
+
+    /// Checks that the node advertised compact filters, as nodes disconnect
+    /// peers which request filters they do not serve
+    fn require_filters(&self) -> Result<(), FilterError> {
+        if !self.services.has(ServiceFlags::COMPACT_FILTERS) {
+            return Err(FilterError::NotSupported);
+        }
+        Ok(())
+    }
+
+    /// Returns the hash of the block at the height in the header chain
+    fn hash_at(chain: &HeaderChain, height: u32) -> anyhow::Result<BlockHash> {
+        chain
+            .hash(height)
+            .with_context(|| format!("Header chain has no block at height {}", height))
+    }
+
     /// Basic version message verification
     fn verify_version_message(
         &self,
         message: RawNetworkMessage,
         count: usize,
-    ) -> Result<(), BitcoinClientError> {
+    ) -> Result<VersionMessage, BitcoinClientError> {
         if count != 126 {
             return Err(BitcoinClientError::MessageError);
         };
         let version_message = match message.payload() {
-            NetworkMessage::Version(message) => message,
+            NetworkMessage::Version(message) => message.clone(),
             _ => return Err(BitcoinClientError::MessageError),
         };
         if version_message.version < 7000 {
-            return Err(BitcoinClientError::MessageError);
+            return Err(BitcoinClientError::VersionTooOld(version_message.version));
         }
-        Ok(())
+        Ok(version_message)
     }
 
     /// Basic verack message verification
@@ -1,16 +1,190 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use tokio::task::JoinHandle;
+use anyhow::Context;
+use bitcoin::{p2p::message_network::VersionMessage, Network};
+use tokio::{
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::{Id, JoinSet},
+};
+use tracing::Instrument;
 
-use crate::{bitcoin::client::BitcoinClient, bitcoin::stream::Stream};
+use crate::{
+    bitcoin::capture::{CaptureStream, PcapCapture},
+    bitcoin::client::BitcoinClient,
+    bitcoin::peer_address::{Host, PeerAddress},
+    bitcoin::retry::{ErrorKind, RetryPolicy},
+    bitcoin::stream::Stream,
+    bitcoin::timing::PhaseTimings,
+    bitcoin::transcript::SessionRecorder,
+    metrics::{ActiveConnection, Metrics},
+    peer_store::PeerStore,
+};
 
 /// Module to handle multiple bitcoin client handshakes
 pub struct BitcoinClientPool {
-    tasks: HashMap<String, JoinHandle<Result<(), anyhow::Error>>>,
+    config: Arc<PoolConfig>,
+    tasks: JoinSet<NodeOutcome>,
+    nodes: HashMap<Id, PeerAddress>,
+}
+
+/// Bitcoin client connected to a node over TCP, as used by the pool
+pub type TcpBitcoinClient = BitcoinClient<OwnedReadHalf, OwnedWriteHalf>;
+
+/// Future returned by a [PeerSession]
+pub type SessionFuture<'a> = Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send + 'a>>;
+
+/// Work performed with a node after the handshake with it has succeeded,
+/// such as asking it for addresses of other nodes. Results are expected
+/// to be passed on by the session itself, for example over a channel.
+pub trait PeerSession: Send + Sync {
+    /// Runs the session with the node over the connected client
+    fn run<'a>(
+        &'a self,
+        node: &'a PeerAddress,
+        client: &'a mut TcpBitcoinClient,
+    ) -> SessionFuture<'a>;
+}
+
+/// Creates the channel over which sessions pass their results on to the
+/// runner driving the pool
+pub(crate) fn session_channel<T>() -> (SessionSender<T>, SessionReceiver<T>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    (SessionSender(sender), SessionReceiver(receiver))
+}
+
+/// Sending half of a [session_channel], held by the session
+pub(crate) struct SessionSender<T>(UnboundedSender<T>);
+
+impl<T> SessionSender<T> {
+    /// Passes a result of the session on to the runner
+    pub(crate) fn send(&self, result: T) {
+        // Runner stops listening once it is done, so a closed channel is fine
+        let _ = self.0.send(result);
+    }
+}
+
+/// Receiving half of a [session_channel], held by the runner
+pub(crate) struct SessionReceiver<T>(UnboundedReceiver<T>);
+
+impl<T> SessionReceiver<T> {
+    /// Returns the results sent so far. Sessions send before their task
+    /// ends, so once [BitcoinClientPool::next_outcome] returned a node, the
+    /// results of its session are among them.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.0.try_recv().ok())
+    }
+}
+
+/// Configuration shared by all handshakes performed by the pool
+#[derive(Clone)]
+pub struct PoolConfig {
+    /// Timeout in miliseconds of connecting, and of the handshake after it
+    pub timeout: u64,
+    /// Policy deciding whether failed handshakes are repeated
+    pub retry_policy: RetryPolicy,
+    /// Network the nodes belong to
+    pub network: Network,
+    /// Session run with every node after a successful handshake
+    pub session: Option<Arc<dyn PeerSession>>,
+    /// Peer database every outcome is recorded to
+    pub peer_store: Option<Arc<Mutex<PeerStore>>>,
+    /// Metrics updated by every handshake
+    pub metrics: Option<Arc<Metrics>>,
+    /// Pcap capture that records the traffic with the nodes
+    pub capture: Option<Arc<PcapCapture>>,
+    /// Recorder of the transcripts of the sessions with the nodes
+    pub recorder: Option<Arc<SessionRecorder>>,
+    /// Whether the nodes are asked to announce their transactions
+    pub relay: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 500,
+            retry_policy: RetryPolicy::default(),
+            network: Network::Bitcoin,
+            session: None,
+            peer_store: None,
+            metrics: None,
+            capture: None,
+            recorder: None,
+            relay: false,
+        }
+    }
+}
+
+/// Error of a single failed handshake attempt
+#[derive(Debug, Clone)]
+pub struct AttemptError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+/// Record of a single handshake attempt with a node
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// Attempt number, counted from 1
+    pub number: u32,
+    /// Time spent on the attempt
+    pub elapsed: Duration,
+    /// Wall clock time at which the attempt ended
+    pub finished: SystemTime,
+    /// When the phases of the attempt ended
+    pub timings: PhaseTimings,
+    /// Error of the attempt, if it failed
+    pub error: Option<AttemptError>,
+}
+
+/// Outcome of all handshake attempts with a single node. On success it
+/// holds the version message received from the node.
+#[derive(Debug)]
+pub struct NodeOutcome {
+    pub node: PeerAddress,
+    pub attempts: Vec<Attempt>,
+    pub result: Result<VersionMessage, anyhow::Error>,
+    /// Error of the session run after the successful handshake
+    pub session_error: Option<anyhow::Error>,
+}
+
+impl NodeOutcome {
+    /// Returns the kind of error the handshake failed with
+    pub fn error_kind(&self) -> Option<ErrorKind> {
+        self.result.as_ref().err().map(ErrorKind::of)
+    }
+
+    /// Returns the duration of the successful handshake attempt
+    pub fn latency(&self) -> Option<Duration> {
+        match self.result {
+            Ok(_) => self.attempts.last().map(|attempt| attempt.elapsed),
+            Err(_) => None,
+        }
+    }
+
+    /// Returns when the phases of the last attempt ended
+    pub fn timings(&self) -> Option<&PhaseTimings> {
+        self.attempts.last().map(|attempt| &attempt.timings)
+    }
+
+    /// Returns by how many seconds the clock of the node is ahead of the
+    /// local one, based on the timestamp in its version message
+    pub fn timestamp_skew(&self) -> Option<i64> {
+        let version = self.result.as_ref().ok()?;
+        let finished = self.attempts.last()?.finished;
+        let local = finished.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        Some(version.timestamp - local)
+    }
 }
 
 impl BitcoinClientPool {
-    /// Creates mutltiple bitcoin clients from the vector of ip adddresses.
+    /// Creates mutltiple bitcoin clients from the vector of peer adddresses.
     /// Example shows localhost as ip address, instead use real bitcoin node ip.
     ///
     /// #Example
@@ -21,23 +195,158 @@ impl BitcoinClientPool {
     /// #[tokio::main]
     /// async fn main() {
     ///     let clients = vec![
-    ///         "127.0.0.1:1".to_string(), "127.0.0.1:2".to_string(), "127.0.0.1:3".to_string()
+    ///         "127.0.0.1:1".parse().unwrap(),
+    ///         "127.0.0.1:2".parse().unwrap(),
+    ///         "127.0.0.1:3".parse().unwrap(),
     ///     ];
     ///     let timeout = 500; // miliseconds
     ///     let client_pool = BitcoinClientPool::new(clients, timeout);
     /// }
     /// ```
-    pub fn new(nodes: Vec<String>, timeout: u64) -> BitcoinClientPool {
-        let mut tasks: HashMap<String, JoinHandle<Result<(), anyhow::Error>>> = HashMap::new();
+    pub fn new(nodes: Vec<PeerAddress>, timeout: u64) -> BitcoinClientPool {
+        let config = PoolConfig {
+            timeout,
+            ..PoolConfig::default()
+        };
+        BitcoinClientPool::with_config(nodes, config)
+    }
+
+    /// Creates mutltiple bitcoin clients from the vector of peer adddresses,
+    /// using the provided configuration.
+    ///
+    /// #Example
+    ///
+    /// ```
+    /// use p2p_handshake_bitcoin::bitcoin::client_pool::{BitcoinClientPool, PoolConfig};
+    /// use p2p_handshake_bitcoin::bitcoin::retry::RetryPolicy;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let clients = vec!["127.0.0.1:1".parse().unwrap()];
+    ///     let config = PoolConfig {
+    ///         retry_policy: RetryPolicy { max_attempts: 3, ..RetryPolicy::default() },
+    ///         ..PoolConfig::default()
+    ///     };
+    ///     let client_pool = BitcoinClientPool::with_config(clients, config);
+    /// }
+    /// ```
+    pub fn with_config(nodes: Vec<PeerAddress>, config: PoolConfig) -> BitcoinClientPool {
+        let mut pool = Self {
+            config: Arc::new(config),
+            tasks: JoinSet::new(),
+            nodes: HashMap::new(),
+        };
         for node in nodes {
-            let task =
-                tokio::task::spawn(BitcoinClientPool::perform_handshake(node.clone(), timeout));
-            tasks.insert(node, task);
+            pool.spawn(node);
         }
-        Self { tasks }
+        pool
+    }
+
+    /// Starts a handshake with another node, while the pool is running.
+    pub fn spawn(&mut self, node: PeerAddress) {
+        let task = self.tasks.spawn(BitcoinClientPool::perform_handshake(
+            node.clone(),
+            self.config.clone(),
+        ));
+        self.nodes.insert(task.id(), node);
     }
 
-    /// Runs mutltiple bitcoin clients from the BitcoinClientPool.
+    /// Returns the number of nodes whose handshake is not done yet
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns whether all handshakes are done
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Waits for the next node to finish its handshake, logs, records to the
+    /// peer database and returns its outcome. Outcomes are returned in the
+    /// order in which they finish, and `None` is returned once all of them
+    /// are done.
+    ///
+    /// #Example
+    ///
+    /// ```
+    /// use p2p_handshake_bitcoin::bitcoin::client_pool::BitcoinClientPool;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let clients = vec!["127.0.0.1:1".parse().unwrap()];
+    ///     let mut client_pool = BitcoinClientPool::new(clients, 500);
+    ///     while let Some(outcome) = client_pool.next_outcome().await {
+    ///         println!("{}: {}", outcome.node, outcome.result.is_ok());
+    ///     }
+    /// }
+    /// ```
+    pub async fn next_outcome(&mut self) -> Option<NodeOutcome> {
+        let outcome = match self.tasks.join_next_with_id().await? {
+            Ok((id, outcome)) => {
+                self.nodes.remove(&id);
+                outcome
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                );
+                let node = self
+                    .nodes
+                    .remove(&e.id())
+                    .expect("Every task belongs to a node");
+                NodeOutcome {
+                    node,
+                    attempts: Vec::new(),
+                    result: Err(anyhow::anyhow!(e)),
+                    session_error: None,
+                }
+            }
+        };
+        match &outcome.result {
+            Ok(version) => {
+                tracing::info!(
+                    attempts = outcome.attempts.len(),
+                    timings = %outcome.timings().map(ToString::to_string).unwrap_or_default(),
+                    peer.version = version.version,
+                    peer.user_agent = %version.user_agent,
+                    "Successfully performed handshake for Node {}",
+                    outcome.node
+                );
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    attempts = outcome.attempts.len(),
+                    timings = %outcome.timings().map(ToString::to_string).unwrap_or_default(),
+                    "Error with Node {}",
+                    outcome.node
+                );
+            }
+        }
+        if let Some(metrics) = &self.config.metrics {
+            metrics.record_outcome(&outcome);
+        }
+        if let Some(store) = &self.config.peer_store {
+            let recorded = match store.lock() {
+                Ok(mut store) => store.record(&outcome),
+                Err(_) => Err(anyhow::anyhow!("Peer database lock is poisoned")),
+            };
+            if let Err(e) = recorded {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to record Node {}",
+                    outcome.node
+                );
+            }
+        }
+        Some(outcome)
+    }
+
+    /// Runs mutltiple bitcoin clients from the BitcoinClientPool and returns
+    /// the outcome of each node.
     /// Example shows localhost as ip address, instead use real bitcoin node ip.
     ///
     /// #Example
@@ -48,56 +357,195 @@ impl BitcoinClientPool {
     /// #[tokio::main]
     /// async fn main() {
     ///     let clients = vec![
-    ///         "127.0.0.1:1".to_string(), "127.0.0.1:2".to_string(), "127.0.0.1:3".to_string()
+    ///         "127.0.0.1:1".parse().unwrap(),
+    ///         "127.0.0.1:2".parse().unwrap(),
+    ///         "127.0.0.1:3".parse().unwrap(),
     ///     ];
     ///     let timeout = 500; // miliseconds
     ///     let client_pool = BitcoinClientPool::new(clients, timeout);
-    ///     client_pool.run().await.unwrap();
+    ///     let outcomes = client_pool.run().await.unwrap();
+    ///     assert_eq!(outcomes.len(), 3);
     /// }
     /// ```
-    pub async fn run(self) -> Result<(), anyhow::Error> {
-        for (node, task) in self.tasks.into_iter() {
-            let result = match task.await {
-                Ok(result) => result,
-                Err(e) => {
-                    tracing::error!(
-                        error.cause_chain = ?e,
-                        error.message = %e,
-                    );
-                    Err(anyhow::anyhow!(e))
+    pub async fn run(mut self) -> Result<Vec<NodeOutcome>, anyhow::Error> {
+        let mut outcomes = Vec::with_capacity(self.tasks.len());
+        while let Some(outcome) = self.next_outcome().await {
+            outcomes.push(outcome);
+        }
+        Ok(outcomes)
+    }
+
+    /// Runs handshake with a single node, repeating it according to the
+    /// retry policy.
+    #[tracing::instrument("Performing handshake", skip(node, config), fields(node = %node))]
+    async fn perform_handshake(node: PeerAddress, config: Arc<PoolConfig>) -> NodeOutcome {
+        let policy = &config.retry_policy;
+        let mut attempts = Vec::new();
+        let mut number = 1;
+        loop {
+            let start = Instant::now();
+            let mut timings = PhaseTimings::start_at(start);
+            let result = BitcoinClientPool::attempt_handshake(&node, &config, &mut timings).await;
+            let elapsed = start.elapsed();
+            let finished = SystemTime::now();
+            let e = match result {
+                // Connection stays active for the session
+                Ok((mut client, version, _active)) => {
+                    attempts.push(Attempt {
+                        number,
+                        elapsed,
+                        finished,
+                        timings,
+                        error: None,
+                    });
+                    let session_error = match &config.session {
+                        Some(session) => session.run(&node, &mut client).await.err(),
+                        None => None,
+                    };
+                    if let Some(e) = &session_error {
+                        tracing::warn!(error.cause_chain = ?e, error.message = %e, "Session failed");
+                    }
+                    BitcoinClientPool::record_bytes(&config, &client);
+                    return NodeOutcome {
+                        node,
+                        attempts,
+                        result: Ok(version),
+                        session_error,
+                    };
                 }
+                Err(e) => e,
             };
-            match result {
-                Ok(()) => {
-                    tracing::info!("Successfully performed handshake for Node {}", node);
-                }
-                Err(e) => {
-                    tracing::error!(error.cause_chain = ?e, error.message = %e,"Error with Node {}", node);
-                }
+            let kind = ErrorKind::of(&e);
+            attempts.push(Attempt {
+                number,
+                elapsed,
+                finished,
+                timings,
+                error: Some(AttemptError {
+                    kind,
+                    message: format!("{:#}", e),
+                }),
+            });
+            if !policy.should_retry(number, kind) {
+                return NodeOutcome {
+                    node,
+                    attempts,
+                    result: Err(e),
+                    session_error: None,
+                };
             }
+            let delay = policy.delay(number);
+            tracing::warn!(
+                error.message = %e,
+                "Handshake attempt {} failed with {:?}, retrying in {:?}",
+                number,
+                kind,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            number += 1;
         }
-        Ok(())
     }
 
-    /// Runst handshake on all provided bitcoin clients.
-    #[tracing::instrument("Performing handshake", skip(timeout))]
-    async fn perform_handshake(uri: String, timeout: u64) -> Result<(), anyhow::Error> {
-        let stream = match Stream::new(&uri, timeout).await {
-            Ok(stream) => stream,
-            Err(e) => {
-                return {
-                    tracing::error!("Failed to initialize TCP stream");
-                    Err(anyhow::anyhow!("Failed to initialize TCP stream, {}", e))
+    /// Makes a single handshake attempt with the node, recording when its
+    /// phases ended. The connection counts as active until the returned
+    /// guard is dropped.
+    async fn attempt_handshake(
+        node: &PeerAddress,
+        config: &PoolConfig,
+        timings: &mut PhaseTimings,
+    ) -> Result<(TcpBitcoinClient, VersionMessage, Option<ActiveConnection>), anyhow::Error> {
+        if let Host::Onion(_) = node.host {
+            return Err(anyhow::anyhow!(
+                "Onion addresses can not be reached without a Tor proxy"
+            ));
+        }
+        let active = config.metrics.as_ref().map(|m| m.active_connection());
+        let start = Instant::now();
+        let stream = match Stream::connect(&node.to_string(), config.timeout, timings)
+            .instrument(tracing::info_span!("Connecting"))
+            .await
+        {
+            Ok(stream) => {
+                if let Some(metrics) = &config.metrics {
+                    metrics
+                        .connect_duration
+                        .observe(start.elapsed().as_secs_f64());
                 }
+                stream
             }
-        };
-        let mut bitcoin_client = BitcoinClient::new(stream.rx, stream.tx);
-        match bitcoin_client.handshake().await {
-            Ok(()) => Ok(()),
             Err(e) => {
+                tracing::error!("Failed to initialize TCP stream");
+                return Err(e).context("Failed to initialize TCP stream");
+            }
+        };
+        let capture = match &config.capture {
+            Some(capture) => BitcoinClientPool::open_capture(capture, node, &stream),
+            None => None,
+        };
+        let mut bitcoin_client = BitcoinClient::with_network(stream.rx, stream.tx, config.network)
+            .with_timings(*timings)
+            .with_relay(config.relay);
+        if let Some(capture) = capture {
+            bitcoin_client = bitcoin_client.with_tap(capture);
+        }
+        if let Some(recorder) = &config.recorder {
+            match recorder.open(node) {
+                Ok(transcript) => bitcoin_client = bitcoin_client.with_tap(transcript),
+                Err(e) => {
+                    tracing::warn!(error.message = %e, "Failed to record session with Node {}", node)
+                }
+            }
+        }
+        // Nodes may accept the connection and then never answer
+        let result = tokio::time::timeout(
+            Duration::from_millis(config.timeout),
+            bitcoin_client.handshake(),
+        )
+        .await;
+        *timings = bitcoin_client.timings();
+        match result {
+            Ok(Ok(version)) => Ok((bitcoin_client, version, active)),
+            Ok(Err(e)) => {
                 tracing::error!("Failed to perform handshake: {}", e);
+                BitcoinClientPool::record_bytes(config, &bitcoin_client);
                 Err(anyhow::anyhow!(e))
             }
+            Err(elapsed) => {
+                tracing::error!("Timed out performing handshake");
+                BitcoinClientPool::record_bytes(config, &bitcoin_client);
+                Err(elapsed).context("Timed out performing handshake")
+            }
+        }
+    }
+
+    /// Starts capturing the connection to the node. Failing to do so does
+    /// not fail the handshake, the connection is just not captured.
+    fn open_capture(
+        capture: &PcapCapture,
+        node: &PeerAddress,
+        stream: &Stream,
+    ) -> Option<CaptureStream> {
+        let opened = stream
+            .tx
+            .local_addr()
+            .and_then(|local| Ok((local, stream.tx.peer_addr()?)))
+            .map_err(anyhow::Error::from)
+            .and_then(|(local, remote)| capture.open(node, local, remote));
+        match opened {
+            Ok(capture) => Some(capture),
+            Err(e) => {
+                tracing::warn!(error.message = %e, "Failed to capture traffic with Node {}", node);
+                None
+            }
+        }
+    }
+
+    /// Adds the bytes exchanged by the client to the metrics
+    fn record_bytes(config: &PoolConfig, client: &TcpBitcoinClient) {
+        if let Some(metrics) = &config.metrics {
+            metrics.bytes_sent.inc_by(client.bytes_sent());
+            metrics.bytes_received.inc_by(client.bytes_received());
         }
     }
 }
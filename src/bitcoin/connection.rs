@@ -1,7 +1,22 @@
+use std::time::Instant;
+
 use bitcoin::consensus::{deserialize_partial, Decodable};
 use bytes::{Buf, BytesMut};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::bitcoin::wire::{trace_frames, FrameDirection, FrameHeader, HEADER_LEN};
+
+/// Observer of the bytes exchanged over a connection, such as a capture
+/// of the traffic
+pub trait Tap: Send {
+    /// Called with bytes written to the writing stream
+    fn sent(&mut self, data: &[u8]);
+    /// Called with bytes read from the reading stream
+    fn received(&mut self, data: &[u8]);
+    /// Called once the reading stream was closed by the other side
+    fn closed(&mut self) {}
+}
+
 /// Module that handles connection and message exchange with Bitcoin node
 pub struct Connection<Reader, Writer>
 where
@@ -11,6 +26,12 @@ where
     rx_stream: Reader,
     tx_stream: Writer,
     buffer: BytesMut,
+    bytes_sent: u64,
+    bytes_received: u64,
+    first_byte: Option<Instant>,
+    taps: Vec<Box<dyn Tap>>,
+    /// Number of bytes at the start of the buffer which were already traced
+    traced: usize,
 }
 
 impl<Reader, Writer> Connection<Reader, Writer>
@@ -29,6 +50,11 @@ where
             rx_stream,
             tx_stream,
             buffer: BytesMut::with_capacity(2048),
+            bytes_sent: 0,
+            bytes_received: 0,
+            first_byte: None,
+            taps: Vec::new(),
+            traced: 0,
         }
     }
 
@@ -36,24 +62,79 @@ where
     /// trait Decodable.
     pub async fn read<T: Decodable>(&mut self) -> Result<Option<(T, usize)>, anyhow::Error> {
         loop {
-            if let Ok((message, count)) = deserialize_partial::<T>(&self.buffer) {
-                self.buffer.advance(count);
-                return Ok(Some((message, count)));
+            match deserialize_partial::<T>(&self.buffer) {
+                Ok((message, count)) => {
+                    trace_frames(FrameDirection::Received, &self.buffer[..count]);
+                    self.buffer.advance(count);
+                    self.traced = 0;
+                    return Ok(Some((message, count)));
+                }
+                Err(_) => self.trace_undecodable_frame(),
             }
 
-            if self.rx_stream.read_buf(&mut self.buffer).await? == 0 {
+            let start = self.buffer.len();
+            let count = self.rx_stream.read_buf(&mut self.buffer).await?;
+            if count == 0 {
+                for tap in &mut self.taps {
+                    tap.closed();
+                }
                 if self.buffer.is_empty() {
                     return Ok(None);
                 } else {
+                    trace_frames(FrameDirection::Received, &self.buffer[self.traced..]);
                     return Err(anyhow::anyhow!("connection reset by peer"));
                 }
             }
+            self.bytes_received += count as u64;
+            self.first_byte.get_or_insert_with(Instant::now);
+            for tap in &mut self.taps {
+                tap.received(&self.buffer[start..]);
+            }
+        }
+    }
+
+    /// Traces the frame at the start of the buffer once all of its bytes
+    /// arrived, if it can not be decoded, so it shows up next to the frames
+    /// around it rather than when the connection closes
+    fn trace_undecodable_frame(&mut self) {
+        let Some(header) = FrameHeader::parse(&self.buffer) else {
+            return;
+        };
+        let end = HEADER_LEN + header.length as usize;
+        if self.traced < end && end <= self.buffer.len() {
+            trace_frames(FrameDirection::Received, &self.buffer[..end]);
+            self.traced = end;
         }
     }
 
     /// Writes a chunk of u8's to a writing stream.
     pub async fn write(&mut self, message: &[u8]) -> Result<(), anyhow::Error> {
         self.tx_stream.write_all(message).await?;
+        trace_frames(FrameDirection::Sent, message);
+        self.bytes_sent += message.len() as u64;
+        for tap in &mut self.taps {
+            tap.sent(message);
+        }
         Ok(())
     }
+
+    /// Passes all bytes written and read from now on to the tap
+    pub fn add_tap(&mut self, tap: Box<dyn Tap>) {
+        self.taps.push(tap);
+    }
+
+    /// Returns the number of bytes written to the writing stream
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Returns the number of bytes read from the reading stream
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Returns when the first byte was read from the reading stream
+    pub fn first_byte_at(&self) -> Option<Instant> {
+        self.first_byte
+    }
 }
@@ -0,0 +1,242 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use bitcoin::p2p::message_network::VersionMessage;
+use tokio::time::MissedTickBehavior;
+
+use crate::bitcoin::{
+    addrman::AddrMan,
+    client_pool::{
+        session_channel, BitcoinClientPool, NodeOutcome, PeerSession, PoolConfig, SessionFuture,
+        SessionSender, TcpBitcoinClient,
+    },
+    peer_address::PeerAddress,
+};
+
+/// Limits of a network crawl
+#[derive(Debug, Clone)]
+pub struct CrawlConfig {
+    /// Maximum number of getaddr hops away from the starting nodes
+    pub max_depth: u32,
+    /// Maximum number of nodes handshakes are attempted with
+    pub max_nodes: usize,
+    /// Maximum number of handshakes running at the same time
+    pub max_concurrent: usize,
+    /// Maximum number of new connections per second, 0 for no limit
+    pub connections_per_second: u32,
+    /// How long to wait for addresses after sending getaddr
+    pub addr_timeout: Duration,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 2,
+            max_nodes: 1000,
+            max_concurrent: 100,
+            connections_per_second: 10,
+            addr_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Node which completed the handshake during the crawl
+#[derive(Debug, Clone)]
+pub struct ReachableNode {
+    pub node: PeerAddress,
+    /// Number of getaddr hops from the starting nodes
+    pub depth: u32,
+    pub version: VersionMessage,
+}
+
+/// Result of a network crawl
+#[derive(Debug, Default)]
+pub struct CrawlReport {
+    /// Nodes which completed the handshake, in order of completion
+    pub reachable: Vec<ReachableNode>,
+    /// Number of nodes handshakes were attempted with
+    pub attempted: usize,
+    /// Number of distinct nodes known within the depth limit, including
+    /// the starting ones
+    pub discovered: usize,
+    /// Address manager with everything learned during the crawl, which can
+    /// be passed on to the next one
+    pub addrman: AddrMan,
+}
+
+/// Session which asks the node for addresses of other nodes and passes
+/// them on together with the address of the node.
+struct GetAddrSession {
+    timeout: Duration,
+    /// Time limit of the whole session, so a node which does not read
+    /// getaddr can not hold a slot of the crawl
+    limit: Duration,
+    sender: SessionSender<(PeerAddress, Vec<PeerAddress>)>,
+}
+
+impl PeerSession for GetAddrSession {
+    fn run<'a>(
+        &'a self,
+        node: &'a PeerAddress,
+        client: &'a mut TcpBitcoinClient,
+    ) -> SessionFuture<'a> {
+        Box::pin(async move {
+            let addresses = tokio::time::timeout(self.limit, client.get_addresses(self.timeout))
+                .await
+                .context("Timed out asking for addresses")??;
+            tracing::info!("Node {} sent {} addresses", node, addresses.len());
+            self.sender.send((node.clone(), addresses));
+            Ok(())
+        })
+    }
+}
+
+/// Crawler that discovers the network starting from known nodes. After a
+/// successful handshake it asks the node for addresses with getaddr and
+/// adds the newly discovered ones to an [AddrMan], which selects the nodes
+/// the [BitcoinClientPool] connects to next once the starting nodes are done.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::client_pool::PoolConfig;
+/// use p2p_handshake_bitcoin::bitcoin::crawler::{CrawlConfig, Crawler};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let crawler = Crawler::new(CrawlConfig::default(), PoolConfig::default());
+///     let seeds = vec!["127.0.0.1:1".parse().unwrap()];
+///     let report = crawler.run(seeds, |_| Ok(())).await.unwrap();
+///     assert!(report.reachable.is_empty());
+/// }
+/// ```
+pub struct Crawler {
+    config: CrawlConfig,
+    pool_config: PoolConfig,
+    addrman: AddrMan,
+}
+
+impl Crawler {
+    /// Creates a crawler with the crawl limits and the configuration of
+    /// the underlying pool. Any session in the pool configuration is
+    /// replaced by the getaddr exchange.
+    pub fn new(config: CrawlConfig, pool_config: PoolConfig) -> Crawler {
+        Crawler::with_addrman(config, pool_config, AddrMan::new())
+    }
+
+    /// Creates a crawler which starts with the addresses known to the
+    /// address manager, e.g. the one from a previous crawl. Only addresses
+    /// discovered within the depth limit of this crawl are connected to.
+    pub fn with_addrman(config: CrawlConfig, pool_config: PoolConfig, addrman: AddrMan) -> Crawler {
+        Crawler {
+            config,
+            pool_config,
+            addrman,
+        }
+    }
+
+    /// Crawls the network starting from the seeds. Every node outcome is
+    /// passed to `on_outcome` as soon as it is known.
+    pub async fn run<F>(
+        self,
+        seeds: Vec<PeerAddress>,
+        mut on_outcome: F,
+    ) -> Result<CrawlReport, anyhow::Error>
+    where
+        F: FnMut(&NodeOutcome) -> Result<(), anyhow::Error>,
+    {
+        let (sender, mut receiver) = session_channel();
+        let pool_config = PoolConfig {
+            session: Some(Arc::new(GetAddrSession {
+                timeout: self.config.addr_timeout,
+                // Sending getaddr gets as long as connecting, and then
+                // addresses are collected for the address timeout
+                limit: self.config.addr_timeout + Duration::from_millis(self.pool_config.timeout),
+                sender,
+            })),
+            ..self.pool_config
+        };
+        let mut pool = BitcoinClientPool::with_config(Vec::new(), pool_config);
+
+        let mut addrman = self.addrman;
+        let mut depths: HashMap<PeerAddress, u32> = HashMap::new();
+        let mut attempted = HashSet::new();
+        // Starting nodes are always connected to, even if the address
+        // manager has no room for them
+        let mut queue = VecDeque::new();
+        for seed in seeds {
+            if depths.insert(seed.clone(), 0).is_none() {
+                addrman.add(seed.clone(), &seed);
+                queue.push_back(seed);
+            }
+        }
+        let mut rate_limit = match self.config.connections_per_second {
+            0 => None,
+            rate => {
+                let mut interval =
+                    tokio::time::interval(Duration::from_secs_f64(1.0 / rate as f64));
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                Some(interval)
+            }
+        };
+
+        let mut report = CrawlReport::default();
+        loop {
+            while pool.len() < self.config.max_concurrent
+                && report.attempted < self.config.max_nodes
+            {
+                let node = queue.pop_front().or_else(|| {
+                    addrman.select_where(|a| depths.contains_key(a) && !attempted.contains(a))
+                });
+                let Some(node) = node else {
+                    break;
+                };
+                if let Some(interval) = rate_limit.as_mut() {
+                    interval.tick().await;
+                }
+                attempted.insert(node.clone());
+                addrman.attempt(&node);
+                pool.spawn(node);
+                report.attempted += 1;
+            }
+
+            let Some(outcome) = pool.next_outcome().await else {
+                break;
+            };
+            on_outcome(&outcome)?;
+            if let Ok(version) = &outcome.result {
+                addrman.good(&outcome.node);
+                report.reachable.push(ReachableNode {
+                    depth: depths[&outcome.node],
+                    node: outcome.node,
+                    version: version.clone(),
+                });
+            }
+
+            for (source, addresses) in receiver.drain() {
+                let depth = depths[&source] + 1;
+                if depth > self.config.max_depth {
+                    continue;
+                }
+                for address in addresses {
+                    if depths.contains_key(&address) {
+                        continue;
+                    }
+                    // Address manager decides whether there is room for
+                    // the address, so no single node can flood the crawl
+                    addrman.add(address.clone(), &source);
+                    if addrman.contains(&address) {
+                        depths.insert(address, depth);
+                    }
+                }
+            }
+        }
+        report.discovered = depths.len();
+        report.addrman = addrman;
+        Ok(report)
+    }
+}
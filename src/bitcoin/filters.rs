@@ -0,0 +1,220 @@
+use std::ops::RangeInclusive;
+
+use bitcoin::{bip158::BlockFilter, hashes::Hash, BlockHash, FilterHash, FilterHeader, ScriptBuf};
+
+/// Type of the basic filter, the only one defined by BIP 158
+pub const BASIC_FILTER: u8 = 0;
+
+/// Number of blocks between two filter headers in a cfcheckpt message
+pub const CHECKPOINT_INTERVAL: u32 = 1000;
+
+/// Maximum number of filter headers a node sends in a single cfheaders
+/// message
+pub const MAX_FILTER_HEADERS_PER_MESSAGE: u32 = 2000;
+
+/// Maximum number of filters a node sends for a single getcfilters message
+pub const MAX_FILTERS_PER_MESSAGE: u32 = 1000;
+
+/// Error enumeration of the reasons filters or filter headers sent by a
+/// node are not accepted
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    #[error("Node does not serve compact block filters")]
+    NotSupported,
+    #[error("Filter headers starting at height {height} do not connect to the chain")]
+    Unconnected { height: u32 },
+    #[error("Node sent {actual} filter headers or checkpoints, expected {expected}")]
+    WrongCount { expected: usize, actual: usize },
+    #[error("Filter header at height {height} does not match the checkpoint")]
+    CheckpointMismatch { height: u32 },
+    #[error("Filter of block {hash} at height {height} does not match its filter header")]
+    BadFilter { hash: BlockHash, height: u32 },
+    #[error("Heights {start} to {stop} are not all in the filter header chain")]
+    OutsideChain { start: u32, stop: u32 },
+    #[error("Filter of block {hash} could not be decoded: {message}")]
+    Undecodable { hash: BlockHash, message: String },
+}
+
+/// Chain of filter headers, each of which commits to the filter of the
+/// block at its height and to the filter header before it (BIP 157).
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::{bip158::BlockFilter, hashes::Hash, FilterHash, FilterHeader};
+/// use p2p_handshake_bitcoin::bitcoin::filters::FilterHeaderChain;
+///
+/// let filter = BlockFilter::new(&[0]);
+/// let mut chain = FilterHeaderChain::new();
+/// let filter_hash = FilterHash::hash(&filter.content);
+/// chain.extend(0, FilterHeader::all_zeros(), &[filter_hash]).unwrap();
+/// assert_eq!(chain.height(), Some(0));
+/// assert_eq!(chain.header(0), Some(filter.filter_header(&FilterHeader::all_zeros())));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterHeaderChain {
+    /// Height of the first header
+    base: u32,
+    /// Filter header before the first header
+    previous: FilterHeader,
+    headers: Vec<FilterHeader>,
+}
+
+impl Default for FilterHeaderChain {
+    fn default() -> Self {
+        FilterHeaderChain::new()
+    }
+}
+
+impl FilterHeaderChain {
+    /// Creates an empty chain starting at the genesis block, whose previous
+    /// filter header is all zeros
+    pub fn new() -> FilterHeaderChain {
+        FilterHeaderChain::starting_at(0, FilterHeader::all_zeros())
+    }
+
+    /// Creates an empty chain starting at the height, after the trusted
+    /// filter header of the block before it
+    pub fn starting_at(height: u32, previous: FilterHeader) -> FilterHeaderChain {
+        FilterHeaderChain {
+            base: height,
+            previous,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Returns the height of the last filter header, `None` if there is none
+    pub fn height(&self) -> Option<u32> {
+        (self.base + self.headers.len() as u32).checked_sub(1)
+    }
+
+    /// Returns the height the next filter header is at
+    pub fn next_height(&self) -> u32 {
+        self.base + self.headers.len() as u32
+    }
+
+    /// Returns whether the chain has the filter headers of all the heights
+    pub fn contains(&self, heights: &RangeInclusive<u32>) -> bool {
+        self.base <= *heights.start() && *heights.end() < self.next_height()
+    }
+
+    /// Returns the filter header at the height
+    pub fn header(&self, height: u32) -> Option<FilterHeader> {
+        self.headers
+            .get(height.checked_sub(self.base)? as usize)
+            .copied()
+    }
+
+    /// Returns the last filter header, or the one before the first header
+    /// if the chain is empty
+    pub fn tip(&self) -> FilterHeader {
+        self.headers.last().copied().unwrap_or(self.previous)
+    }
+
+    /// Returns the filter header before the one at the height
+    fn previous_of(&self, height: u32) -> Option<FilterHeader> {
+        match height.checked_sub(self.base)? {
+            0 => Some(self.previous),
+            index => self.headers.get(index as usize - 1).copied(),
+        }
+    }
+
+    /// Appends the filter headers committing to the filter hashes of the
+    /// blocks from the start height on, as sent in cfheaders. The previous
+    /// filter header of the message must be the tip of the chain. Returns
+    /// the number of headers appended.
+    pub fn extend(
+        &mut self,
+        start_height: u32,
+        previous: FilterHeader,
+        filter_hashes: &[FilterHash],
+    ) -> Result<usize, FilterError> {
+        if start_height != self.next_height() || previous != self.tip() {
+            return Err(FilterError::Unconnected {
+                height: start_height,
+            });
+        }
+        for filter_hash in filter_hashes {
+            let header = filter_hash.filter_header(&self.tip());
+            self.headers.push(header);
+        }
+        Ok(filter_hashes.len())
+    }
+
+    /// Checks the filter headers at the heights against the checkpoints
+    /// from cfcheckpt, which are the filter headers at every multiple of
+    /// the checkpoint interval. Only the checkpoints within the heights
+    /// are checked, so each batch of headers can be checked as it is
+    /// appended. Checkpoints beyond the chain are skipped.
+    pub fn check_checkpoints(
+        &self,
+        checkpoints: &[FilterHeader],
+        heights: RangeInclusive<u32>,
+    ) -> Result<(), FilterError> {
+        let first = heights.start().div_ceil(CHECKPOINT_INTERVAL).max(1);
+        let last = heights.end() / CHECKPOINT_INTERVAL;
+        for multiple in first..=last {
+            let height = multiple * CHECKPOINT_INTERVAL;
+            match (checkpoints.get(multiple as usize - 1), self.header(height)) {
+                (Some(checkpoint), Some(header)) if header != *checkpoint => {
+                    return Err(FilterError::CheckpointMismatch { height })
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the filter of the block at the height is the one its
+    /// filter header commits to
+    pub fn verify(
+        &self,
+        height: u32,
+        block_hash: BlockHash,
+        filter: BlockFilter,
+    ) -> Result<VerifiedFilter, FilterError> {
+        let bad = FilterError::BadFilter {
+            hash: block_hash,
+            height,
+        };
+        let (Some(previous), Some(header)) = (self.previous_of(height), self.header(height)) else {
+            return Err(bad);
+        };
+        if filter.filter_header(&previous) != header {
+            return Err(bad);
+        }
+        Ok(VerifiedFilter {
+            height,
+            block_hash,
+            filter,
+        })
+    }
+}
+
+/// Basic filter of a block which matches its filter header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedFilter {
+    pub height: u32,
+    pub block_hash: BlockHash,
+    pub filter: BlockFilter,
+}
+
+impl VerifiedFilter {
+    /// Returns whether the block may spend from or pay to any of the
+    /// scripts. Filters have false positives at a rate of about 1 in
+    /// 784931, but no false negatives.
+    pub fn matches_any(&self, scripts: &[ScriptBuf]) -> Result<bool, FilterError> {
+        if scripts.is_empty() {
+            return Ok(false);
+        }
+        self.filter
+            .match_any(
+                &self.block_hash,
+                scripts.iter().map(|script| script.as_bytes()),
+            )
+            .map_err(|e| FilterError::Undecodable {
+                hash: self.block_hash,
+                message: e.to_string(),
+            })
+    }
+}
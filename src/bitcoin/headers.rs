@@ -0,0 +1,382 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use anyhow::Context;
+
+use bitcoin::{
+    block::Header,
+    blockdata::constants::genesis_block,
+    consensus::{deserialize, serialize, Params},
+    hex::{DisplayHex, FromHex},
+    pow::Work,
+    BlockHash, CompactTarget, Network, Target,
+};
+
+/// Maximum number of headers a node sends in a single headers message
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+
+/// Error enumeration of the reasons a header is not accepted
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    #[error("Header {hash} does not connect to {tip}, its parent is {parent}")]
+    Unconnected {
+        hash: BlockHash,
+        parent: BlockHash,
+        tip: BlockHash,
+    },
+    #[error("Header {hash} at height {height} has bits {actual:#010x}, expected {expected:#010x}")]
+    BadDifficulty {
+        hash: BlockHash,
+        height: u32,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("Header {hash} at height {height} does not meet its target")]
+    BadProofOfWork { hash: BlockHash, height: u32 },
+}
+
+/// Best header of a chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainTip {
+    /// Height of the header, the genesis block being at height 0
+    pub height: u32,
+    /// Hash of the header
+    pub hash: BlockHash,
+    /// Total work of the chain from its first header up to and including
+    /// the header
+    pub work: Work,
+}
+
+/// Header at a known height a chain can start from instead of the genesis
+/// block, written as `<height>:<header in hex>`. The height must be a
+/// multiple of the difficulty adjustment interval, so the headers after
+/// it can be validated without the ones before it.
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::{blockdata::constants::genesis_block, consensus::serialize, hex::DisplayHex, Network};
+/// use p2p_handshake_bitcoin::bitcoin::headers::Checkpoint;
+///
+/// let genesis = genesis_block(Network::Bitcoin).header;
+/// let text = format!("0:{}", serialize(&genesis).to_lower_hex_string());
+/// let checkpoint: Checkpoint = text.parse().unwrap();
+/// assert_eq!(checkpoint.height, 0);
+/// assert_eq!(checkpoint.header, genesis);
+/// assert_eq!(checkpoint.to_string(), text);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Height of the header
+    pub height: u32,
+    /// Header the chain starts with
+    pub header: Header,
+}
+
+impl FromStr for Checkpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (height, header) = s
+            .split_once(':')
+            .context("Checkpoint must be written as <height>:<header in hex>")?;
+        let height = height
+            .parse()
+            .with_context(|| format!("Invalid checkpoint height {}", height))?;
+        let bytes = Vec::<u8>::from_hex(header).context("Checkpoint header is not valid hex")?;
+        let header = deserialize(&bytes).context("Checkpoint header is not a block header")?;
+        Ok(Checkpoint { height, header })
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            self.height,
+            serialize(&self.header).to_lower_hex_string()
+        )
+    }
+}
+
+/// Chain of block headers from the genesis block, each of which is linked
+/// to the one before it, meets its target and has the difficulty required
+/// by the consensus rules of the network.
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::{blockdata::constants::genesis_block, Network};
+/// use p2p_handshake_bitcoin::bitcoin::headers::HeaderChain;
+///
+/// let chain = HeaderChain::new(Network::Bitcoin);
+/// assert_eq!(chain.tip().height, 0);
+/// assert_eq!(chain.tip().hash, genesis_block(Network::Bitcoin).block_hash());
+/// assert_eq!(chain.locator(), vec![chain.tip().hash]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    params: Params,
+    /// Height of the first header
+    base: u32,
+    headers: Vec<Header>,
+    hashes: Vec<BlockHash>,
+    heights: HashMap<BlockHash, u32>,
+    work: Work,
+}
+
+impl HeaderChain {
+    /// Creates a chain with only the genesis block of the network
+    pub fn new(network: Network) -> HeaderChain {
+        HeaderChain::with_params(Params::new(network))
+    }
+
+    /// Creates a chain with only the genesis block of the network of the
+    /// parameters, which are used to validate the headers
+    pub fn with_params(params: Params) -> HeaderChain {
+        let genesis = genesis_block(params.network).header;
+        let hash = genesis.block_hash();
+        HeaderChain {
+            work: genesis.work(),
+            base: 0,
+            headers: vec![genesis],
+            hashes: vec![hash],
+            heights: HashMap::from([(hash, 0)]),
+            params,
+        }
+    }
+
+    /// Creates a chain which starts at the checkpoint instead of the
+    /// genesis block. Nodes which do not have the checkpoint in their
+    /// chain can not be synced with it.
+    pub fn from_checkpoint(params: Params, checkpoint: Checkpoint) -> anyhow::Result<HeaderChain> {
+        let interval = params.difficulty_adjustment_interval() as u32;
+        if !checkpoint.height.is_multiple_of(interval) {
+            anyhow::bail!(
+                "Checkpoint height {} is not a multiple of the difficulty adjustment interval {}",
+                checkpoint.height,
+                interval
+            );
+        }
+        let header = checkpoint.header;
+        let hash = header
+            .validate_pow(header.target())
+            .context("Checkpoint header does not meet its target")?;
+        Ok(HeaderChain {
+            work: header.work(),
+            base: checkpoint.height,
+            headers: vec![header],
+            hashes: vec![hash],
+            heights: HashMap::from([(hash, checkpoint.height)]),
+            params,
+        })
+    }
+
+    /// Returns the best header of the chain
+    pub fn tip(&self) -> ChainTip {
+        ChainTip {
+            height: self.height(),
+            hash: self.hashes[self.hashes.len() - 1],
+            work: self.work,
+        }
+    }
+
+    /// Returns the header at the height as the tip of the chain up to it
+    pub fn tip_at(&self, height: u32) -> Option<ChainTip> {
+        let hash = self.hash(height)?;
+        let work = self.headers[(height + 1 - self.base) as usize..]
+            .iter()
+            .fold(self.work, |work, header| work - header.work());
+        Some(ChainTip { height, hash, work })
+    }
+
+    /// Returns a copy of the chain up to the height, holding only the
+    /// headers since the last difficulty adjustment, which are all the
+    /// headers after it are validated against
+    pub fn branch(&self, height: u32) -> Option<HeaderChain> {
+        let tip = self.tip_at(height)?;
+        let interval = self.params.difficulty_adjustment_interval() as u32;
+        let base = (height - height % interval).max(self.base);
+        let start = (base - self.base) as usize;
+        let end = (height + 1 - self.base) as usize;
+        Some(HeaderChain {
+            params: self.params.clone(),
+            base,
+            headers: self.headers[start..end].to_vec(),
+            hashes: self.hashes[start..end].to_vec(),
+            heights: (base..)
+                .zip(&self.hashes[start..end])
+                .map(|(height, hash)| (*hash, height))
+                .collect(),
+            work: tip.work,
+        })
+    }
+
+    /// Returns the height of the best header
+    pub fn height(&self) -> u32 {
+        self.base + (self.headers.len() - 1) as u32
+    }
+
+    /// Returns the height of the first header, 0 unless the chain starts
+    /// at a checkpoint
+    pub fn base_height(&self) -> u32 {
+        self.base
+    }
+
+    /// Returns the header at the height
+    pub fn header(&self, height: u32) -> Option<&Header> {
+        self.headers.get(height.checked_sub(self.base)? as usize)
+    }
+
+    /// Returns the hash of the header at the height
+    pub fn hash(&self, height: u32) -> Option<BlockHash> {
+        self.hashes
+            .get(height.checked_sub(self.base)? as usize)
+            .copied()
+    }
+
+    /// Returns the hashes of all headers, starting with the first one
+    pub fn hashes(&self) -> &[BlockHash] {
+        &self.hashes
+    }
+
+    /// Returns the height of the header with the hash, if it is in the chain
+    pub fn height_of(&self, hash: &BlockHash) -> Option<u32> {
+        self.heights.get(hash).copied()
+    }
+
+    /// Returns hashes of the chain to send in getheaders: the last ten
+    /// headers, then exponentially further apart down to the first header
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut index = (self.headers.len() - 1) as i64;
+        let mut step = 1;
+        while index > 0 {
+            locator.push(self.hashes[index as usize]);
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            index -= step;
+        }
+        locator.push(self.hashes[0]);
+        locator
+    }
+
+    /// Validates the headers and appends them to the chain. Headers which
+    /// are already in the chain are skipped. Returns the number of headers
+    /// appended; on error, the headers before the invalid one are kept.
+    pub fn extend(&mut self, headers: &[Header]) -> Result<usize, HeaderError> {
+        let mut appended = 0;
+        for header in headers {
+            let hash = header.block_hash();
+            if self.heights.contains_key(&hash) {
+                continue;
+            }
+            self.validate(header, hash)?;
+            self.work = self.work + header.work();
+            self.heights.insert(hash, self.height() + 1);
+            self.headers.push(*header);
+            self.hashes.push(hash);
+            appended += 1;
+        }
+        Ok(appended)
+    }
+
+    /// Checks that the header can follow the tip of the chain
+    fn validate(&self, header: &Header, hash: BlockHash) -> Result<(), HeaderError> {
+        let tip = self.tip();
+        if header.prev_blockhash != tip.hash {
+            return Err(HeaderError::Unconnected {
+                hash,
+                parent: header.prev_blockhash,
+                tip: tip.hash,
+            });
+        }
+        let height = tip.height + 1;
+        let expected = self.next_work_required(header);
+        if header.bits != expected {
+            return Err(HeaderError::BadDifficulty {
+                hash,
+                height,
+                expected: expected.to_consensus(),
+                actual: header.bits.to_consensus(),
+            });
+        }
+        header
+            .validate_pow(header.target())
+            .map_err(|_| HeaderError::BadProofOfWork { hash, height })?;
+        Ok(())
+    }
+
+    /// Returns the bits the header following the tip must have
+    fn next_work_required(&self, header: &Header) -> CompactTarget {
+        let last = &self.headers[self.headers.len() - 1];
+        let height = self.height() + 1;
+        let interval = self.params.difficulty_adjustment_interval() as u32;
+        let pow_limit = self.params.pow_limit.to_compact_lossy();
+        if !height.is_multiple_of(interval) {
+            if !self.params.allow_min_difficulty_blocks {
+                return last.bits;
+            }
+            // Blocks more than twice the spacing after the previous one may
+            // have the minimum difficulty, the others have the difficulty of
+            // the last block which did not
+            if header.time as u64 > last.time as u64 + self.params.pow_target_spacing * 2 {
+                return pow_limit;
+            }
+            return self
+                .headers
+                .iter()
+                .enumerate()
+                .rev()
+                .find(|(index, previous)| {
+                    (self.base + *index as u32).is_multiple_of(interval)
+                        || previous.bits != pow_limit
+                })
+                .map(|(_, previous)| previous.bits)
+                .unwrap_or(last.bits);
+        }
+        if self.params.no_pow_retargeting {
+            return last.bits;
+        }
+        let first = &self.headers[(height - interval - self.base) as usize];
+        let timespan = self.params.pow_target_timespan;
+        let actual = (last.time as i64 - first.time as i64)
+            .clamp((timespan / 4) as i64, (timespan * 4) as i64) as u64;
+        retarget(Target::from_compact(last.bits), actual, timespan)
+            .map_or(self.params.pow_limit, |target| {
+                target.min(self.params.pow_limit)
+            })
+            .to_compact_lossy()
+    }
+}
+
+/// Scales the target by `actual / expected`, as done at every difficulty
+/// adjustment. Returns `None` if the result does not fit into 256 bits.
+fn retarget(target: Target, actual: u64, expected: u64) -> Option<Target> {
+    let bytes = target.to_le_bytes();
+    let mut limbs = [0u64; 5];
+    for (i, limb) in limbs.iter_mut().take(4).enumerate() {
+        *limb = u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+    }
+    let mut carry = 0u128;
+    for limb in limbs.iter_mut() {
+        let product = *limb as u128 * actual as u128 + carry;
+        *limb = product as u64;
+        carry = product >> 64;
+    }
+    let mut remainder = 0u128;
+    for limb in limbs.iter_mut().rev() {
+        let dividend = (remainder << 64) | *limb as u128;
+        *limb = (dividend / expected as u128) as u64;
+        remainder = dividend % expected as u128;
+    }
+    if limbs[4] != 0 {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    for (i, limb) in limbs.iter().take(4).enumerate() {
+        bytes[i * 8..i * 8 + 8].copy_from_slice(&limb.to_le_bytes());
+    }
+    Some(Target::from_le_bytes(bytes))
+}
@@ -0,0 +1,333 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bitcoin::{
+    p2p::{message::NetworkMessage, message_blockdata::Inventory},
+    Transaction, Txid, Wtxid,
+};
+use serde::Serialize;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+
+use crate::bitcoin::{
+    client_pool::{BitcoinClientPool, PeerSession, PoolConfig, SessionFuture, TcpBitcoinClient},
+    peer_address::PeerAddress,
+};
+
+/// Settings of a mempool observation
+#[derive(Debug, Clone)]
+pub struct MempoolConfig {
+    /// How long a node has to send a requested transaction before it is
+    /// requested from the next node announcing it
+    pub fetch_timeout: Duration,
+    /// How long announced transactions are remembered, so announcements
+    /// by other nodes are not mistaken for new transactions
+    pub retention: Duration,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            fetch_timeout: Duration::from_secs(5),
+            retention: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// Transaction seen on the network for the first time
+#[derive(Debug, Clone)]
+pub struct ObservedTx {
+    pub txid: Txid,
+    pub wtxid: Wtxid,
+    pub transaction: Transaction,
+    /// When the first node announced the transaction
+    pub first_seen: SystemTime,
+    /// Node which announced the transaction first
+    pub peer: PeerAddress,
+    /// Number of nodes which announced the transaction until it arrived
+    pub announcements: usize,
+}
+
+impl ObservedTx {
+    /// Returns the JSON line written for the transaction by the mempool
+    /// command
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(ObservedTxRecord {
+            txid: self.txid.to_string(),
+            wtxid: self.wtxid.to_string(),
+            first_seen_ms: self
+                .first_seen
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            peer: self.peer.to_string(),
+            announcements: self.announcements,
+            size: self.transaction.total_size(),
+            vsize: self.transaction.vsize(),
+        })
+    }
+}
+
+/// Serializable form of an [ObservedTx]
+#[derive(Serialize)]
+struct ObservedTxRecord {
+    txid: String,
+    wtxid: String,
+    first_seen_ms: u64,
+    peer: String,
+    announcements: usize,
+    size: usize,
+    vsize: usize,
+}
+
+/// First announcement of a transaction and the state of fetching it
+#[derive(Debug)]
+struct Announced {
+    first_seen: SystemTime,
+    seen_at: Instant,
+    peer: PeerAddress,
+    announcements: usize,
+    /// When the transaction was last requested, if a request is pending
+    requested_at: Option<Instant>,
+    fetched: bool,
+}
+
+/// Transactions announced by any of the nodes, shared by their sessions
+#[derive(Debug, Default)]
+struct TxTracker {
+    txs: HashMap<Txid, Announced>,
+    pruned_at: Option<Instant>,
+}
+
+impl TxTracker {
+    /// Records the announcement of the transaction by the node and returns
+    /// whether the transaction should be requested from it, which is the
+    /// case unless it already arrived or another node is expected to send
+    /// it
+    fn announce(&mut self, txid: Txid, peer: &PeerAddress, config: &MempoolConfig) -> bool {
+        let now = Instant::now();
+        self.prune(now, config.retention);
+        let announced = self.txs.entry(txid).or_insert_with(|| Announced {
+            first_seen: SystemTime::now(),
+            seen_at: now,
+            peer: peer.clone(),
+            announcements: 0,
+            requested_at: None,
+            fetched: false,
+        });
+        announced.announcements += 1;
+        let pending = announced
+            .requested_at
+            .is_some_and(|at| now.duration_since(at) < config.fetch_timeout);
+        if announced.fetched || pending {
+            return false;
+        }
+        announced.requested_at = Some(now);
+        true
+    }
+
+    /// Marks the transaction as arrived and returns its first announcement,
+    /// unless it already arrived from another node or was never announced
+    fn fetched(&mut self, txid: &Txid) -> Option<&Announced> {
+        let announced = self.txs.get_mut(txid)?;
+        if announced.fetched {
+            return None;
+        }
+        announced.fetched = true;
+        Some(announced)
+    }
+
+    /// Lets the next node announcing the transaction be asked for it
+    fn not_found(&mut self, txid: &Txid) {
+        if let Some(announced) = self.txs.get_mut(txid) {
+            announced.requested_at = None;
+        }
+    }
+
+    /// Forgets transactions first seen longer than the retention ago, at
+    /// most once per tenth of the retention
+    fn prune(&mut self, now: Instant, retention: Duration) {
+        if self
+            .pruned_at
+            .is_some_and(|at| now.duration_since(at) < retention / 10)
+        {
+            return;
+        }
+        self.pruned_at = Some(now);
+        self.txs
+            .retain(|_, announced| now.duration_since(announced.seen_at) < retention);
+    }
+}
+
+/// Session which fetches the transactions the node announces and passes
+/// on the ones no other node sent before, until the subscription is
+/// dropped.
+struct MempoolSession {
+    config: MempoolConfig,
+    tracker: Arc<Mutex<TxTracker>>,
+    sender: UnboundedSender<ObservedTx>,
+}
+
+impl MempoolSession {
+    fn tracker(&self) -> std::sync::MutexGuard<'_, TxTracker> {
+        // Tracker is consistent after every call, so a panic in another
+        // session does not spoil it
+        self.tracker
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl PeerSession for MempoolSession {
+    fn run<'a>(
+        &'a self,
+        node: &'a PeerAddress,
+        client: &'a mut TcpBitcoinClient,
+    ) -> SessionFuture<'a> {
+        Box::pin(async move {
+            loop {
+                let message = tokio::select! {
+                    _ = self.sender.closed() => return Ok(()),
+                    message = client.receive() => message?,
+                };
+                match message {
+                    NetworkMessage::Inv(inventory) => {
+                        let wanted: Vec<Inventory> = {
+                            let mut tracker = self.tracker();
+                            inventory
+                                .iter()
+                                .filter_map(|item| match item {
+                                    Inventory::Transaction(txid)
+                                        if tracker.announce(*txid, node, &self.config) =>
+                                    {
+                                        Some(Inventory::WitnessTransaction(*txid))
+                                    }
+                                    _ => None,
+                                })
+                                .collect()
+                        };
+                        if !wanted.is_empty() {
+                            client.send(NetworkMessage::GetData(wanted)).await?;
+                        }
+                    }
+                    NetworkMessage::Tx(transaction) => {
+                        let txid = transaction.txid();
+                        let observed = self.tracker().fetched(&txid).map(|announced| ObservedTx {
+                            txid,
+                            wtxid: transaction.wtxid(),
+                            first_seen: announced.first_seen,
+                            peer: announced.peer.clone(),
+                            announcements: announced.announcements,
+                            transaction,
+                        });
+                        if let Some(observed) = observed {
+                            tracing::debug!(
+                                tx.txid = %txid,
+                                tx.peer = %observed.peer,
+                                "Observed transaction from Node {}",
+                                node
+                            );
+                            // Closed channel ends the session on the next message
+                            let _ = self.sender.send(observed);
+                        }
+                    }
+                    NetworkMessage::NotFound(inventory) => {
+                        let mut tracker = self.tracker();
+                        for item in inventory {
+                            if let Inventory::Transaction(txid)
+                            | Inventory::WitnessTransaction(txid) = item
+                            {
+                                tracker.not_found(&txid);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        })
+    }
+}
+
+/// Watcher which asks nodes to relay the transactions entering their
+/// mempools, fetches the announced transactions and streams each of them
+/// once, with when and by which node it was first announced.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::client_pool::PoolConfig;
+/// use p2p_handshake_bitcoin::bitcoin::mempool::{MempoolConfig, MempoolWatcher};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let watcher = MempoolWatcher::new(MempoolConfig::default(), PoolConfig::default());
+///     let nodes = vec!["127.0.0.1:1".parse().unwrap()];
+///     let mut subscription = watcher.subscribe(nodes);
+///     while let Some(observed) = subscription.next().await {
+///         println!("{} first announced by {}", observed.txid, observed.peer);
+///     }
+/// }
+/// ```
+pub struct MempoolWatcher {
+    config: MempoolConfig,
+    pool_config: PoolConfig,
+}
+
+/// Stream of the transactions observed by a [MempoolWatcher]. Dropping it
+/// disconnects from the nodes.
+pub struct MempoolSubscription {
+    receiver: UnboundedReceiver<ObservedTx>,
+    pool: JoinHandle<()>,
+}
+
+impl MempoolWatcher {
+    /// Creates a watcher with the configuration of the underlying pool.
+    /// Any session in the pool configuration is replaced by the mempool
+    /// observation, and the nodes are always asked to relay transactions.
+    pub fn new(config: MempoolConfig, pool_config: PoolConfig) -> MempoolWatcher {
+        MempoolWatcher {
+            config,
+            pool_config,
+        }
+    }
+
+    /// Handshakes with the nodes and starts observing their transactions.
+    /// The subscription ends once all nodes are disconnected.
+    pub fn subscribe(self, nodes: Vec<PeerAddress>) -> MempoolSubscription {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let pool_config = PoolConfig {
+            session: Some(Arc::new(MempoolSession {
+                config: self.config,
+                tracker: Arc::new(Mutex::new(TxTracker::default())),
+                sender,
+            })),
+            relay: true,
+            ..self.pool_config
+        };
+        let mut pool = BitcoinClientPool::with_config(nodes, pool_config);
+        // Outcomes are logged by the pool, and only known once the node
+        // disconnects
+        let pool = tokio::spawn(async move { while pool.next_outcome().await.is_some() {} });
+        MempoolSubscription { receiver, pool }
+    }
+}
+
+impl MempoolSubscription {
+    /// Waits for the next transaction no node announced before. Returns
+    /// `None` once all nodes are disconnected.
+    pub async fn next(&mut self) -> Option<ObservedTx> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for MempoolSubscription {
+    fn drop(&mut self) {
+        // Dropping the handle would leave the pool running, along with the
+        // nodes still handshaking or waiting to retry
+        self.pool.abort();
+    }
+}
@@ -45,14 +45,29 @@ impl BitcoinMessage {
 
     /// Returns a VersionMessage which can be sent to Bitcoin node
     pub fn version_message() -> RawNetworkMessage {
-        RawNetworkMessage::new(
-            Network::Bitcoin.magic(),
-            NetworkMessage::Version(BitcoinMessage::get_bitcoin_version_message()),
-        )
+        BitcoinMessage::version_message_for(Network::Bitcoin)
     }
 
     /// Returns a VerackMessage which can be sent to Bitcoin node
     pub fn verack_message() -> RawNetworkMessage {
-        RawNetworkMessage::new(Network::Bitcoin.magic(), NetworkMessage::Verack)
+        BitcoinMessage::verack_message_for(Network::Bitcoin)
+    }
+
+    /// Returns a VersionMessage which can be sent to node on the network
+    pub fn version_message_for(network: Network) -> RawNetworkMessage {
+        BitcoinMessage::message_for(
+            network,
+            NetworkMessage::Version(BitcoinMessage::get_bitcoin_version_message()),
+        )
+    }
+
+    /// Returns a VerackMessage which can be sent to node on the network
+    pub fn verack_message_for(network: Network) -> RawNetworkMessage {
+        BitcoinMessage::message_for(network, NetworkMessage::Verack)
+    }
+
+    /// Wraps the payload into a message which can be sent to node on the network
+    pub fn message_for(network: Network, payload: NetworkMessage) -> RawNetworkMessage {
+        RawNetworkMessage::new(network.magic(), payload)
     }
 }
@@ -1,10 +1,42 @@
+/// Module that keeps known addresses in new and tried buckets
+pub mod addrman;
+/// Module that validates blocks received from nodes
+pub mod blocks;
+/// Module that announces transactions to nodes and serves them
+pub mod broadcast;
+/// Module that records the exchanged bytes into pcap files
+pub mod capture;
 /// Client that is used to establish communication with the remote node
 pub mod client;
 /// Module to handle multiple bitcoin client handshakes
 pub mod client_pool;
 /// Module that handles connection and message exchange with Bitcoin node
 pub mod connection;
+/// Module that discovers the network through getaddr and addr exchange
+pub mod crawler;
+/// Module that verifies compact block filters and their headers
+pub mod filters;
+/// Module that validates chains of block headers
+pub mod headers;
+/// Module that observes the transactions nodes relay from their mempools
+pub mod mempool;
 /// Module that creates Bitcoin compatible messages
 pub mod message;
+/// Module that parses and canonicalises addresses of Bitcoin nodes
+pub mod peer_address;
+/// Module that measures how announcements propagate through nodes
+pub mod propagation;
+/// Module that decides whether failed handshakes are repeated
+pub mod retry;
+/// Module that discovers initial nodes through DNS seeds
+pub mod seed;
 /// Module that provides reading and writing streams
 pub mod stream;
+/// Module that measures the phases of a handshake
+pub mod timing;
+/// Module that compares the chain tips served by nodes
+pub mod tips;
+/// Module that records sessions with nodes and replays them as a fake node
+pub mod transcript;
+/// Module that describes the frames exchanged with nodes in trace logs
+pub mod wire;
@@ -0,0 +1,289 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use bitcoin::Network;
+
+/// Host part of a peer address
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Host {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// Lowercase DNS name, resolved when connecting
+    Hostname(String),
+    /// Lowercase Tor onion service name, including the `.onion` suffix
+    Onion(String),
+}
+
+/// Address of a Bitcoin node, parsed and canonicalised so that the same
+/// node written in different ways compares equal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerAddress {
+    pub host: Host,
+    pub port: u16,
+}
+
+/// Error enumeration for addresses which cannot be parsed
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum PeerAddressError {
+    #[error("Address is empty")]
+    Empty,
+    #[error("Invalid host: {0}")]
+    InvalidHost(String),
+    #[error("Invalid port: {0}")]
+    InvalidPort(String),
+}
+
+/// Result of parsing a list of addresses
+#[derive(Debug, Default)]
+pub struct ParsedPeers {
+    /// Valid and deduplicated addresses, in order of first appearance
+    pub valid: Vec<PeerAddress>,
+    /// Raw entries which could not be parsed, with the reason
+    pub invalid: Vec<(String, PeerAddressError)>,
+}
+
+/// Returns the port Bitcoin nodes listen on by default in the network
+pub fn default_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 8333,
+        Network::Testnet => 18333,
+        Network::Signet => 38333,
+        Network::Regtest => 18444,
+    }
+}
+
+impl PeerAddress {
+    /// Creates an address from an already known host and port
+    pub fn new(host: Host, port: u16) -> PeerAddress {
+        let host = match host {
+            Host::Ipv6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => Host::Ipv4(ip),
+                None => Host::Ipv6(ip),
+            },
+            Host::Hostname(name) => Host::Hostname(name.to_ascii_lowercase()),
+            Host::Onion(name) => Host::Onion(name.to_ascii_lowercase()),
+            host => host,
+        };
+        PeerAddress { host, port }
+    }
+
+    /// Parses an IPv4, bracketed IPv6, hostname or onion address with an
+    /// optional port. If the port is missing, the default port of the network
+    /// is used.
+    ///
+    /// #Example
+    ///
+    /// ```
+    /// use bitcoin::Network;
+    /// use p2p_handshake_bitcoin::bitcoin::peer_address::PeerAddress;
+    ///
+    /// let address = PeerAddress::parse("[::1]", Network::Testnet).unwrap();
+    /// assert_eq!(address.to_string(), "[::1]:18333");
+    /// ```
+    pub fn parse(input: &str, network: Network) -> Result<PeerAddress, PeerAddressError> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(PeerAddressError::Empty);
+        }
+        let (host, port) = if let Some(rest) = input.strip_prefix('[') {
+            let (ip, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| PeerAddressError::InvalidHost(input.to_string()))?;
+            let ip = Ipv6Addr::from_str(ip)
+                .map_err(|_| PeerAddressError::InvalidHost(ip.to_string()))?;
+            let port = match rest {
+                "" => None,
+                rest => Some(
+                    rest.strip_prefix(':')
+                        .ok_or_else(|| PeerAddressError::InvalidPort(rest.to_string()))?,
+                ),
+            };
+            (Host::Ipv6(ip), port)
+        } else if let Ok(ip) = Ipv6Addr::from_str(input) {
+            (Host::Ipv6(ip), None)
+        } else {
+            let (host, port) = match input.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (input, None),
+            };
+            (PeerAddress::parse_host(host)?, port)
+        };
+        let port = match port {
+            Some(port) => match port.parse::<u16>() {
+                Ok(port) if port != 0 => port,
+                _ => return Err(PeerAddressError::InvalidPort(port.to_string())),
+            },
+            None => default_port(network),
+        };
+        Ok(PeerAddress::new(host, port))
+    }
+
+    /// Parses all inputs, deduplicates the valid ones while preserving
+    /// their order and collects the invalid ones.
+    pub fn parse_all<I, S>(inputs: I, network: Network) -> ParsedPeers
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut parsed = ParsedPeers::default();
+        let mut seen = HashSet::new();
+        for input in inputs {
+            let input = input.as_ref();
+            match PeerAddress::parse(input, network) {
+                Ok(address) => {
+                    if seen.insert(address.clone()) {
+                        parsed.valid.push(address);
+                    }
+                }
+                Err(e) => parsed.invalid.push((input.to_string(), e)),
+            }
+        }
+        parsed
+    }
+
+    /// Returns the socket address if the host is an IP address
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self.host {
+            Host::Ipv4(ip) => Some(SocketAddr::new(IpAddr::V4(ip), self.port)),
+            Host::Ipv6(ip) => Some(SocketAddr::new(IpAddr::V6(ip), self.port)),
+            _ => None,
+        }
+    }
+
+    /// Returns the network group of the address, which is the /16 prefix
+    /// for IPv4 and the /32 prefix for IPv6. Addresses in the same group
+    /// are likely run by the same operator.
+    ///
+    /// #Example
+    ///
+    /// ```
+    /// use p2p_handshake_bitcoin::bitcoin::peer_address::PeerAddress;
+    ///
+    /// let address: PeerAddress = "203.0.113.7".parse().unwrap();
+    /// assert_eq!(address.group(), "203.0.0.0/16");
+    /// let address: PeerAddress = "[2001:db8:1::1]".parse().unwrap();
+    /// assert_eq!(address.group(), "2001:db8::/32");
+    /// ```
+    pub fn group(&self) -> String {
+        match &self.host {
+            Host::Ipv4(ip) => {
+                let [a, b, ..] = ip.octets();
+                format!("{}.{}.0.0/16", a, b)
+            }
+            Host::Ipv6(ip) => {
+                let [a, b, ..] = ip.segments();
+                format!("{}/32", Ipv6Addr::new(a, b, 0, 0, 0, 0, 0, 0))
+            }
+            Host::Hostname(name) => name.clone(),
+            Host::Onion(_) => "onion".to_string(),
+        }
+    }
+
+    /// Returns the address in a form which can be used in file names, with
+    /// colons replaced by underscores and without brackets
+    ///
+    /// #Example
+    ///
+    /// ```
+    /// use p2p_handshake_bitcoin::bitcoin::peer_address::PeerAddress;
+    ///
+    /// let address: PeerAddress = "[2001:db8::1]:8333".parse().unwrap();
+    /// assert_eq!(address.file_stem(), "2001_db8__1_8333");
+    /// ```
+    pub fn file_stem(&self) -> String {
+        self.to_string()
+            .chars()
+            .filter(|c| !matches!(c, '[' | ']'))
+            .map(|c| if c == ':' { '_' } else { c })
+            .collect()
+    }
+
+    fn parse_host(host: &str) -> Result<Host, PeerAddressError> {
+        let invalid = || PeerAddressError::InvalidHost(host.to_string());
+        if let Ok(ip) = Ipv4Addr::from_str(host) {
+            return Ok(Host::Ipv4(ip));
+        }
+        let name = host.strip_suffix('.').unwrap_or(host).to_ascii_lowercase();
+        if name.is_empty() || name.len() > 253 {
+            return Err(invalid());
+        }
+        if let Some(service) = name.strip_suffix(".onion") {
+            // v2 services are 16 and v3 services 56 base32 characters long
+            let is_base32 = service
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c));
+            return match service.len() {
+                16 | 56 if is_base32 => Ok(Host::Onion(name)),
+                _ => Err(invalid()),
+            };
+        }
+        let labels_valid = name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+        // A numeric top level label means a mistyped IPv4 address
+        let numeric_tld = name
+            .rsplit('.')
+            .next()
+            .is_some_and(|tld| tld.chars().all(|c| c.is_ascii_digit()));
+        if !labels_valid || numeric_tld {
+            return Err(invalid());
+        }
+        Ok(Host::Hostname(name))
+    }
+}
+
+impl From<SocketAddr> for PeerAddress {
+    fn from(address: SocketAddr) -> Self {
+        let host = match address.ip() {
+            IpAddr::V4(ip) => Host::Ipv4(ip),
+            IpAddr::V6(ip) => Host::Ipv6(ip),
+        };
+        PeerAddress::new(host, address.port())
+    }
+}
+
+impl FromStr for PeerAddress {
+    type Err = PeerAddressError;
+
+    /// Parses an address using the mainnet default port
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PeerAddress::parse(s, Network::Bitcoin)
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Ipv4(ip) => write!(f, "{}", ip),
+            Host::Ipv6(ip) => write!(f, "[{}]", ip),
+            Host::Hostname(name) | Host::Onion(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl serde::Serialize for PeerAddress {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for PeerAddress {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let address = String::deserialize(deserializer)?;
+        address.parse().map_err(serde::de::Error::custom)
+    }
+}
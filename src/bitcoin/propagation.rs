@@ -0,0 +1,470 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bitcoin::{
+    p2p::{message::NetworkMessage, message_blockdata::Inventory},
+    BlockHash, Txid,
+};
+use serde::Serialize;
+
+use crate::{
+    bitcoin::{
+        client_pool::{
+            BitcoinClientPool, NodeOutcome, PeerSession, PoolConfig, SessionFuture,
+            TcpBitcoinClient,
+        },
+        peer_address::PeerAddress,
+    },
+    output::{percentile, write_columns, ReportFormat},
+};
+
+/// Settings of a propagation measurement
+#[derive(Debug, Clone)]
+pub struct PropagationConfig {
+    /// How long announcements are recorded for, from the start of the
+    /// measurement
+    pub duration: Duration,
+    /// Minimum number of nodes which must announce an item for it to count
+    /// towards the delay distributions
+    pub min_peers: usize,
+}
+
+impl Default for PropagationConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(600),
+            min_peers: 2,
+        }
+    }
+}
+
+/// Item announced with inv whose propagation is measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvItem {
+    Transaction(Txid),
+    Block(BlockHash),
+}
+
+impl InvItem {
+    /// Returns the item announced by the inventory entry. Entries by wtxid
+    /// are skipped, as they can not be matched with entries by txid.
+    pub fn from_inventory(inventory: &Inventory) -> Option<InvItem> {
+        match inventory {
+            Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+                Some(InvItem::Transaction(*txid))
+            }
+            Inventory::Block(hash)
+            | Inventory::WitnessBlock(hash)
+            | Inventory::CompactBlock(hash) => Some(InvItem::Block(*hash)),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the item is a block
+    pub fn is_block(&self) -> bool {
+        matches!(self, InvItem::Block(_))
+    }
+}
+
+/// When each node first announced each item, relative to the start of the
+/// log
+///
+/// #Example
+///
+/// ```
+/// use std::time::Duration;
+/// use bitcoin::{hashes::Hash, Txid};
+/// use p2p_handshake_bitcoin::bitcoin::propagation::{AnnouncementLog, InvItem};
+///
+/// let item = InvItem::Transaction(Txid::all_zeros());
+/// let node = "127.0.0.1:8333".parse().unwrap();
+/// let mut log = AnnouncementLog::new();
+/// log.record(item, &node, Duration::from_millis(40));
+/// log.record(item, &node, Duration::from_millis(90));
+/// assert_eq!(log.announcements(&item).unwrap()[&node], Duration::from_millis(40));
+/// ```
+#[derive(Debug)]
+pub struct AnnouncementLog {
+    started: Instant,
+    items: HashMap<InvItem, HashMap<PeerAddress, Duration>>,
+}
+
+impl Default for AnnouncementLog {
+    fn default() -> Self {
+        AnnouncementLog::new()
+    }
+}
+
+impl AnnouncementLog {
+    /// Creates an empty log starting now
+    pub fn new() -> AnnouncementLog {
+        AnnouncementLog {
+            started: Instant::now(),
+            items: HashMap::new(),
+        }
+    }
+
+    /// Records the announcement of the item by the node at the time since
+    /// the start of the log, unless the node announced it earlier
+    pub fn record(&mut self, item: InvItem, node: &PeerAddress, at: Duration) {
+        let first = self
+            .items
+            .entry(item)
+            .or_default()
+            .entry(node.clone())
+            .or_insert(at);
+        *first = (*first).min(at);
+    }
+
+    /// Records the announcement of the item by the node now
+    pub fn record_now(&mut self, item: InvItem, node: &PeerAddress) {
+        self.record(item, node, self.started.elapsed());
+    }
+
+    /// Returns when each node first announced the item
+    pub fn announcements(&self, item: &InvItem) -> Option<&HashMap<PeerAddress, Duration>> {
+        self.items.get(item)
+    }
+
+    /// Returns the number of distinct items announced
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns whether no item was announced
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// Spread of propagation delays in miliseconds, using the nearest-rank
+/// percentiles
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DelayDistribution {
+    pub count: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl DelayDistribution {
+    /// Returns the distribution of the delays, `None` if there are none
+    pub fn of(delays: &[Duration]) -> Option<DelayDistribution> {
+        let mut sorted: Vec<f64> = delays
+            .iter()
+            .map(|delay| delay.as_secs_f64() * 1000.0)
+            .collect();
+        sorted.sort_by(f64::total_cmp);
+        let p = |p| percentile(&sorted, p);
+        Some(DelayDistribution {
+            count: sorted.len(),
+            mean_ms: sorted.iter().sum::<f64>() / sorted.len().max(1) as f64,
+            p50_ms: p(50.0)?,
+            p90_ms: p(90.0)?,
+            p99_ms: p(99.0)?,
+            max_ms: p(100.0)?,
+        })
+    }
+}
+
+/// How quickly a single node announced items compared to the first node
+/// announcing them
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PeerPropagation {
+    #[serde(serialize_with = "serialize_node")]
+    pub node: PeerAddress,
+    /// Number of items the node announced
+    pub announced: usize,
+    /// Number of counted items the node announced before any other node
+    pub first: usize,
+    /// Delays after the first announcement of the transactions it announced
+    pub transactions: Option<DelayDistribution>,
+    /// Delays after the first announcement of the blocks it announced
+    pub blocks: Option<DelayDistribution>,
+}
+
+fn serialize_node<S: serde::Serializer>(node: &PeerAddress, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(node)
+}
+
+/// Result of measuring how announcements propagate through the nodes
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PropagationReport {
+    /// Number of nodes handshakes were attempted with
+    pub attempted: usize,
+    /// Number of nodes which completed the handshake
+    pub connected: usize,
+    /// Number of transactions announced by at least the minimum number of
+    /// nodes
+    pub transactions: usize,
+    /// Number of blocks announced by at least the minimum number of nodes
+    pub blocks: usize,
+    /// Delays of all nodes but the first one announcing each transaction
+    pub transaction_delays: Option<DelayDistribution>,
+    /// Delays of all nodes but the first one announcing each block
+    pub block_delays: Option<DelayDistribution>,
+    /// Nodes which announced anything, fastest first
+    pub peers: Vec<PeerPropagation>,
+}
+
+/// Session which records the items the node announces until the end of
+/// the measurement.
+struct AnnouncementSession {
+    log: Arc<Mutex<AnnouncementLog>>,
+    until: tokio::time::Instant,
+}
+
+impl PeerSession for AnnouncementSession {
+    fn run<'a>(
+        &'a self,
+        node: &'a PeerAddress,
+        client: &'a mut TcpBitcoinClient,
+    ) -> SessionFuture<'a> {
+        Box::pin(async move {
+            let recorded = tokio::time::timeout_at(self.until, async {
+                loop {
+                    if let NetworkMessage::Inv(inventory) = client.receive().await? {
+                        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+                        for item in inventory.iter().filter_map(InvItem::from_inventory) {
+                            log.record_now(item, node);
+                        }
+                    }
+                }
+            })
+            .await;
+            match recorded {
+                Ok(result) => result,
+                // Measurement is over
+                Err(_) => Ok(()),
+            }
+        })
+    }
+}
+
+/// Measurement which keeps sessions with all nodes open, asking them to
+/// relay transactions, records when each node first announced each
+/// transaction and block, and reports how much later than the first node
+/// the others announced them.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::client_pool::PoolConfig;
+/// use p2p_handshake_bitcoin::bitcoin::propagation::{PropagationConfig, PropagationMonitor};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let monitor = PropagationMonitor::new(PropagationConfig::default(), PoolConfig::default());
+///     let nodes = vec!["127.0.0.1:1".parse().unwrap()];
+///     let report = monitor.run(nodes, |_| Ok(())).await.unwrap();
+///     assert_eq!(report.connected, 0);
+///     assert!(report.peers.is_empty());
+/// }
+/// ```
+pub struct PropagationMonitor {
+    config: PropagationConfig,
+    pool_config: PoolConfig,
+}
+
+impl PropagationMonitor {
+    /// Creates a measurement with the configuration of the underlying pool.
+    /// Any session in the pool configuration is replaced by the recording
+    /// of announcements, and the nodes are always asked to relay
+    /// transactions.
+    pub fn new(config: PropagationConfig, pool_config: PoolConfig) -> PropagationMonitor {
+        PropagationMonitor {
+            config,
+            pool_config,
+        }
+    }
+
+    /// Handshakes with the nodes and records their announcements for the
+    /// duration. Every node outcome is passed to `on_outcome` as soon as
+    /// it is known, which is once the node disconnected or the duration
+    /// is over.
+    pub async fn run<F>(
+        self,
+        nodes: Vec<PeerAddress>,
+        mut on_outcome: F,
+    ) -> Result<PropagationReport, anyhow::Error>
+    where
+        F: FnMut(&NodeOutcome) -> Result<(), anyhow::Error>,
+    {
+        let log = Arc::new(Mutex::new(AnnouncementLog::new()));
+        let pool_config = PoolConfig {
+            session: Some(Arc::new(AnnouncementSession {
+                log: log.clone(),
+                until: tokio::time::Instant::now() + self.config.duration,
+            })),
+            relay: true,
+            ..self.pool_config
+        };
+        let attempted = nodes.len();
+        let mut pool = BitcoinClientPool::with_config(nodes, pool_config);
+        let mut connected = 0;
+        while let Some(outcome) = pool.next_outcome().await {
+            on_outcome(&outcome)?;
+            if outcome.result.is_ok() {
+                connected += 1;
+            }
+        }
+        let log = log.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report = PropagationReport::analyze(&log, self.config.min_peers);
+        report.attempted = attempted;
+        report.connected = connected;
+        Ok(report)
+    }
+}
+
+impl PropagationReport {
+    /// Computes the delays of the announcements in the log. Items announced
+    /// by fewer than `min_peers` nodes only count towards the number of
+    /// items the nodes announced, as a single announcement has no delay to
+    /// compare with.
+    pub fn analyze(log: &AnnouncementLog, min_peers: usize) -> PropagationReport {
+        #[derive(Default)]
+        struct PeerDelays {
+            announced: usize,
+            first: usize,
+            transactions: Vec<Duration>,
+            blocks: Vec<Duration>,
+        }
+        let mut report = PropagationReport::default();
+        let mut peers: HashMap<&PeerAddress, PeerDelays> = HashMap::new();
+        let mut transaction_delays = Vec::new();
+        let mut block_delays = Vec::new();
+        for (item, announcements) in &log.items {
+            for node in announcements.keys() {
+                peers.entry(node).or_default().announced += 1;
+            }
+            if announcements.len() < min_peers.max(1) {
+                continue;
+            }
+            let Some((first_node, &first_at)) = announcements
+                .iter()
+                .min_by(|(a_node, a), (b_node, b)| a.cmp(b).then_with(|| a_node.cmp(b_node)))
+            else {
+                continue;
+            };
+            if item.is_block() {
+                report.blocks += 1;
+            } else {
+                report.transactions += 1;
+            }
+            peers.entry(first_node).or_default().first += 1;
+            for (node, at) in announcements {
+                let delay = *at - first_at;
+                let delays = peers.entry(node).or_default();
+                if item.is_block() {
+                    delays.blocks.push(delay);
+                } else {
+                    delays.transactions.push(delay);
+                }
+                if node != first_node {
+                    if item.is_block() {
+                        block_delays.push(delay);
+                    } else {
+                        transaction_delays.push(delay);
+                    }
+                }
+            }
+        }
+        report.transaction_delays = DelayDistribution::of(&transaction_delays);
+        report.block_delays = DelayDistribution::of(&block_delays);
+        report.peers = peers
+            .into_iter()
+            .map(|(node, delays)| PeerPropagation {
+                node: node.clone(),
+                announced: delays.announced,
+                first: delays.first,
+                transactions: DelayDistribution::of(&delays.transactions),
+                blocks: DelayDistribution::of(&delays.blocks),
+            })
+            .collect();
+        let p50 = |peer: &PeerPropagation| peer.transactions.map_or(f64::INFINITY, |d| d.p50_ms);
+        report.peers.sort_by(|a, b| {
+            p50(a)
+                .total_cmp(&p50(b))
+                .then_with(|| b.first.cmp(&a.first))
+                .then_with(|| a.node.to_string().cmp(&b.node.to_string()))
+        });
+        report
+    }
+
+    /// Writes the report in the selected format
+    pub fn write<W: Write>(&self, writer: &mut W, format: ReportFormat) -> anyhow::Result<()> {
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut *writer, self)?;
+                writeln!(writer)?;
+            }
+            ReportFormat::Report => self.write_report(writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a readable report with the overall delays and a row per node
+    fn write_report<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "Nodes: {}, connected: {}, announcing: {}",
+            self.attempted,
+            self.connected,
+            self.peers.len()
+        )?;
+        for (name, count, delays) in [
+            ("Transactions", self.transactions, &self.transaction_delays),
+            ("Blocks", self.blocks, &self.block_delays),
+        ] {
+            write!(writer, "{}: {}", name, count)?;
+            if let Some(delays) = delays {
+                write!(
+                    writer,
+                    ", delay mean {:.1} ms, p50 {:.1} ms, p90 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+                    delays.mean_ms, delays.p50_ms, delays.p90_ms, delays.p99_ms, delays.max_ms
+                )?;
+            }
+            writeln!(writer)?;
+        }
+        if self.peers.is_empty() {
+            return Ok(());
+        }
+        let ms = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.1} ms", v));
+        let rows: Vec<Vec<String>> = self
+            .peers
+            .iter()
+            .map(|peer| {
+                vec![
+                    peer.node.to_string(),
+                    peer.announced.to_string(),
+                    peer.first.to_string(),
+                    ms(peer.transactions.map(|d| d.p50_ms)),
+                    ms(peer.transactions.map(|d| d.p90_ms)),
+                    ms(peer.blocks.map(|d| d.p50_ms)),
+                    ms(peer.blocks.map(|d| d.max_ms)),
+                ]
+            })
+            .collect();
+        writeln!(writer)?;
+        write_columns(
+            writer,
+            &[
+                "NODE",
+                "ANNOUNCED",
+                "FIRST",
+                "TX P50",
+                "TX P90",
+                "BLOCK P50",
+                "BLOCK MAX",
+            ],
+            &rows,
+        )
+    }
+}
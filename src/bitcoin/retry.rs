@@ -0,0 +1,149 @@
+use std::{fmt, time::Duration};
+
+use rand::Rng;
+
+use crate::bitcoin::client::BitcoinClientError;
+
+/// Category of a failed handshake attempt. It is used to decide whether
+/// the attempt should be repeated.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// Connecting or the handshake took longer than the timeout
+    Timeout,
+    /// TCP connection could not be established or was dropped
+    Connection,
+    /// Node did not respond with a readable message
+    Communication,
+    /// Node responded with an invalid message
+    Message,
+    /// Node advertised a protocol version that is not supported
+    VersionTooOld,
+    /// Any other error
+    Unexpected,
+}
+
+impl ErrorKind {
+    /// Classifies an error by walking its cause chain and looking for
+    /// a known error type.
+    pub fn of(error: &anyhow::Error) -> ErrorKind {
+        for cause in error.chain() {
+            if cause.is::<tokio::time::error::Elapsed>() {
+                return ErrorKind::Timeout;
+            }
+            if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                return match e.kind() {
+                    std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+                    _ => ErrorKind::Connection,
+                };
+            }
+            match cause.downcast_ref::<BitcoinClientError>() {
+                Some(BitcoinClientError::CommunicationError) => return ErrorKind::Communication,
+                Some(BitcoinClientError::MessageError)
+                | Some(BitcoinClientError::InvalidHeader(_))
+                | Some(BitcoinClientError::BlockNotFound(_))
+                | Some(BitcoinClientError::InvalidBlock(_))
+                | Some(BitcoinClientError::InvalidFilter(_)) => return ErrorKind::Message,
+                Some(BitcoinClientError::VersionTooOld(_)) => return ErrorKind::VersionTooOld,
+                // Unexpected errors wrap their cause, so keep on looking
+                Some(BitcoinClientError::UnexpectedError(_)) | None => {}
+            }
+        }
+        ErrorKind::Unexpected
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Connection => "connection",
+            ErrorKind::Communication => "communication",
+            ErrorKind::Message => "message",
+            ErrorKind::VersionTooOld => "version-too-old",
+            ErrorKind::Unexpected => "unexpected",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Policy that decides how many times and how often a failed handshake
+/// is repeated. Delay between attempts grows exponentially from
+/// `base_delay` up to `max_delay`, with a random `jitter` fraction applied.
+///
+/// #Example
+///
+/// ```
+/// use std::time::Duration;
+/// use p2p_handshake_bitcoin::bitcoin::retry::{ErrorKind, RetryPolicy};
+///
+/// let policy = RetryPolicy {
+///     max_attempts: 5,
+///     base_delay: Duration::from_millis(100),
+///     ..RetryPolicy::default()
+/// };
+/// assert!(policy.is_retryable(ErrorKind::Timeout));
+/// assert!(!policy.is_retryable(ErrorKind::VersionTooOld));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the second attempt
+    pub base_delay: Duration,
+    /// Upper bound for the delay between two attempts
+    pub max_delay: Duration,
+    /// Fraction in range 0.0 to 1.0 by which the delay is randomly varied
+    pub jitter: f64,
+    /// Error kinds which are worth another attempt
+    pub retryable: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    /// Single attempt, so the default behaviour is not to retry at all.
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.2,
+            retryable: vec![
+                ErrorKind::Timeout,
+                ErrorKind::Connection,
+                ErrorKind::Communication,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns whether the error kind is worth another attempt
+    pub fn is_retryable(&self, kind: ErrorKind) -> bool {
+        self.retryable.contains(&kind)
+    }
+
+    /// Returns whether another attempt should follow the failed `attempt`
+    /// (counted from 1) which failed with the error `kind`.
+    pub fn should_retry(&self, attempt: u32, kind: ErrorKind) -> bool {
+        attempt < self.max_attempts && self.is_retryable(kind)
+    }
+
+    /// Returns the delay to wait after the failed `attempt` (counted from 1)
+    /// before the next one is made.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        // NaN passes the clamp and would make the range below panic
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 || jitter.is_nan() {
+            return delay;
+        }
+        let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+        delay.mul_f64(factor)
+    }
+}
@@ -0,0 +1,125 @@
+use std::{collections::HashSet, future::Future, io, net::IpAddr};
+
+use bitcoin::{p2p::ServiceFlags, Network};
+
+use crate::bitcoin::peer_address::{default_port, Host, PeerAddress};
+
+/// Resolver of DNS seed hostnames. It is pluggable, so tests can resolve
+/// seeds without touching the real DNS.
+pub trait Resolver: Send + Sync {
+    /// Returns IP addresses the hostname resolves to
+    fn resolve(&self, host: &str) -> impl Future<Output = io::Result<Vec<IpAddr>>> + Send;
+}
+
+/// Resolver which uses the resolver of the operating system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        // Port is required by the lookup, but not used
+        let addresses = tokio::net::lookup_host((host, 0)).await?;
+        Ok(addresses.map(|address| address.ip()).collect())
+    }
+}
+
+/// Returns the well-known DNS seeds of the network, as used by Bitcoin Core
+pub fn dns_seeds(network: Network) -> &'static [&'static str] {
+    match network {
+        Network::Bitcoin => &[
+            "seed.bitcoin.sipa.be",
+            "dnsseed.bluematt.me",
+            "dnsseed.bitcoin.dashjr-list-of-p2p-nodes.us",
+            "seed.bitcoinstats.com",
+            "seed.bitcoin.jonasschnelli.ch",
+            "seed.btc.petertodd.net",
+            "seed.bitcoin.sprovoost.nl",
+            "dnsseed.emzy.de",
+            "seed.bitcoin.wiz.biz",
+            "seed.mainnet.achownodes.xyz",
+        ],
+        Network::Testnet => &[
+            "testnet-seed.bitcoin.jonasschnelli.ch",
+            "seed.tbtc.petertodd.net",
+            "seed.testnet.bitcoin.sprovoost.nl",
+            "testnet-seed.bluematt.me",
+            "seed.testnet.achownodes.xyz",
+        ],
+        Network::Signet => &[
+            "seed.signet.bitcoin.sprovoost.nl",
+            "seed.signet.achownodes.xyz",
+        ],
+        Network::Regtest => &[],
+    }
+}
+
+/// Returns the hostname to query on the seed. With service flags, the
+/// `x<flags in hex>.` subdomain is used, which makes the seed return only
+/// nodes advertising all of the flags.
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::p2p::ServiceFlags;
+/// use p2p_handshake_bitcoin::bitcoin::seed::seed_host;
+///
+/// let services = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
+/// assert_eq!(seed_host("seed.bitcoin.sipa.be", Some(services)), "x9.seed.bitcoin.sipa.be");
+/// assert_eq!(seed_host("seed.bitcoin.sipa.be", None), "seed.bitcoin.sipa.be");
+/// ```
+pub fn seed_host(seed: &str, services: Option<ServiceFlags>) -> String {
+    match services {
+        Some(services) if services != ServiceFlags::NONE => {
+            format!("x{:x}.{}", services.to_u64(), seed)
+        }
+        _ => seed.to_string(),
+    }
+}
+
+/// Resolves the DNS seeds of the network and returns the deduplicated
+/// addresses of the nodes, using the default port of the network. Seeds
+/// which fail to resolve are logged and skipped.
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::Network;
+/// use p2p_handshake_bitcoin::bitcoin::seed::{resolve_seeds, SystemResolver};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let nodes = resolve_seeds(&SystemResolver, Network::Regtest, None).await;
+///     assert!(nodes.is_empty());
+/// }
+/// ```
+pub async fn resolve_seeds<R: Resolver>(
+    resolver: &R,
+    network: Network,
+    services: Option<ServiceFlags>,
+) -> Vec<PeerAddress> {
+    let port = default_port(network);
+    let mut seen = HashSet::new();
+    let mut nodes = Vec::new();
+    for seed in dns_seeds(network) {
+        let host = seed_host(seed, services);
+        let addresses = match resolver.resolve(&host).await {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                tracing::warn!(error.message = %e, "Failed to resolve DNS seed {}", host);
+                continue;
+            }
+        };
+        tracing::info!("DNS seed {} returned {} addresses", host, addresses.len());
+        for ip in addresses {
+            let host = match ip {
+                IpAddr::V4(ip) => Host::Ipv4(ip),
+                IpAddr::V6(ip) => Host::Ipv6(ip),
+            };
+            let node = PeerAddress::new(host, port);
+            if seen.insert(node.clone()) {
+                nodes.push(node);
+            }
+        }
+    }
+    nodes
+}
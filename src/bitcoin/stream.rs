@@ -1,10 +1,13 @@
-use std::time::Duration;
+use std::{io, time::Duration};
 
 use tokio::net::{
+    lookup_host,
     tcp::{OwnedReadHalf, OwnedWriteHalf},
     TcpStream,
 };
 
+use crate::bitcoin::timing::{Phase, PhaseTimings};
+
 /// Module that provides reading and writing streams
 pub struct Stream {
     pub rx: OwnedReadHalf,
@@ -14,8 +17,54 @@ pub struct Stream {
 impl Stream {
     /// Creates a stream on the provided ip addresses of Bitcoin nodes
     pub async fn new(uri: &str, timeout: u64) -> Result<Self, anyhow::Error> {
-        let socket =
-            tokio::time::timeout(Duration::from_millis(timeout), TcpStream::connect(uri)).await??;
+        Stream::connect(uri, timeout, &mut PhaseTimings::start()).await
+    }
+
+    /// Creates a stream like [Stream::new], recording when the host name
+    /// was resolved and when the TCP connection was established. Resolved
+    /// addresses are tried in turn until one of them accepts the connection.
+    ///
+    /// #Example
+    ///
+    /// ```
+    /// use p2p_handshake_bitcoin::bitcoin::stream::Stream;
+    /// use p2p_handshake_bitcoin::bitcoin::timing::{Phase, PhaseTimings};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut timings = PhaseTimings::start();
+    ///     let result = Stream::connect("127.0.0.1:1", 500, &mut timings).await;
+    ///     assert!(result.is_err());
+    ///     assert!(timings.get(Phase::Dns).is_some());
+    ///     assert_eq!(timings.get(Phase::Connect), None);
+    /// }
+    /// ```
+    pub async fn connect(
+        uri: &str,
+        timeout: u64,
+        timings: &mut PhaseTimings,
+    ) -> Result<Self, anyhow::Error> {
+        let socket = tokio::time::timeout(Duration::from_millis(timeout), async {
+            let addresses: Vec<_> = lookup_host(uri).await?.collect();
+            timings.mark(Phase::Dns);
+            let mut last_error = None;
+            for address in addresses {
+                match TcpStream::connect(address).await {
+                    Ok(socket) => {
+                        timings.mark(Phase::Connect);
+                        return Ok(socket);
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            Err(last_error.unwrap_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "could not resolve to any address",
+                )
+            }))
+        })
+        .await??;
         let (rx, tx) = socket.into_split();
         Ok(Self { rx, tx })
     }
@@ -0,0 +1,129 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Phases of a handshake, in the order in which they end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Resolution of the host name into addresses
+    Dns,
+    /// Establishment of the TCP connection
+    Connect,
+    /// Wait for the first byte from the node
+    FirstByte,
+    /// Wait for the version message of the node
+    Version,
+    /// Wait for the verack message of the node
+    Verack,
+}
+
+impl Phase {
+    /// All phases, in the order in which they end
+    pub const ALL: [Phase; 5] = [
+        Phase::Dns,
+        Phase::Connect,
+        Phase::FirstByte,
+        Phase::Version,
+        Phase::Verack,
+    ];
+
+    /// Returns the name of the phase, as used in logs and metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Dns => "dns",
+            Phase::Connect => "connect",
+            Phase::FirstByte => "first_byte",
+            Phase::Version => "version",
+            Phase::Verack => "verack",
+        }
+    }
+}
+
+/// Times at which the phases of a single handshake attempt ended, measured
+/// from the start of the attempt. Phases which were not reached are `None`.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::timing::{Phase, PhaseTimings};
+///
+/// let mut timings = PhaseTimings::start();
+/// timings.mark(Phase::Dns);
+/// timings.mark(Phase::Connect);
+/// assert!(timings.get(Phase::Connect) >= timings.get(Phase::Dns));
+/// assert_eq!(timings.get(Phase::Verack), None);
+/// assert_eq!(timings.phases().len(), 2);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTimings {
+    start: Instant,
+    ends: [Option<Duration>; 5],
+}
+
+impl PhaseTimings {
+    /// Starts measuring an attempt now
+    pub fn start() -> PhaseTimings {
+        PhaseTimings::start_at(Instant::now())
+    }
+
+    /// Starts measuring an attempt which started at the instant
+    pub fn start_at(start: Instant) -> PhaseTimings {
+        PhaseTimings {
+            start,
+            ends: [None; 5],
+        }
+    }
+
+    /// Records that the phase ended now, unless it already ended before
+    pub fn mark(&mut self, phase: Phase) {
+        self.mark_at(phase, Instant::now());
+    }
+
+    /// Records that the phase ended at the instant, unless it already
+    /// ended before
+    pub fn mark_at(&mut self, phase: Phase, instant: Instant) {
+        let end = &mut self.ends[phase as usize];
+        if end.is_none() {
+            *end = Some(instant.saturating_duration_since(self.start));
+        }
+    }
+
+    /// Returns when the phase ended, measured from the start of the attempt
+    pub fn get(&self, phase: Phase) -> Option<Duration> {
+        self.ends[phase as usize]
+    }
+
+    /// Returns the time spent in each phase which ended, measured from the
+    /// end of the phase before it
+    pub fn phases(&self) -> Vec<(Phase, Duration)> {
+        let mut previous = Duration::ZERO;
+        let mut phases = Vec::new();
+        for phase in Phase::ALL {
+            if let Some(end) = self.get(phase) {
+                phases.push((phase, end.saturating_sub(previous)));
+                previous = end;
+            }
+        }
+        phases
+    }
+}
+
+/// Lists the time spent in each phase which ended, e.g.
+/// `dns=0.02ms connect=0.31ms first_byte=0.85ms version=0.01ms verack=0.40ms`
+impl fmt::Display for PhaseTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (phase, duration)) in self.phases().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(
+                f,
+                "{}={:.2}ms",
+                phase.name(),
+                duration.as_secs_f64() * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,591 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    slice,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use bitcoin::{block::Header, BlockHash};
+use serde::Serialize;
+
+use crate::{
+    bitcoin::{
+        client::BitcoinClientError,
+        client_pool::{
+            session_channel, BitcoinClientPool, NodeOutcome, PeerSession, PoolConfig,
+            SessionFuture, SessionSender, TcpBitcoinClient,
+        },
+        headers::{ChainTip, HeaderChain, HeaderError, MAX_HEADERS_PER_MESSAGE},
+        peer_address::PeerAddress,
+    },
+    output::{write_columns, ReportFormat},
+};
+
+/// Settings of a comparison of chain tips
+#[derive(Debug, Clone)]
+pub struct TipConfig {
+    /// How long to wait for each batch of headers
+    pub headers_timeout: Duration,
+    /// Number of blocks a node may be behind the consensus tip, or its
+    /// start height off its best header, without being flagged
+    pub tolerance: u32,
+}
+
+impl Default for TipConfig {
+    fn default() -> Self {
+        Self {
+            headers_timeout: Duration::from_secs(10),
+            tolerance: 1,
+        }
+    }
+}
+
+/// Headers a node served: the headers of the chain shared by the survey
+/// up to a height, then the ones of its own branch if it diverged from it
+#[derive(Debug, Clone)]
+pub struct ServedChain {
+    /// Height of the last header of the shared chain the node served
+    pub trunk_height: u32,
+    /// Hashes of the headers after the trunk height, which are not in the
+    /// shared chain
+    pub branch: Vec<BlockHash>,
+    /// Best header the node served
+    pub tip: ChainTip,
+    /// Error which stopped the sync, such as an invalid header
+    pub error: Option<String>,
+}
+
+/// Headers a node served so far in the sync. Only the ones after it
+/// diverged from the shared chain are kept for the node, in a branch which
+/// starts at the last difficulty adjustment before.
+struct NodeHeaders {
+    trunk_height: u32,
+    branch: Option<HeaderChain>,
+}
+
+impl NodeHeaders {
+    /// Validates the headers the node sent and adds them either to the
+    /// shared chain or to the branch of the node. Returns the number of
+    /// headers the node had not served before.
+    fn extend(
+        &mut self,
+        trunk: &mut HeaderChain,
+        headers: &[Header],
+    ) -> Result<usize, HeaderError> {
+        let mut appended = 0;
+        for header in headers {
+            if let Some(branch) = &mut self.branch {
+                appended += branch.extend(slice::from_ref(header))?;
+            } else if let Some(height) = trunk.height_of(&header.block_hash()) {
+                if height == self.trunk_height + 1 {
+                    self.trunk_height = height;
+                    appended += 1;
+                }
+            } else if self.trunk_height == trunk.height() {
+                appended += trunk.extend(slice::from_ref(header))?;
+                self.trunk_height = trunk.height();
+            } else {
+                let mut branch = trunk
+                    .branch(self.trunk_height)
+                    .expect("Node headers are on the shared chain");
+                appended += branch.extend(slice::from_ref(header))?;
+                self.branch = Some(branch);
+            }
+        }
+        Ok(appended)
+    }
+
+    /// Returns what the node served, keeping only the hashes of its branch
+    fn served(&self, trunk: &HeaderChain, error: Option<String>) -> ServedChain {
+        match &self.branch {
+            Some(branch) => ServedChain {
+                trunk_height: self.trunk_height,
+                branch: branch.hashes()[(self.trunk_height + 1 - branch.base_height()) as usize..]
+                    .to_vec(),
+                tip: branch.tip(),
+                error,
+            },
+            None => ServedChain {
+                trunk_height: self.trunk_height,
+                branch: Vec::new(),
+                tip: trunk
+                    .tip_at(self.trunk_height)
+                    .expect("Node headers are on the shared chain"),
+                error,
+            },
+        }
+    }
+}
+
+/// How the best header of a node relates to the consensus tip
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TipStatus {
+    /// Best header is the consensus tip, or at most the tolerance behind it
+    Synced,
+    /// Best header is on the consensus chain, more than the tolerance behind
+    Lagging { blocks: u32 },
+    /// Best header extends the consensus chain
+    Ahead { blocks: u32 },
+    /// Best header is on a chain which diverged from the consensus chain
+    /// after the header at the height
+    Forked { fork_height: u32 },
+    /// Headers could not be synced, e.g. because the node sent an invalid one
+    Failed { error: String },
+}
+
+impl TipStatus {
+    /// Returns the name of the status, as used in reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            TipStatus::Synced => "synced",
+            TipStatus::Lagging { .. } => "lagging",
+            TipStatus::Ahead { .. } => "ahead",
+            TipStatus::Forked { .. } => "forked",
+            TipStatus::Failed { .. } => "failed",
+        }
+    }
+}
+
+impl fmt::Display for TipStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TipStatus::Synced => write!(f, "synced"),
+            TipStatus::Lagging { blocks } => write!(f, "lagging by {} blocks", blocks),
+            TipStatus::Ahead { blocks } => write!(f, "ahead by {} blocks", blocks),
+            TipStatus::Forked { fork_height } => write!(f, "forked after height {}", fork_height),
+            TipStatus::Failed { error } => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+/// Best header of a single node compared to the consensus tip
+#[derive(Debug, Clone)]
+pub struct PeerTip {
+    pub node: PeerAddress,
+    /// Start height from the version message of the node
+    pub start_height: i32,
+    /// Best header the node served
+    pub tip: ChainTip,
+    pub status: TipStatus,
+    /// Whether the start height is further off the best header than the
+    /// tolerance
+    pub misreports_start_height: bool,
+}
+
+impl PeerTip {
+    /// Returns whether the node is behind, ahead, on another fork, failed
+    /// to sync or misreported its start height
+    pub fn is_flagged(&self) -> bool {
+        self.status != TipStatus::Synced || self.misreports_start_height
+    }
+}
+
+/// Result of comparing the best headers of nodes
+#[derive(Debug, Clone, Default)]
+pub struct TipReport {
+    /// Number of nodes handshakes were attempted with
+    pub attempted: usize,
+    /// Best header served by most nodes, ties going to the one with the
+    /// most work
+    pub consensus: Option<ChainTip>,
+    /// Nodes which completed the handshake and served headers
+    pub peers: Vec<PeerTip>,
+}
+
+/// Session which syncs the headers of the node on top of the chain shared
+/// by all nodes and passes them on together with the address of the node.
+/// Headers most nodes serve are only stored once, in the shared chain.
+struct HeaderSyncSession {
+    trunk: Mutex<HeaderChain>,
+    timeout: Duration,
+    sender: SessionSender<(PeerAddress, ServedChain)>,
+}
+
+impl HeaderSyncSession {
+    /// Requests headers from the node until a batch is not full or brings
+    /// nothing new
+    async fn sync(
+        &self,
+        headers: &mut NodeHeaders,
+        client: &mut TcpBitcoinClient,
+    ) -> Result<(), BitcoinClientError> {
+        loop {
+            let locator = match &headers.branch {
+                Some(branch) => branch.locator(),
+                None => self
+                    .trunk()
+                    .hash(headers.trunk_height)
+                    .into_iter()
+                    .collect(),
+            };
+            let received = client.get_headers(locator, self.timeout).await?;
+            let appended = headers.extend(&mut self.trunk(), &received)?;
+            if received.len() < MAX_HEADERS_PER_MESSAGE || appended == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn trunk(&self) -> MutexGuard<'_, HeaderChain> {
+        self.trunk.lock().expect("Shared chain lock is poisoned")
+    }
+}
+
+impl PeerSession for HeaderSyncSession {
+    fn run<'a>(
+        &'a self,
+        node: &'a PeerAddress,
+        client: &'a mut TcpBitcoinClient,
+    ) -> SessionFuture<'a> {
+        Box::pin(async move {
+            let mut headers = NodeHeaders {
+                trunk_height: self.trunk().base_height(),
+                branch: None,
+            };
+            let result = self.sync(&mut headers, client).await;
+            let error = result.as_ref().err().map(ToString::to_string);
+            let served = headers.served(&self.trunk(), error);
+            tracing::info!(
+                tip.height = served.tip.height,
+                tip.hash = %served.tip.hash,
+                "Node {} served headers",
+                node
+            );
+            self.sender.send((node.clone(), served));
+            result?;
+            Ok(())
+        })
+    }
+}
+
+/// Survey which syncs the headers of every node after the handshake and
+/// compares their best headers, finding the consensus tip and the nodes
+/// which are lagging, on a different fork or misreport their start height.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::client_pool::PoolConfig;
+/// use p2p_handshake_bitcoin::bitcoin::tips::{TipConfig, TipSurvey};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let survey = TipSurvey::new(TipConfig::default(), PoolConfig::default());
+///     let nodes = vec!["127.0.0.1:1".parse().unwrap()];
+///     let report = survey.run(nodes, |_| Ok(())).await.unwrap();
+///     assert_eq!(report.attempted, 1);
+///     assert!(report.consensus.is_none());
+/// }
+/// ```
+pub struct TipSurvey {
+    config: TipConfig,
+    pool_config: PoolConfig,
+    chain: HeaderChain,
+}
+
+impl TipSurvey {
+    /// Creates a survey which syncs headers from the genesis block of the
+    /// network of the pool. Any session in the pool configuration is
+    /// replaced by the header sync.
+    pub fn new(config: TipConfig, pool_config: PoolConfig) -> TipSurvey {
+        let chain = HeaderChain::new(pool_config.network);
+        TipSurvey::with_chain(config, pool_config, chain)
+    }
+
+    /// Creates a survey which syncs headers on top of the chain, e.g. one
+    /// starting at a recent checkpoint
+    pub fn with_chain(config: TipConfig, pool_config: PoolConfig, chain: HeaderChain) -> TipSurvey {
+        TipSurvey {
+            config,
+            pool_config,
+            chain,
+        }
+    }
+
+    /// Handshakes with the nodes and syncs their headers. Every node
+    /// outcome is passed to `on_outcome` as soon as it is known.
+    pub async fn run<F>(
+        self,
+        nodes: Vec<PeerAddress>,
+        mut on_outcome: F,
+    ) -> Result<TipReport, anyhow::Error>
+    where
+        F: FnMut(&NodeOutcome) -> Result<(), anyhow::Error>,
+    {
+        let (sender, mut receiver) = session_channel();
+        let pool_config = PoolConfig {
+            session: Some(Arc::new(HeaderSyncSession {
+                trunk: Mutex::new(self.chain),
+                timeout: self.config.headers_timeout,
+                sender,
+            })),
+            ..self.pool_config
+        };
+        let attempted = nodes.len();
+        let mut pool = BitcoinClientPool::with_config(nodes, pool_config);
+        let mut start_heights = HashMap::new();
+        let mut served = Vec::new();
+        while let Some(outcome) = pool.next_outcome().await {
+            on_outcome(&outcome)?;
+            if let Ok(version) = &outcome.result {
+                start_heights.insert(outcome.node.clone(), version.start_height);
+            }
+            served.extend(receiver.drain());
+        }
+        let served = served
+            .into_iter()
+            .filter_map(|(node, chain)| {
+                let start_height = *start_heights.get(&node)?;
+                Some((node, start_height, chain))
+            })
+            .collect();
+        let report = TipReport::compare(attempted, served, self.config.tolerance);
+        for peer in report.peers.iter().filter(|peer| peer.is_flagged()) {
+            tracing::warn!(
+                tip.height = peer.tip.height,
+                tip.hash = %peer.tip.hash,
+                peer.start_height = peer.start_height,
+                "Node {} is {}{}",
+                peer.node,
+                peer.status,
+                if peer.misreports_start_height {
+                    " and misreports its start height"
+                } else {
+                    ""
+                }
+            );
+        }
+        Ok(report)
+    }
+}
+
+impl TipReport {
+    /// Compares the chains served by the nodes, together with the start
+    /// heights from their version messages. All chains must have been
+    /// served on top of the same shared chain.
+    pub fn compare(
+        attempted: usize,
+        served: Vec<(PeerAddress, i32, ServedChain)>,
+        tolerance: u32,
+    ) -> TipReport {
+        let mut counts: HashMap<BlockHash, (usize, usize)> = HashMap::new();
+        for (index, (_, _, chain)) in served.iter().enumerate() {
+            if chain.error.is_none() {
+                counts.entry(chain.tip.hash).or_insert((0, index)).0 += 1;
+            }
+        }
+        let consensus = counts
+            .values()
+            .map(|&(count, index)| (count, &served[index].2))
+            .max_by(|(a, a_chain), (b, b_chain)| {
+                a.cmp(b)
+                    .then_with(|| a_chain.tip.work.cmp(&b_chain.tip.work))
+                    .then_with(|| b_chain.tip.hash.cmp(&a_chain.tip.hash))
+            })
+            .map(|(_, chain)| chain.clone());
+        let peers = served
+            .into_iter()
+            .map(|(node, start_height, chain)| {
+                let status = match (&chain.error, &consensus) {
+                    (Some(error), _) => TipStatus::Failed {
+                        error: error.clone(),
+                    },
+                    (None, Some(consensus)) => TipReport::status(&chain, consensus, tolerance),
+                    (None, None) => TipStatus::Synced,
+                };
+                // Tip of a failed sync is only how far it got, which says
+                // nothing about the start height
+                let misreports_start_height = chain.error.is_none()
+                    && (start_height as i64 - chain.tip.height as i64).unsigned_abs()
+                        > tolerance as u64;
+                PeerTip {
+                    node,
+                    start_height,
+                    tip: chain.tip,
+                    status,
+                    misreports_start_height,
+                }
+            })
+            .collect();
+        TipReport {
+            attempted,
+            consensus: consensus.map(|chain| chain.tip),
+            peers,
+        }
+    }
+
+    /// Returns how the chain relates to the consensus chain
+    fn status(chain: &ServedChain, consensus: &ServedChain, tolerance: u32) -> TipStatus {
+        // A branch differs from the shared chain from its first header, so
+        // only branches off the same height can have headers in common
+        let common = if chain.trunk_height == consensus.trunk_height {
+            let shared = chain
+                .branch
+                .iter()
+                .zip(&consensus.branch)
+                .take_while(|(a, b)| a == b)
+                .count();
+            chain.trunk_height + shared as u32
+        } else {
+            chain.trunk_height.min(consensus.trunk_height)
+        };
+        let height = chain.tip.height;
+        let consensus_height = consensus.tip.height;
+        if common == height {
+            match consensus_height - height {
+                blocks if blocks > tolerance => TipStatus::Lagging { blocks },
+                _ => TipStatus::Synced,
+            }
+        } else if common == consensus_height {
+            TipStatus::Ahead {
+                blocks: height - consensus_height,
+            }
+        } else {
+            TipStatus::Forked {
+                fork_height: common,
+            }
+        }
+    }
+
+    /// Returns the nodes which are not synced with the consensus tip or
+    /// misreport their start height
+    pub fn flagged(&self) -> impl Iterator<Item = &PeerTip> {
+        self.peers.iter().filter(|peer| peer.is_flagged())
+    }
+
+    /// Writes the report in the selected format
+    pub fn write<W: Write>(&self, writer: &mut W, format: ReportFormat) -> anyhow::Result<()> {
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut *writer, &TipReportRecord::from(self))?;
+                writeln!(writer)?;
+            }
+            ReportFormat::Report => self.write_report(writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a readable report with the consensus tip and a row per node
+    fn write_report<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match &self.consensus {
+            Some(tip) => {
+                let agreeing = self
+                    .peers
+                    .iter()
+                    .filter(|peer| peer.tip.hash == tip.hash)
+                    .count();
+                writeln!(
+                    writer,
+                    "Consensus tip: {} at height {}, served by {} of {} nodes",
+                    tip.hash,
+                    tip.height,
+                    agreeing,
+                    self.peers.len()
+                )?;
+            }
+            None => writeln!(writer, "Consensus tip: none")?,
+        }
+        writeln!(
+            writer,
+            "Nodes: {}, serving headers: {}, flagged: {}",
+            self.attempted,
+            self.peers.len(),
+            self.flagged().count()
+        )?;
+        if self.peers.is_empty() {
+            return Ok(());
+        }
+        let mut peers: Vec<&PeerTip> = self.peers.iter().collect();
+        peers.sort_by(|a, b| {
+            b.is_flagged()
+                .cmp(&a.is_flagged())
+                .then_with(|| a.node.to_string().cmp(&b.node.to_string()))
+        });
+        let rows: Vec<Vec<String>> = peers
+            .iter()
+            .map(|peer| {
+                let mut status = peer.status.to_string();
+                if peer.misreports_start_height {
+                    status.push_str(", misreports start height");
+                }
+                vec![
+                    peer.node.to_string(),
+                    peer.start_height.to_string(),
+                    peer.tip.height.to_string(),
+                    peer.tip.hash.to_string(),
+                    status,
+                ]
+            })
+            .collect();
+        writeln!(writer)?;
+        write_columns(
+            writer,
+            &["NODE", "START HEIGHT", "HEIGHT", "HASH", "STATUS"],
+            &rows,
+        )
+    }
+}
+
+/// Serializable form of a [TipReport]
+#[derive(Serialize)]
+struct TipReportRecord {
+    attempted: usize,
+    consensus_height: Option<u32>,
+    consensus_hash: Option<String>,
+    peers: Vec<PeerTipRecord>,
+}
+
+/// Serializable form of a [PeerTip]
+#[derive(Serialize)]
+struct PeerTipRecord {
+    node: String,
+    start_height: i32,
+    height: u32,
+    hash: String,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fork_height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    misreports_start_height: bool,
+}
+
+impl From<&TipReport> for TipReportRecord {
+    fn from(report: &TipReport) -> Self {
+        TipReportRecord {
+            attempted: report.attempted,
+            consensus_height: report.consensus.map(|tip| tip.height),
+            consensus_hash: report.consensus.map(|tip| tip.hash.to_string()),
+            peers: report
+                .peers
+                .iter()
+                .map(|peer| {
+                    let (blocks, fork_height, error) = match &peer.status {
+                        TipStatus::Lagging { blocks } | TipStatus::Ahead { blocks } => {
+                            (Some(*blocks), None, None)
+                        }
+                        TipStatus::Forked { fork_height } => (None, Some(*fork_height), None),
+                        TipStatus::Failed { error } => (None, None, Some(error.clone())),
+                        TipStatus::Synced => (None, None, None),
+                    };
+                    PeerTipRecord {
+                        node: peer.node.to_string(),
+                        start_height: peer.start_height,
+                        height: peer.tip.height,
+                        hash: peer.tip.hash.to_string(),
+                        status: peer.status.name(),
+                        blocks,
+                        fork_height,
+                        error,
+                        misreports_start_height: peer.misreports_start_height,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
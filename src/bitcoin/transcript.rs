@@ -0,0 +1,435 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    future::Future,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use bitcoin::hex::{DisplayHex, FromHex};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+use crate::bitcoin::{connection::Tap, peer_address::PeerAddress};
+
+/// Direction of the bytes of a transcript entry, seen from the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    /// Bytes written by the client
+    Sent,
+    /// Bytes read by the client
+    Received,
+    /// The node closed the connection
+    Closed,
+}
+
+/// Single event of a session, stored as one JSON object per line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub direction: Direction,
+    /// Time of the event since the start of the session in microseconds
+    pub time_us: u64,
+    /// Bytes exchanged, in hex
+    pub data: String,
+}
+
+impl TranscriptEntry {
+    /// Returns the bytes exchanged
+    pub fn bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Vec::<u8>::from_hex(&self.data).context("Transcript data is not valid hex")
+    }
+}
+
+/// Transcript of a complete session with a node, in the order in which
+/// the bytes were exchanged
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::transcript::{Direction, Transcript};
+///
+/// let transcript = Transcript::parse(
+///     "{\"direction\":\"sent\",\"time_us\":0,\"data\":\"f9beb4d9\"}\n\
+///      {\"direction\":\"closed\",\"time_us\":1500,\"data\":\"\"}\n",
+/// ).unwrap();
+/// assert_eq!(transcript.entries.len(), 2);
+/// assert_eq!(transcript.entries[1].direction, Direction::Closed);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    /// Reads a transcript from a file
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Transcript> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read transcript {}", path.display()))?;
+        Transcript::parse(&content)
+            .with_context(|| format!("Failed to parse transcript {}", path.display()))
+    }
+
+    /// Parses a transcript with one JSON entry per line
+    pub fn parse(content: &str) -> anyhow::Result<Transcript> {
+        let mut entries = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: TranscriptEntry = serde_json::from_str(line)
+                .with_context(|| format!("Invalid entry on line {}", number + 1))?;
+            entry
+                .bytes()
+                .with_context(|| format!("Invalid entry on line {}", number + 1))?;
+            entries.push(entry);
+        }
+        Ok(Transcript { entries })
+    }
+
+    /// Writes the transcript with one JSON entry per line
+    pub fn write<W: Write>(&self, writer: &mut W) -> anyhow::Result<()> {
+        for entry in &self.entries {
+            serde_json::to_writer(&mut *writer, entry)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns all bytes in the direction, concatenated
+    pub fn bytes(&self, direction: Direction) -> Vec<u8> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.direction == direction)
+            .flat_map(|entry| entry.bytes().unwrap_or_default())
+            .collect()
+    }
+}
+
+/// Recorder of the sessions with the nodes into transcript files in a
+/// directory, one file per connection named `<address>.<n>.ndjson`, where
+/// `n` counts the connections to the node from 1.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::transcript::SessionRecorder;
+///
+/// let directory = std::env::temp_dir().join("p2p_handshake_bitcoin_doc_sessions");
+/// let recorder = SessionRecorder::new(&directory).unwrap();
+/// let node = "127.0.0.1:8333".parse().unwrap();
+/// let transcript = recorder.open(&node).unwrap();
+/// assert!(directory.join("127.0.0.1_8333.1.ndjson").exists());
+/// ```
+pub struct SessionRecorder {
+    directory: PathBuf,
+    connections: Mutex<HashMap<PeerAddress, u32>>,
+}
+
+impl SessionRecorder {
+    /// Creates a recorder writing into the directory, creating the
+    /// directory if needed
+    pub fn new(directory: impl AsRef<Path>) -> anyhow::Result<SessionRecorder> {
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+        Ok(SessionRecorder {
+            directory: directory.to_path_buf(),
+            connections: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the name of the transcript file of the connection to the node
+    pub fn file_name(node: &PeerAddress, connection: u32) -> String {
+        format!("{}.{}.ndjson", node.file_stem(), connection)
+    }
+
+    /// Starts recording a new connection to the node
+    pub fn open(&self, node: &PeerAddress) -> anyhow::Result<TranscriptWriter> {
+        let connection = {
+            let mut connections = self
+                .connections
+                .lock()
+                .expect("Session recorder lock is poisoned");
+            let connection = connections.entry(node.clone()).or_insert(0);
+            *connection += 1;
+            *connection
+        };
+        let path = self
+            .directory
+            .join(SessionRecorder::file_name(node, connection));
+        let file =
+            File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(TranscriptWriter {
+            writer: Some(BufWriter::new(file)),
+            path,
+            start: Instant::now(),
+        })
+    }
+}
+
+/// Tap which appends every event of a connection to a transcript file as
+/// it happens, so the transcript survives a crash. Errors writing the file
+/// are logged once, after which the connection is not recorded.
+pub struct TranscriptWriter {
+    writer: Option<BufWriter<File>>,
+    path: PathBuf,
+    start: Instant,
+}
+
+impl TranscriptWriter {
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        let entry = TranscriptEntry {
+            direction,
+            time_us: self.start.elapsed().as_micros() as u64,
+            data: data.to_lower_hex_string(),
+        };
+        let written = serde_json::to_writer(&mut *writer, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            tracing::warn!(
+                error.message = %e,
+                "Failed to record session into {}",
+                self.path.display()
+            );
+            self.writer = None;
+        }
+    }
+}
+
+impl Tap for TranscriptWriter {
+    fn sent(&mut self, data: &[u8]) {
+        self.record(Direction::Sent, data);
+    }
+
+    fn received(&mut self, data: &[u8]) {
+        self.record(Direction::Received, data);
+    }
+
+    fn closed(&mut self) {
+        self.record(Direction::Closed, &[]);
+    }
+}
+
+/// Bytes the node sends, once the client has sent what came before them
+#[derive(Debug)]
+struct Reply {
+    /// Number of bytes the client sent before the reply in the transcript
+    after_sent: usize,
+    time: Duration,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    replies: VecDeque<Reply>,
+    closes: bool,
+    written: Vec<u8>,
+    reader: Option<Waker>,
+}
+
+/// Fake node which replays a transcript to a client, through reading and
+/// writing halves like the ones of a [Stream].
+/// The replies of the node are read in the recorded chunks, each one once
+/// the client has written as many bytes as it had before the reply. What
+/// the client writes is not compared with the transcript, as messages such
+/// as version carry a random nonce, but it is kept for inspection. After
+/// the last reply the node closes the connection if it did so in the
+/// transcript, otherwise it stays silent.
+///
+/// [Stream]: crate::bitcoin::stream::Stream
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::client::BitcoinClient;
+/// use p2p_handshake_bitcoin::bitcoin::transcript::{ReplayPeer, Transcript};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let transcript = Transcript::read("tests/transcripts/handshake.ndjson").unwrap();
+///     let peer = ReplayPeer::new(&transcript).unwrap();
+///     let progress = peer.progress();
+///     let mut client = BitcoinClient::new(peer.rx, peer.tx);
+///     client.handshake().await.unwrap();
+///     assert!(progress.is_finished());
+/// }
+/// ```
+pub struct ReplayPeer {
+    pub rx: ReplayReader,
+    pub tx: ReplayWriter,
+}
+
+impl ReplayPeer {
+    /// Creates a fake node which replies as fast as the client reads
+    pub fn new(transcript: &Transcript) -> anyhow::Result<ReplayPeer> {
+        let mut replies = VecDeque::new();
+        let mut sent = 0;
+        let mut closes = false;
+        for entry in &transcript.entries {
+            let data = entry.bytes()?;
+            match entry.direction {
+                Direction::Sent => sent += data.len(),
+                Direction::Received => replies.push_back(Reply {
+                    after_sent: sent,
+                    time: Duration::from_micros(entry.time_us),
+                    data,
+                }),
+                Direction::Closed => closes = true,
+            }
+        }
+        let state = Arc::new(Mutex::new(ReplayState {
+            replies,
+            closes,
+            written: Vec::new(),
+            reader: None,
+        }));
+        Ok(ReplayPeer {
+            rx: ReplayReader {
+                state: state.clone(),
+                realtime: false,
+                start: None,
+                delay: None,
+            },
+            tx: ReplayWriter { state },
+        })
+    }
+
+    /// Makes the node send every reply no earlier than it did in the
+    /// transcript, measured from the first read
+    pub fn realtime(mut self) -> ReplayPeer {
+        self.rx.realtime = true;
+        self
+    }
+
+    /// Returns a handle to follow the replay once the halves are in use
+    pub fn progress(&self) -> ReplayProgress {
+        ReplayProgress {
+            state: self.rx.state.clone(),
+        }
+    }
+}
+
+/// Handle to follow a replay, see [ReplayPeer::progress]
+#[derive(Debug, Clone)]
+pub struct ReplayProgress {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl ReplayProgress {
+    /// Returns all bytes the client wrote so far
+    pub fn written(&self) -> Vec<u8> {
+        self.state
+            .lock()
+            .expect("Replay lock is poisoned")
+            .written
+            .clone()
+    }
+
+    /// Returns whether the client read all replies
+    pub fn is_finished(&self) -> bool {
+        self.state
+            .lock()
+            .expect("Replay lock is poisoned")
+            .replies
+            .is_empty()
+    }
+}
+
+/// Reading half of a [ReplayPeer]
+#[derive(Debug)]
+pub struct ReplayReader {
+    state: Arc<Mutex<ReplayState>>,
+    /// Whether replies are sent no earlier than in the transcript
+    realtime: bool,
+    /// Start of the replay, set on the first read
+    start: Option<Instant>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl AsyncRead for ReplayReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let start = *this.start.get_or_insert_with(Instant::now);
+        let mut guard = this.state.lock().expect("Replay lock is poisoned");
+        let state = &mut *guard;
+        let Some(reply) = state.replies.front_mut() else {
+            if state.closes {
+                return Poll::Ready(Ok(()));
+            }
+            // The node stays silent, until the client gives up
+            return Poll::Pending;
+        };
+        if state.written.len() < reply.after_sent {
+            state.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        if this.realtime {
+            let due = start + reply.time;
+            if Instant::now() < due {
+                let delay = this
+                    .delay
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(due.into())));
+                if delay.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+            this.delay = None;
+        }
+        let count = reply.data.len().min(buf.remaining());
+        buf.put_slice(&reply.data[..count]);
+        reply.data.drain(..count);
+        if reply.data.is_empty() {
+            state.replies.pop_front();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writing half of a [ReplayPeer]
+#[derive(Debug)]
+pub struct ReplayWriter {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl AsyncWrite for ReplayWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut state = self.state.lock().expect("Replay lock is poisoned");
+        state.written.extend_from_slice(buf);
+        if let Some(reader) = state.reader.take() {
+            reader.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,221 @@
+use bitcoin::{
+    consensus::deserialize_partial,
+    hashes::{sha256d, Hash},
+    hex::DisplayHex,
+    p2p::message::{NetworkMessage, RawNetworkMessage},
+};
+
+/// Length of the header that precedes the payload of every message
+pub const HEADER_LEN: usize = 24;
+
+/// Maximum number of bytes of a frame included in its hex dump
+pub const DUMP_LIMIT: usize = 128;
+
+/// Direction in which a frame travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDirection {
+    /// Written to the node
+    Sent,
+    /// Read from the node
+    Received,
+}
+
+impl FrameDirection {
+    /// Returns the name of the direction, as used in logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameDirection::Sent => "sent",
+            FrameDirection::Received => "received",
+        }
+    }
+}
+
+/// Header of a message as it appeared on the wire, before any validation
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::consensus::serialize;
+/// use p2p_handshake_bitcoin::bitcoin::{message::BitcoinMessage, wire::FrameHeader};
+///
+/// let frame = serialize(&BitcoinMessage::verack_message());
+/// let header = FrameHeader::parse(&frame).unwrap();
+/// assert_eq!(header.command, "verack");
+/// assert_eq!(header.length, 0);
+/// assert!(header.checksum_matches(&frame[24..]));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// Magic bytes of the network
+    pub magic: [u8; 4],
+    /// Command name, with unprintable characters replaced by `?`
+    pub command: String,
+    /// Announced length of the payload
+    pub length: u32,
+    /// Announced checksum of the payload
+    pub checksum: [u8; 4],
+}
+
+impl FrameHeader {
+    /// Parses the header at the start of the bytes, if they are long enough
+    pub fn parse(data: &[u8]) -> Option<FrameHeader> {
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        let command = data[4..16]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '?' })
+            .collect();
+        Some(FrameHeader {
+            magic: data[0..4].try_into().ok()?,
+            command,
+            length: u32::from_le_bytes(data[16..20].try_into().ok()?),
+            checksum: data[20..24].try_into().ok()?,
+        })
+    }
+
+    /// Returns whether the checksum of the header matches the payload
+    pub fn checksum_matches(&self, payload: &[u8]) -> bool {
+        sha256d::Hash::hash(payload).as_byte_array()[..4] == self.checksum
+    }
+}
+
+/// Renders at most `limit` bytes as hex, noting how many bytes were left out
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::wire::hex_dump;
+///
+/// assert_eq!(hex_dump(&[0xf9, 0xbe, 0xb4, 0xd9], 8), "f9beb4d9");
+/// assert_eq!(hex_dump(&[0xf9, 0xbe, 0xb4, 0xd9], 2), "f9be (+2 bytes)");
+/// ```
+pub fn hex_dump(data: &[u8], limit: usize) -> String {
+    if data.len() <= limit {
+        data.to_lower_hex_string()
+    } else {
+        format!(
+            "{} (+{} bytes)",
+            data[..limit].to_lower_hex_string(),
+            data.len() - limit
+        )
+    }
+}
+
+/// Describes the content of a message in a single line
+///
+/// #Example
+///
+/// ```
+/// use bitcoin::p2p::message::NetworkMessage;
+/// use p2p_handshake_bitcoin::bitcoin::wire::summary;
+///
+/// assert_eq!(summary(&NetworkMessage::Ping(7)), "nonce=7");
+/// assert_eq!(summary(&NetworkMessage::Verack), "");
+/// ```
+pub fn summary(message: &NetworkMessage) -> String {
+    match message {
+        NetworkMessage::Version(version) => format!(
+            "version={} services={} user_agent={:?} start_height={} relay={} nonce={}",
+            version.version,
+            version.services,
+            version.user_agent,
+            version.start_height,
+            version.relay,
+            version.nonce
+        ),
+        NetworkMessage::Ping(nonce) | NetworkMessage::Pong(nonce) => format!("nonce={}", nonce),
+        NetworkMessage::Addr(addresses) => format!("addresses={}", addresses.len()),
+        NetworkMessage::AddrV2(addresses) => format!("addresses={}", addresses.len()),
+        NetworkMessage::Inv(inventory)
+        | NetworkMessage::GetData(inventory)
+        | NetworkMessage::NotFound(inventory) => format!("items={}", inventory.len()),
+        NetworkMessage::GetHeaders(message) => format!(
+            "locator={} stop={}",
+            message.locator_hashes.len(),
+            message.stop_hash
+        ),
+        NetworkMessage::GetBlocks(message) => format!(
+            "locator={} stop={}",
+            message.locator_hashes.len(),
+            message.stop_hash
+        ),
+        NetworkMessage::Headers(headers) => format!("headers={}", headers.len()),
+        NetworkMessage::Block(block) => format!(
+            "hash={} transactions={}",
+            block.block_hash(),
+            block.txdata.len()
+        ),
+        NetworkMessage::Tx(tx) => format!(
+            "txid={} inputs={} outputs={}",
+            tx.txid(),
+            tx.input.len(),
+            tx.output.len()
+        ),
+        NetworkMessage::FeeFilter(rate) => format!("sat_per_kvb={}", rate),
+        NetworkMessage::SendCmpct(message) => {
+            format!(
+                "announce={} version={}",
+                message.send_compact, message.version
+            )
+        }
+        NetworkMessage::Reject(reject) => format!(
+            "message={} code={:?} reason={:?}",
+            reject.message, reject.ccode, reject.reason
+        ),
+        NetworkMessage::Unknown { command, payload } => {
+            format!("unknown command={} payload={}", command, payload.len())
+        }
+        _ => String::new(),
+    }
+}
+
+/// Emits a trace event for every complete frame in the bytes, and one for
+/// any trailing bytes which do not form a complete frame
+pub fn trace_frames(direction: FrameDirection, data: &[u8]) {
+    if !tracing::enabled!(tracing::Level::TRACE) {
+        return;
+    }
+    let mut rest = data;
+    while !rest.is_empty() {
+        let len = FrameHeader::parse(rest)
+            .map(|header| HEADER_LEN + header.length as usize)
+            .filter(|&len| len <= rest.len())
+            .unwrap_or(rest.len());
+        trace_frame(direction, &rest[..len]);
+        rest = &rest[len..];
+    }
+}
+
+/// Emits a trace event describing a single frame
+fn trace_frame(direction: FrameDirection, frame: &[u8]) {
+    let Some(header) = FrameHeader::parse(frame) else {
+        tracing::trace!(
+            frame.direction = direction.name(),
+            frame.size = frame.len(),
+            frame.dump = %hex_dump(frame, DUMP_LIMIT),
+            "Incomplete frame {}",
+            direction.name()
+        );
+        return;
+    };
+    let payload = &frame[HEADER_LEN..];
+    let decoded = match deserialize_partial::<RawNetworkMessage>(frame) {
+        Ok((message, _)) => summary(message.payload()),
+        Err(e) => format!("undecodable: {}", e),
+    };
+    tracing::trace!(
+        frame.direction = direction.name(),
+        frame.magic = %header.magic.to_lower_hex_string(),
+        frame.command = %header.command,
+        frame.length = header.length,
+        frame.checksum = %header.checksum.to_lower_hex_string(),
+        frame.checksum_valid = header.checksum_matches(payload),
+        frame.dump = %hex_dump(frame, DUMP_LIMIT),
+        frame.summary = %decoded,
+        "Frame {} {}",
+        direction.name(),
+        header.command
+    );
+}
@@ -0,0 +1,387 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    net::IpAddr,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{
+    bitcoin::{
+        client_pool::NodeOutcome,
+        peer_address::{Host, PeerAddress},
+    },
+    output::{percentile, service_names, write_columns, ReportFormat},
+};
+
+/// Number of most common values listed per section of the report
+const REPORT_TOP: usize = 10;
+
+/// Mapping of IP prefixes to the autonomous systems announcing them
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::census::AsnMap;
+///
+/// let map = AsnMap::parse("# prefix asn\n203.0.0.0/16 64500\n203.0.113.0/24 AS64501\n").unwrap();
+/// assert_eq!(map.lookup("203.0.113.7".parse().unwrap()), Some(64501));
+/// assert_eq!(map.lookup("203.0.1.1".parse().unwrap()), Some(64500));
+/// assert_eq!(map.lookup("198.51.100.1".parse().unwrap()), None);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct AsnMap {
+    /// Prefixes by their length, with IPv4 prefixes stored as IPv4-mapped
+    /// IPv6 ones
+    prefixes: BTreeMap<u8, HashMap<u128, u32>>,
+}
+
+impl AsnMap {
+    /// Reads the map from a file, see [parse](AsnMap::parse)
+    pub fn read(path: &str) -> Result<AsnMap, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read ASN map from {}", path))?;
+        AsnMap::parse(&content).with_context(|| format!("Failed to parse ASN map from {}", path))
+    }
+
+    /// Parses the map from text with one `<prefix>/<length> <asn>` entry
+    /// per line, where the AS number may be prefixed with `AS` and
+    /// everything after `#` is a comment.
+    pub fn parse(content: &str) -> Result<AsnMap, anyhow::Error> {
+        let mut map = AsnMap::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (prefix, asn) = AsnMap::parse_entry(line)
+                .with_context(|| format!("Invalid entry on line {}: {}", number + 1, line))?;
+            map.insert(prefix, asn);
+        }
+        Ok(map)
+    }
+
+    /// Returns the AS number of the longest prefix containing the address
+    pub fn lookup(&self, ip: IpAddr) -> Option<u32> {
+        let bits = ip_bits(ip);
+        self.prefixes
+            .iter()
+            .rev()
+            .find_map(|(length, prefixes)| prefixes.get(&mask(bits, *length)).copied())
+    }
+
+    fn insert(&mut self, (ip, length): (IpAddr, u8), asn: u32) {
+        let length = match ip {
+            IpAddr::V4(_) => length + 96,
+            IpAddr::V6(_) => length,
+        };
+        self.prefixes
+            .entry(length)
+            .or_default()
+            .insert(mask(ip_bits(ip), length), asn);
+    }
+
+    fn parse_entry(line: &str) -> Result<((IpAddr, u8), u32), anyhow::Error> {
+        let mut fields = line.split_whitespace();
+        let (prefix, asn) = match (fields.next(), fields.next()) {
+            (Some(prefix), Some(asn)) => (prefix, asn),
+            _ => anyhow::bail!("Expected prefix and AS number"),
+        };
+        let (ip, length) = prefix.split_once('/').context("Prefix length is missing")?;
+        let ip: IpAddr = ip.parse()?;
+        let length: u8 = length.parse()?;
+        let max_length = if ip.is_ipv4() { 32 } else { 128 };
+        if length > max_length {
+            anyhow::bail!("Prefix length {} is too long", length);
+        }
+        let asn = asn.trim_start_matches("AS").parse()?;
+        Ok(((ip, length), asn))
+    }
+}
+
+fn ip_bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+fn mask(bits: u128, length: u8) -> u128 {
+    match length {
+        0 => 0,
+        length => bits & (u128::MAX << (128 - length.min(128) as u32)),
+    }
+}
+
+/// Spread of numeric values, using the nearest-rank percentiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Distribution {
+    pub min: i64,
+    pub p10: i64,
+    pub p50: i64,
+    pub p90: i64,
+    pub max: i64,
+}
+
+impl Distribution {
+    fn of(values: &[i64]) -> Option<Distribution> {
+        let mut sorted: Vec<f64> = values.iter().map(|&value| value as f64).collect();
+        sorted.sort_by(f64::total_cmp);
+        let p = |p| percentile(&sorted, p).map(|value| value as i64);
+        Some(Distribution {
+            min: p(0.0)?,
+            p10: p(10.0)?,
+            p50: p(50.0)?,
+            p90: p(90.0)?,
+            max: p(100.0)?,
+        })
+    }
+}
+
+/// Number of attempted and reachable nodes of one address type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct NetworkShare {
+    pub attempted: usize,
+    pub reachable: usize,
+}
+
+/// Aggregated statistics over handshake results. Apart from the address
+/// types, the statistics only cover reachable nodes.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Census {
+    pub attempted: usize,
+    pub reachable: usize,
+    /// Nodes per protocol version
+    pub protocol_versions: BTreeMap<u32, usize>,
+    /// Nodes per user agent
+    pub user_agents: BTreeMap<String, usize>,
+    /// Nodes advertising each service flag
+    pub services: BTreeMap<String, usize>,
+    pub start_height: Option<Distribution>,
+    /// Seconds the clocks of the nodes are ahead of the local one
+    pub timestamp_skew: Option<Distribution>,
+    /// Nodes per address type: ipv4, ipv6, onion or hostname
+    pub networks: BTreeMap<String, NetworkShare>,
+    /// Nodes per autonomous system, or per network group if the address
+    /// is not in the ASN map
+    pub groups: BTreeMap<String, usize>,
+}
+
+/// Collects handshake outcomes into a [Census]
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::client_pool::BitcoinClientPool;
+/// use p2p_handshake_bitcoin::census::CensusCollector;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let clients = vec!["127.0.0.1:1".parse().unwrap()];
+///     let outcomes = BitcoinClientPool::new(clients, 500).run().await.unwrap();
+///     let mut collector = CensusCollector::new(None);
+///     for outcome in &outcomes {
+///         collector.add(outcome);
+///     }
+///     let census = collector.finish();
+///     assert_eq!((census.attempted, census.reachable), (1, 0));
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct CensusCollector {
+    asn_map: Option<AsnMap>,
+    census: Census,
+    start_heights: Vec<i64>,
+    timestamp_skews: Vec<i64>,
+}
+
+impl CensusCollector {
+    /// Creates a collector which groups nodes by autonomous system if the
+    /// map is provided, and by network group otherwise
+    pub fn new(asn_map: Option<AsnMap>) -> CensusCollector {
+        CensusCollector {
+            asn_map,
+            ..CensusCollector::default()
+        }
+    }
+
+    /// Adds the outcome of the handshake with a node
+    pub fn add(&mut self, outcome: &NodeOutcome) {
+        let group = self.group(&outcome.node);
+        let census = &mut self.census;
+        census.attempted += 1;
+        let network = census
+            .networks
+            .entry(network_name(&outcome.node).to_string())
+            .or_default();
+        network.attempted += 1;
+        let Ok(version) = &outcome.result else {
+            return;
+        };
+        census.reachable += 1;
+        network.reachable += 1;
+        *census.protocol_versions.entry(version.version).or_default() += 1;
+        *census
+            .user_agents
+            .entry(version.user_agent.clone())
+            .or_default() += 1;
+        let services = version.services.to_u64();
+        for bit in 0..64 {
+            if services & (1 << bit) != 0 {
+                *census.services.entry(service_names(1 << bit)).or_default() += 1;
+            }
+        }
+        *census.groups.entry(group).or_default() += 1;
+        self.start_heights.push(version.start_height as i64);
+        if let Some(skew) = outcome.timestamp_skew() {
+            self.timestamp_skews.push(skew);
+        }
+    }
+
+    /// Returns the statistics over all added outcomes
+    pub fn finish(mut self) -> Census {
+        self.census.start_height = Distribution::of(&self.start_heights);
+        self.census.timestamp_skew = Distribution::of(&self.timestamp_skews);
+        self.census
+    }
+
+    fn group(&self, node: &PeerAddress) -> String {
+        let asn = match (&self.asn_map, node.socket_addr()) {
+            (Some(map), Some(address)) => map.lookup(address.ip()),
+            _ => None,
+        };
+        match asn {
+            Some(asn) => format!("AS{}", asn),
+            None => node.group(),
+        }
+    }
+}
+
+fn network_name(node: &PeerAddress) -> &'static str {
+    match node.host {
+        Host::Ipv4(_) => "ipv4",
+        Host::Ipv6(_) => "ipv6",
+        Host::Onion(_) => "onion",
+        Host::Hostname(_) => "hostname",
+    }
+}
+
+impl Census {
+    /// Writes the census in the selected format
+    pub fn write<W: Write>(&self, writer: &mut W, format: ReportFormat) -> anyhow::Result<()> {
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut *writer, self)?;
+                writeln!(writer)?;
+            }
+            ReportFormat::Report => self.write_report(writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a readable report, listing the most common values of each
+    /// statistic with their share of reachable nodes.
+    fn write_report<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "Nodes: {}, reachable: {}",
+            self.attempted, self.reachable
+        )?;
+
+        writeln!(writer)?;
+        let rows: Vec<Vec<String>> = self
+            .networks
+            .iter()
+            .map(|(name, share)| {
+                vec![
+                    name.clone(),
+                    share.attempted.to_string(),
+                    share.reachable.to_string(),
+                    self.share(share.reachable),
+                ]
+            })
+            .collect();
+        write_columns(writer, &["NETWORK", "NODES", "REACHABLE", "SHARE"], &rows)?;
+
+        let versions = self
+            .protocol_versions
+            .iter()
+            .map(|(version, count)| (version.to_string(), *count));
+        self.write_counts(writer, "PROTOCOL VERSION", versions)?;
+        let user_agents = self
+            .user_agents
+            .iter()
+            .map(|(user_agent, count)| (user_agent.clone(), *count));
+        self.write_counts(writer, "USER AGENT", user_agents)?;
+        let services = self
+            .services
+            .iter()
+            .map(|(service, count)| (service.clone(), *count));
+        self.write_counts(writer, "SERVICE", services)?;
+        let groups = self
+            .groups
+            .iter()
+            .map(|(group, count)| (group.clone(), *count));
+        self.write_counts(writer, "GROUP", groups)?;
+
+        let distributions = [
+            ("Start height", self.start_height, ""),
+            ("Timestamp skew", self.timestamp_skew, " s"),
+        ];
+        for (name, distribution, unit) in distributions {
+            if let Some(d) = distribution {
+                writeln!(writer)?;
+                writeln!(
+                    writer,
+                    "{}: min {}{unit}, p10 {}{unit}, p50 {}{unit}, p90 {}{unit}, max {}{unit}",
+                    name,
+                    d.min,
+                    d.p10,
+                    d.p50,
+                    d.p90,
+                    d.max,
+                    unit = unit
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the most common values, followed by the number of the rest
+    fn write_counts<W: Write>(
+        &self,
+        writer: &mut W,
+        name: &str,
+        counts: impl Iterator<Item = (String, usize)>,
+    ) -> std::io::Result<()> {
+        let mut counts: Vec<(String, usize)> = counts.collect();
+        if counts.is_empty() {
+            return Ok(());
+        }
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let mut rows: Vec<Vec<String>> = counts
+            .iter()
+            .take(REPORT_TOP)
+            .map(|(value, count)| vec![value.clone(), count.to_string(), self.share(*count)])
+            .collect();
+        if counts.len() > REPORT_TOP {
+            let rest: usize = counts[REPORT_TOP..].iter().map(|(_, count)| count).sum();
+            rows.push(vec![
+                format!("({} others)", counts.len() - REPORT_TOP),
+                rest.to_string(),
+                self.share(rest),
+            ]);
+        }
+        writeln!(writer)?;
+        write_columns(writer, &[name, "NODES", "SHARE"], &rows)
+    }
+
+    fn share(&self, count: usize) -> String {
+        match self.reachable {
+            0 => "-".to_string(),
+            reachable => format!("{:.1}%", count as f64 * 100.0 / reachable as f64),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Time a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Response to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    /// Creates a successful response with the body
+    pub fn ok(content_type: &'static str, body: String) -> Response {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    /// Creates a response for paths which are not served
+    pub fn not_found() -> Response {
+        Response {
+            status: 404,
+            content_type: "text/plain; charset=utf-8",
+            body: "Not found\n".to_string(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Bad Request",
+        }
+    }
+}
+
+/// Serves GET requests on the listener with a handler which maps the
+/// requested path to the response. It is a minimal HTTP/1.1 server meant
+/// for status endpoints, which closes the connection after every response.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::http::{serve, Response};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+///     tokio::spawn(serve(listener, |path: &str| match path {
+///         "/health" => Response::ok("text/plain", "ok\n".to_string()),
+///         _ => Response::not_found(),
+///     }));
+/// }
+/// ```
+pub async fn serve<H>(listener: TcpListener, handler: H)
+where
+    H: Fn(&str) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                tracing::warn!(error.message = %e, "Failed to accept HTTP connection");
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(socket, handler.as_ref()).await {
+                tracing::debug!(error.message = %e, "Failed to answer HTTP request");
+            }
+        });
+    }
+}
+
+async fn respond<H>(socket: TcpStream, handler: &H) -> std::io::Result<()>
+where
+    H: Fn(&str) -> Response,
+{
+    let mut socket = BufReader::new(socket);
+    let mut request_line = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        socket.read_line(&mut request_line).await?;
+        // Headers are not needed, but have to be read before responding
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if socket.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+                return Ok::<(), std::io::Error>(());
+            }
+        }
+    })
+    .await??;
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => {
+            let path = target.split('?').next().unwrap_or(target);
+            handler(path)
+        }
+        (Some(_), Some(_)) => Response {
+            status: 405,
+            content_type: "text/plain; charset=utf-8",
+            body: "Method not allowed\n".to_string(),
+        },
+        _ => Response {
+            status: 400,
+            content_type: "text/plain; charset=utf-8",
+            body: "Bad request\n".to_string(),
+        },
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    let socket = socket.get_mut();
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(response.body.as_bytes()).await?;
+    socket.shutdown().await
+}
@@ -0,0 +1,136 @@
+use std::io::Read;
+
+use anyhow::Context;
+use serde_json::Value;
+
+/// Name used on the command line for the standard input
+pub const STDIN: &str = "-";
+
+/// Collects raw node addresses from positional arguments and input files.
+/// A positional argument or input file named `-` is read from the standard
+/// input, which is read at most once.
+pub fn collect_nodes(
+    positional: &[String],
+    inputs: &[String],
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut nodes = Vec::new();
+    let mut stdin_read = false;
+    for node in positional {
+        if node == STDIN {
+            if !stdin_read {
+                nodes.extend(read_nodes(STDIN)?);
+                stdin_read = true;
+            }
+        } else {
+            nodes.push(node.clone());
+        }
+    }
+    for input in inputs {
+        if input == STDIN {
+            if stdin_read {
+                continue;
+            }
+            stdin_read = true;
+        }
+        nodes.extend(read_nodes(input)?);
+    }
+    Ok(nodes)
+}
+
+/// Reads node addresses from a file, or from the standard input if the path
+/// is `-`.
+pub fn read_nodes(path: &str) -> Result<Vec<String>, anyhow::Error> {
+    let content = if path == STDIN {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read nodes from standard input")?;
+        content
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read nodes from {}", path))?
+    };
+    parse_nodes(&content).with_context(|| format!("Failed to parse nodes from {}", path))
+}
+
+/// Parses node addresses from text. The text is either a list with one
+/// address per line, where everything after `#` is a comment, or the JSON
+/// output of Bitcoin Core's `getnodeaddresses` or `getpeerinfo` RPC.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::input::parse_nodes;
+///
+/// let nodes = parse_nodes("# my nodes\n45.9.148.241:8333\n\n[::1] # local\n").unwrap();
+/// assert_eq!(nodes, vec!["45.9.148.241:8333", "[::1]"]);
+///
+/// let nodes = parse_nodes(r#"[{"address": "::1", "port": 8333, "network": "ipv6"}]"#).unwrap();
+/// assert_eq!(nodes, vec!["[::1]:8333"]);
+/// ```
+pub fn parse_nodes(content: &str) -> Result<Vec<String>, anyhow::Error> {
+    let content_start = content.trim_start();
+    // Lists may start with a bracketed IPv6 address, while JSON arrays of
+    // nodes hold objects or strings
+    let json = match content_start.strip_prefix('[') {
+        Some(rest) => matches!(rest.trim_start().chars().next(), Some('{' | '"' | ']')),
+        None => content_start.starts_with('{'),
+    };
+    if json {
+        parse_json_nodes(content)
+    } else {
+        Ok(parse_line_nodes(content))
+    }
+}
+
+fn parse_line_nodes(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_json_nodes(content: &str) -> Result<Vec<String>, anyhow::Error> {
+    let value: Value = serde_json::from_str(content).context("Invalid JSON")?;
+    // Output of bitcoin-cli is the bare result, raw RPC responses wrap it
+    let value = match value {
+        Value::Object(mut object) => object
+            .remove("result")
+            .context("JSON object has no result field")?,
+        value => value,
+    };
+    let entries = match value {
+        Value::Array(entries) => entries,
+        _ => anyhow::bail!("Expected a JSON array of nodes"),
+    };
+    entries.iter().map(parse_json_node).collect()
+}
+
+fn parse_json_node(entry: &Value) -> Result<String, anyhow::Error> {
+    match entry {
+        Value::String(address) => Ok(address.clone()),
+        // getpeerinfo entry
+        Value::Object(object) if object.contains_key("addr") => object["addr"]
+            .as_str()
+            .map(str::to_string)
+            .context("Field addr is not a string"),
+        // getnodeaddresses entry
+        Value::Object(object) if object.contains_key("address") => {
+            let address = object["address"]
+                .as_str()
+                .context("Field address is not a string")?;
+            let address = if address.contains(':') {
+                format!("[{}]", address)
+            } else {
+                address.to_string()
+            };
+            match object.get("port").and_then(Value::as_u64) {
+                Some(port) => Ok(format!("{}:{}", address, port)),
+                None => Ok(address),
+            }
+        }
+        _ => anyhow::bail!("Unsupported JSON node entry: {}", entry),
+    }
+}
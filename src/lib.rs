@@ -1,6 +1,20 @@
 /// Main modules used for communicating with bitcoin nodes
 pub mod bitcoin;
+/// Module used to aggregate handshake results into network statistics
+pub mod census;
+/// Module used to serve status endpoints over HTTP
+pub mod http;
+/// Module used to read node addresses from files and standard input
+pub mod input;
+/// Module used to export Prometheus metrics of the handshakes
+pub mod metrics;
+/// Module used to watch nodes with periodic handshakes
+pub mod monitor;
+/// Module used to write handshake results in machine-readable formats
+pub mod output;
 /// Module used for argument parsing
 pub mod parser_arguments;
+/// Module used to persist reachability history of peers
+pub mod peer_store;
 /// Module used to log messages
 pub mod telemetry;
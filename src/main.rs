@@ -1,22 +1,474 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use bitcoin::{consensus::Params, Transaction};
 use clap::Parser;
+use tokio::net::TcpListener;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
+#[cfg(feature = "otlp")]
+use p2p_handshake_bitcoin::telemetry::otlp_layer;
 use p2p_handshake_bitcoin::{
-    bitcoin::client_pool::BitcoinClientPool,
-    parser_arguments::Arguments,
-    telemetry::{get_subscriber, init_subscriber},
+    bitcoin::broadcast::{parse_transaction, BroadcastConfig, TxBroadcast},
+    bitcoin::capture::PcapCapture,
+    bitcoin::client::BitcoinClient,
+    bitcoin::client_pool::{BitcoinClientPool, NodeOutcome, PoolConfig},
+    bitcoin::crawler::{CrawlConfig, CrawlReport, Crawler},
+    bitcoin::headers::HeaderChain,
+    bitcoin::mempool::{MempoolConfig, MempoolWatcher},
+    bitcoin::peer_address::PeerAddress,
+    bitcoin::propagation::{PropagationConfig, PropagationMonitor},
+    bitcoin::retry::RetryPolicy,
+    bitcoin::seed::{resolve_seeds, SystemResolver},
+    bitcoin::tips::{TipConfig, TipSurvey},
+    bitcoin::transcript::{ReplayPeer, SessionRecorder, Transcript},
+    census::{AsnMap, CensusCollector},
+    input::collect_nodes,
+    metrics::Metrics,
+    monitor::{serve_status, Monitor, MonitorConfig},
+    output::{NodeRecord, OutputWriter},
+    parser_arguments::{
+        Arguments, BroadcastArguments, Command, MempoolArguments, MonitorArguments, PeersArguments,
+        PropagationArguments, ReplayArguments,
+    },
+    peer_store::{export, PeerStore},
+    telemetry::{get_subscriber_with_layer, init_subscriber, log_file_writer},
 };
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Arguments::parse();
 
-    let subscriber = get_subscriber(
+    // Standard output is reserved for the results, if they are requested
+    let results_on_stdout = args.output.is_some()
+        || args.census.is_some()
+        || args.tips.is_some()
+        || matches!(
+            args.command,
+            Some(Command::Peers(_))
+                | Some(Command::Broadcast(_))
+                | Some(Command::Mempool(_))
+                | Some(Command::Propagation(_))
+        );
+    let sink = match &args.log_file {
+        Some(path) => BoxMakeWriter::new(log_file_writer(
+            path,
+            args.log_rotation,
+            args.log_max_files,
+        )?),
+        None if args.log_stderr || results_on_stdout => BoxMakeWriter::new(std::io::stderr),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+    let log_level = match args.verbose {
+        0 => args.log_level.clone(),
+        1 => "debug".to_string(),
+        _ => "trace".to_string(),
+    };
+    #[cfg(feature = "otlp")]
+    let (otlp, provider) = match &args.otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, provider) = otlp_layer("p2p_handshake_bitcoin".into(), endpoint)?;
+            (Some(layer), Some(provider))
+        }
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otlp"))]
+    let otlp = None::<tracing_subscriber::layer::Identity>;
+    let subscriber = get_subscriber_with_layer(
         "p2p_handshake_bitcoin".into(),
-        "info".into(),
-        std::io::stdout,
+        log_level,
+        args.log_format,
+        sink,
+        otlp,
     );
     init_subscriber(subscriber);
-    let bitcoin_client_pool = BitcoinClientPool::new(args.ip_nodes, args.timeout);
-    bitcoin_client_pool.run().await?;
+
+    let result = run(args).await;
+    // Sends the spans which are still buffered
+    #[cfg(feature = "otlp")]
+    if let Some(provider) = provider {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to export the remaining spans: {}", e);
+        }
+    }
+    result
+}
+
+/// Handshakes with the nodes, or runs the command, as the arguments request
+async fn run(args: Arguments) -> anyhow::Result<()> {
+    if let Some(Command::Peers(peers_args)) = &args.command {
+        return list_peers(&args, peers_args);
+    }
+    if let Some(Command::Replay(replay_args)) = &args.command {
+        return replay(&args, replay_args).await;
+    }
+    // Transaction is checked before connecting to any node
+    let transaction = match &args.command {
+        Some(Command::Broadcast(broadcast_args)) => Some(read_transaction(broadcast_args)?),
+        _ => None,
+    };
+    let peer_store = match &args.peer_db {
+        Some(path) => Some(PeerStore::open(path)?),
+        None => None,
+    };
+    let nodes = collect_peers(&args, peer_store.as_ref()).await?;
+    let capture = match (&args.pcap_dir, &args.pcap_file) {
+        (Some(directory), _) => Some(Arc::new(PcapCapture::per_node(directory)?)),
+        (None, Some(path)) => Some(Arc::new(PcapCapture::merged(path)?)),
+        (None, None) => None,
+    };
+    let recorder = match &args.record_dir {
+        Some(directory) => Some(Arc::new(SessionRecorder::new(directory)?)),
+        None => None,
+    };
+    let config = PoolConfig {
+        timeout: args.timeout,
+        retry_policy: RetryPolicy {
+            max_attempts: args.max_attempts,
+            base_delay: Duration::from_millis(args.retry_delay),
+            max_delay: Duration::from_millis(args.max_retry_delay),
+            jitter: args.retry_jitter,
+            retryable: args.retry_on.clone(),
+        },
+        network: args.network,
+        session: None,
+        peer_store: peer_store.map(|store| Arc::new(Mutex::new(store))),
+        metrics: None,
+        capture,
+        recorder,
+        relay: false,
+    };
+    if let Some(Command::Monitor(monitor_args)) = &args.command {
+        return run_monitor(nodes, config, monitor_args).await;
+    }
+    if let (Some(Command::Broadcast(broadcast_args)), Some(transaction)) =
+        (&args.command, transaction)
+    {
+        return broadcast(nodes, config, transaction, broadcast_args).await;
+    }
+    if let Some(Command::Mempool(mempool_args)) = &args.command {
+        return watch_mempool(nodes, config, mempool_args).await;
+    }
+    if let Some(Command::Propagation(propagation_args)) = &args.command {
+        return measure_propagation(nodes, config, propagation_args).await;
+    }
+
+    let mut writer = args
+        .output
+        .map(|format| OutputWriter::new(format, std::io::stdout()));
+    let mut census = match (args.census, &args.asn_map) {
+        (Some(_), Some(path)) => Some(CensusCollector::new(Some(AsnMap::read(path)?))),
+        (Some(_), None) => Some(CensusCollector::new(None)),
+        (None, _) => None,
+    };
+    let mut record = |outcome: &NodeOutcome| -> anyhow::Result<()> {
+        if let Some(writer) = writer.as_mut() {
+            writer.write(NodeRecord::from(outcome))?;
+        }
+        if let Some(census) = census.as_mut() {
+            census.add(outcome);
+        }
+        Ok(())
+    };
+    if args.crawl {
+        let crawl_config = CrawlConfig {
+            max_depth: args.crawl_depth,
+            max_nodes: args.crawl_max_nodes,
+            max_concurrent: args.crawl_concurrency,
+            connections_per_second: args.crawl_rate,
+            addr_timeout: Duration::from_millis(args.addr_timeout),
+        };
+        let report = Crawler::new(crawl_config, config)
+            .run(nodes, &mut record)
+            .await?;
+        tracing::info!(
+            attempted = report.attempted,
+            discovered = report.discovered,
+            "Crawl finished with {} reachable Nodes",
+            report.reachable.len()
+        );
+        if let Some(path) = &args.reachable_output {
+            write_reachable(path, &report)?;
+        }
+    } else if let Some(format) = args.tips {
+        let tip_config = TipConfig {
+            headers_timeout: Duration::from_millis(args.headers_timeout),
+            tolerance: args.tip_tolerance,
+        };
+        let chain = match args.tip_checkpoint {
+            Some(checkpoint) => {
+                HeaderChain::from_checkpoint(Params::new(args.network), checkpoint)?
+            }
+            None => HeaderChain::new(args.network),
+        };
+        let report = TipSurvey::with_chain(tip_config, config, chain)
+            .run(nodes, &mut record)
+            .await?;
+        if let Some(tip) = &report.consensus {
+            tracing::info!(
+                flagged = report.flagged().count(),
+                "Consensus tip is {} at height {}",
+                tip.hash,
+                tip.height
+            );
+        }
+        report.write(&mut std::io::stdout().lock(), format)?;
+    } else {
+        let mut bitcoin_client_pool = BitcoinClientPool::with_config(nodes, config);
+        while let Some(outcome) = bitcoin_client_pool.next_outcome().await {
+            record(&outcome)?;
+        }
+    }
+    if let Some(writer) = writer {
+        writer.finish()?;
+    }
+    if let (Some(census), Some(format)) = (census, args.census) {
+        census
+            .finish()
+            .write(&mut std::io::stdout().lock(), format)?;
+    }
+    Ok(())
+}
+
+/// Collects the valid node addresses from the arguments, the peer database
+/// and the DNS seeds
+async fn collect_peers(
+    args: &Arguments,
+    peer_store: Option<&PeerStore>,
+) -> anyhow::Result<Vec<PeerAddress>> {
+    let nodes = collect_nodes(&args.ip_nodes, &args.input)?;
+    let mut nodes = PeerAddress::parse_all(nodes, args.network);
+    for (input, e) in &nodes.invalid {
+        tracing::error!(error.message = %e, "Invalid Node address {}", input);
+    }
+    let mut extra_nodes = Vec::new();
+    if let (Some(n), Some(store)) = (args.best, peer_store) {
+        extra_nodes.extend(store.best(n));
+    }
+    if args.seed {
+        extra_nodes.extend(resolve_seeds(&SystemResolver, args.network, args.seed_services).await);
+    }
+    for node in extra_nodes {
+        if !nodes.valid.contains(&node) {
+            nodes.valid.push(node);
+        }
+    }
+    if nodes.valid.is_empty() {
+        anyhow::bail!("No valid Node addresses provided");
+    }
+    Ok(nodes.valid)
+}
+
+/// Monitors the nodes until the process is interrupted
+async fn run_monitor(
+    nodes: Vec<PeerAddress>,
+    mut config: PoolConfig,
+    monitor_args: &MonitorArguments,
+) -> anyhow::Result<()> {
+    let metrics = Arc::new(Metrics::new());
+    config.metrics = Some(metrics.clone());
+    let monitor_config = MonitorConfig {
+        interval: Duration::from_secs(monitor_args.interval),
+        down_after: monitor_args.down_after,
+    };
+    let monitor = Monitor::new(nodes, monitor_config, config);
+    if let Some(address) = monitor_args.listen {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        tracing::info!(
+            "Serving status of the Nodes on http://{0}/status and metrics on http://{0}/metrics",
+            address
+        );
+        tokio::spawn(serve_status(listener, monitor.status(), metrics));
+    }
+    tokio::select! {
+        _ = monitor.run() => {}
+        result = tokio::signal::ctrl_c() => {
+            result.context("Failed to listen for the interrupt signal")?;
+            tracing::info!("Monitor stopped");
+        }
+    }
+    Ok(())
+}
+
+/// Returns the transaction to broadcast, read from the argument or from
+/// standard input
+fn read_transaction(broadcast_args: &BroadcastArguments) -> anyhow::Result<Transaction> {
+    let hex = match broadcast_args.transaction.as_str() {
+        "-" => std::io::read_to_string(std::io::stdin())
+            .context("Failed to read the transaction from standard input")?,
+        hex => hex.to_string(),
+    };
+    parse_transaction(&hex)
+}
+
+/// Announces the transaction to the nodes and writes which of them
+/// requested it to the standard output
+async fn broadcast(
+    nodes: Vec<PeerAddress>,
+    config: PoolConfig,
+    transaction: Transaction,
+    broadcast_args: &BroadcastArguments,
+) -> anyhow::Result<()> {
+    let broadcast_config = BroadcastConfig {
+        peers: broadcast_args.peers,
+        wait: Duration::from_millis(broadcast_args.wait),
+        collect_rejects: broadcast_args.rejects,
+    };
+    let report = TxBroadcast::new(broadcast_config, config, transaction)
+        .run(nodes, |_| Ok(()))
+        .await?;
+    tracing::info!(
+        announced = report.peers.len(),
+        rejected = report.rejected().count(),
+        "Transaction {} was requested by {} Nodes",
+        report.txid,
+        report.requested().count()
+    );
+    report.write(&mut std::io::stdout().lock(), broadcast_args.format)
+}
+
+/// Writes the transactions relayed by the nodes to the standard output,
+/// one JSON line each, until the duration is over or the process is
+/// interrupted
+async fn watch_mempool(
+    nodes: Vec<PeerAddress>,
+    config: PoolConfig,
+    mempool_args: &MempoolArguments,
+) -> anyhow::Result<()> {
+    let mempool_config = MempoolConfig {
+        fetch_timeout: Duration::from_millis(mempool_args.fetch_timeout),
+        ..MempoolConfig::default()
+    };
+    let mut subscription = MempoolWatcher::new(mempool_config, config).subscribe(nodes);
+    let deadline = match mempool_args.duration {
+        0 => None,
+        seconds => Some(tokio::time::Instant::now() + Duration::from_secs(seconds)),
+    };
+    let mut observed = 0;
+    let mut stdout = std::io::stdout();
+    loop {
+        let next = async {
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, subscription.next())
+                    .await
+                    .ok()
+                    .flatten(),
+                None => subscription.next().await,
+            }
+        };
+        let transaction = tokio::select! {
+            transaction = next => transaction,
+            result = tokio::signal::ctrl_c() => {
+                result.context("Failed to listen for the interrupt signal")?;
+                None
+            }
+        };
+        let Some(transaction) = transaction else {
+            break;
+        };
+        writeln!(stdout, "{}", transaction.to_json())?;
+        stdout.flush()?;
+        observed += 1;
+    }
+    tracing::info!("Observed {} transactions", observed);
+    Ok(())
+}
+
+/// Records the announcements of the nodes for the duration and writes
+/// their propagation delays to the standard output
+async fn measure_propagation(
+    nodes: Vec<PeerAddress>,
+    config: PoolConfig,
+    propagation_args: &PropagationArguments,
+) -> anyhow::Result<()> {
+    let propagation_config = PropagationConfig {
+        duration: Duration::from_secs(propagation_args.duration),
+        min_peers: propagation_args.min_peers,
+    };
+    let report = PropagationMonitor::new(propagation_config, config)
+        .run(nodes, |_| Ok(()))
+        .await?;
+    tracing::info!(
+        connected = report.connected,
+        blocks = report.blocks,
+        "Measured propagation of {} transactions",
+        report.transactions
+    );
+    report.write(&mut std::io::stdout().lock(), propagation_args.format)
+}
+
+/// Writes the peers from the peer database to the standard output
+fn list_peers(args: &Arguments, peers_args: &PeersArguments) -> anyhow::Result<()> {
+    let path = args
+        .peer_db
+        .as_ref()
+        .context("The peers command requires --peer-db")?;
+    let store = PeerStore::open(path)?;
+    let peers = match peers_args.best {
+        Some(n) => store
+            .best(n)
+            .iter()
+            .filter_map(|address| store.get(address))
+            .collect(),
+        None => store.peers(),
+    };
+    export(&mut std::io::stdout().lock(), &peers, peers_args.format)
+}
+
+/// Handshakes with a fake node replaying the recorded session
+async fn replay(args: &Arguments, replay_args: &ReplayArguments) -> anyhow::Result<()> {
+    let transcript = Transcript::read(&replay_args.transcript)?;
+    let mut peer = ReplayPeer::new(&transcript)?;
+    if replay_args.realtime {
+        peer = peer.realtime();
+    }
+    let progress = peer.progress();
+    let mut client = BitcoinClient::with_network(peer.rx, peer.tx, args.network);
+    let result = tokio::time::timeout(Duration::from_millis(replay_args.wait), client.handshake())
+        .await
+        .context("Replayed handshake timed out")
+        .and_then(|result| result.context("Replayed handshake failed"));
+    let version = match result {
+        Ok(version) => version,
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                replay.finished = progress.is_finished(),
+                "Replay of {} failed",
+                replay_args.transcript
+            );
+            return Err(e);
+        }
+    };
+    tracing::info!(
+        peer.version = version.version,
+        peer.user_agent = %version.user_agent,
+        replay.finished = progress.is_finished(),
+        timings = %client.timings(),
+        "Successfully performed replayed handshake of {}",
+        replay_args.transcript
+    );
+    Ok(())
+}
+
+/// Writes reachable nodes one per line, so the file can be used as input
+fn write_reachable(path: &str, report: &CrawlReport) -> anyhow::Result<()> {
+    let mut file = std::io::BufWriter::new(
+        std::fs::File::create(path).with_context(|| format!("Failed to create {}", path))?,
+    );
+    for node in &report.reachable {
+        writeln!(
+            file,
+            "{} # depth {}, {}",
+            node.node, node.depth, node.version.user_agent
+        )?;
+    }
+    file.flush()?;
     Ok(())
 }
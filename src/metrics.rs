@@ -0,0 +1,189 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use crate::bitcoin::client_pool::NodeOutcome;
+
+/// Prefix of the names of all metrics
+const NAMESPACE: &str = "p2p_handshake";
+/// Upper bounds of the latency histogram buckets in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Prometheus metrics of the handshakes, kept in their own registry
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::metrics::Metrics;
+///
+/// let metrics = Metrics::new();
+/// metrics.handshakes_attempted.inc();
+/// assert!(metrics.encode().contains("p2p_handshake_attempts_total 1"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Handshake attempts, including repeated ones
+    pub handshakes_attempted: IntCounter,
+    /// Handshake attempts which succeeded
+    pub handshakes_succeeded: IntCounter,
+    /// Handshake attempts which failed, by error kind
+    pub handshakes_failed: IntCounterVec,
+    /// Time to establish the TCP connection
+    pub connect_duration: Histogram,
+    /// Time of successful handshakes, including the connection
+    pub handshake_duration: Histogram,
+    /// Time spent in each phase of the handshake attempts, by phase
+    pub phase_duration: HistogramVec,
+    pub bytes_sent: IntCounter,
+    pub bytes_received: IntCounter,
+    /// Connections being established or in use
+    pub active_connections: IntGauge,
+    /// Whether the monitored node is up, by node
+    pub node_up: IntGaugeVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Creates the metrics and registers them in a new registry
+    pub fn new() -> Metrics {
+        let opts = |name: &str, help: &str| Opts::new(name, help).namespace(NAMESPACE);
+        let histogram_opts = |name: &str, help: &str| {
+            HistogramOpts::new(name, help)
+                .namespace(NAMESPACE)
+                .buckets(LATENCY_BUCKETS.to_vec())
+        };
+        let metrics = Metrics {
+            registry: Registry::new(),
+            handshakes_attempted: IntCounter::with_opts(opts(
+                "attempts_total",
+                "Handshake attempts, including repeated ones",
+            ))
+            .expect("Metric options are valid"),
+            handshakes_succeeded: IntCounter::with_opts(opts(
+                "successes_total",
+                "Handshake attempts which succeeded",
+            ))
+            .expect("Metric options are valid"),
+            handshakes_failed: IntCounterVec::new(
+                opts("failures_total", "Handshake attempts which failed"),
+                &["kind"],
+            )
+            .expect("Metric options are valid"),
+            connect_duration: Histogram::with_opts(histogram_opts(
+                "connect_duration_seconds",
+                "Time to establish the TCP connection",
+            ))
+            .expect("Metric options are valid"),
+            handshake_duration: Histogram::with_opts(histogram_opts(
+                "handshake_duration_seconds",
+                "Time of successful handshakes, including the connection",
+            ))
+            .expect("Metric options are valid"),
+            phase_duration: HistogramVec::new(
+                histogram_opts(
+                    "phase_duration_seconds",
+                    "Time spent in each phase of the handshake attempts",
+                ),
+                &["phase"],
+            )
+            .expect("Metric options are valid"),
+            bytes_sent: IntCounter::with_opts(opts("sent_bytes_total", "Bytes sent to nodes"))
+                .expect("Metric options are valid"),
+            bytes_received: IntCounter::with_opts(opts(
+                "received_bytes_total",
+                "Bytes received from nodes",
+            ))
+            .expect("Metric options are valid"),
+            active_connections: IntGauge::with_opts(opts(
+                "active_connections",
+                "Connections being established or in use",
+            ))
+            .expect("Metric options are valid"),
+            node_up: IntGaugeVec::new(
+                opts("node_up", "Whether the monitored node is up"),
+                &["node"],
+            )
+            .expect("Metric options are valid"),
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(metrics.handshakes_attempted.clone()),
+            Box::new(metrics.handshakes_succeeded.clone()),
+            Box::new(metrics.handshakes_failed.clone()),
+            Box::new(metrics.connect_duration.clone()),
+            Box::new(metrics.handshake_duration.clone()),
+            Box::new(metrics.phase_duration.clone()),
+            Box::new(metrics.bytes_sent.clone()),
+            Box::new(metrics.bytes_received.clone()),
+            Box::new(metrics.active_connections.clone()),
+            Box::new(metrics.node_up.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("Metric names are unique");
+        }
+        metrics
+    }
+
+    /// Counts the attempts of the outcome and observes its latency and the
+    /// phases of every attempt
+    pub fn record_outcome(&self, outcome: &NodeOutcome) {
+        for attempt in &outcome.attempts {
+            self.handshakes_attempted.inc();
+            for (phase, duration) in attempt.timings.phases() {
+                self.phase_duration
+                    .with_label_values(&[phase.name()])
+                    .observe(duration.as_secs_f64());
+            }
+            match &attempt.error {
+                Some(error) => self
+                    .handshakes_failed
+                    .with_label_values(&[&error.kind.to_string()])
+                    .inc(),
+                None => self.handshakes_succeeded.inc(),
+            }
+        }
+        if let Some(latency) = outcome.latency() {
+            self.handshake_duration.observe(latency.as_secs_f64());
+        }
+    }
+
+    /// Returns a guard which counts a connection as active until dropped
+    pub fn active_connection(&self) -> ActiveConnection {
+        self.active_connections.inc();
+        ActiveConnection {
+            gauge: self.active_connections.clone(),
+        }
+    }
+
+    /// Returns all metrics in the Prometheus text format
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Metrics can be encoded");
+        String::from_utf8(buffer).expect("Metrics are valid UTF-8")
+    }
+}
+
+/// Guard of an active connection, see [Metrics::active_connection]
+#[derive(Debug)]
+pub struct ActiveConnection {
+    gauge: IntGauge,
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
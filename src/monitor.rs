@@ -0,0 +1,314 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::{net::TcpListener, time::MissedTickBehavior};
+
+use crate::{
+    bitcoin::{
+        client_pool::{BitcoinClientPool, NodeOutcome, PoolConfig},
+        peer_address::PeerAddress,
+        retry::ErrorKind,
+    },
+    http::{serve, Response},
+    metrics::Metrics,
+};
+
+/// State of a monitored node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeState {
+    /// Node was not checked yet, or has not failed often enough to be down
+    Unknown,
+    /// Last handshake with the node succeeded
+    Up,
+    /// Handshakes with the node failed the configured number of times in a row
+    Down,
+}
+
+impl fmt::Display for NodeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NodeState::Unknown => "unknown",
+            NodeState::Up => "up",
+            NodeState::Down => "down",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Current status of a monitored node
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeStatus {
+    pub address: PeerAddress,
+    pub state: NodeState,
+    /// Unix time of the last state change in seconds
+    pub since: Option<u64>,
+    /// Unix time of the last check in seconds
+    pub last_check: Option<u64>,
+    pub checks: u64,
+    pub failures: u64,
+    /// Number of failed checks since the last successful one
+    pub consecutive_failures: u32,
+    pub error_kind: Option<ErrorKind>,
+    pub error: Option<String>,
+    pub peer_version: Option<u32>,
+    pub user_agent: Option<String>,
+    pub start_height: Option<i32>,
+    pub latency_ms: Option<f64>,
+}
+
+impl NodeStatus {
+    fn new(address: PeerAddress) -> NodeStatus {
+        NodeStatus {
+            address,
+            state: NodeState::Unknown,
+            since: None,
+            last_check: None,
+            checks: 0,
+            failures: 0,
+            consecutive_failures: 0,
+            error_kind: None,
+            error: None,
+            peer_version: None,
+            user_agent: None,
+            start_height: None,
+            latency_ms: None,
+        }
+    }
+}
+
+/// Change of the state of a node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub node: PeerAddress,
+    pub from: NodeState,
+    pub to: NodeState,
+}
+
+/// Status of all monitored nodes, shared between the monitor and whoever
+/// reports it
+#[derive(Debug, Clone, Default)]
+pub struct StatusBoard {
+    nodes: Arc<RwLock<BTreeMap<PeerAddress, NodeStatus>>>,
+}
+
+impl StatusBoard {
+    /// Returns the status of all nodes, ordered by address
+    pub fn snapshot(&self) -> Vec<NodeStatus> {
+        self.nodes
+            .read()
+            .expect("Status board lock is poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the status of all nodes as a JSON array
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.snapshot()).expect("Status is serializable")
+    }
+
+    fn insert(&self, node: PeerAddress) {
+        self.nodes
+            .write()
+            .expect("Status board lock is poisoned")
+            .entry(node.clone())
+            .or_insert_with(|| NodeStatus::new(node));
+    }
+
+    /// Updates the status of the node with the outcome of a check and
+    /// returns the state change, if any
+    fn update(&self, outcome: &NodeOutcome, down_after: u32) -> Option<Transition> {
+        let now = unix_time();
+        let mut nodes = self.nodes.write().expect("Status board lock is poisoned");
+        let status = nodes
+            .entry(outcome.node.clone())
+            .or_insert_with(|| NodeStatus::new(outcome.node.clone()));
+        status.checks += 1;
+        status.last_check = Some(now);
+        let state = match &outcome.result {
+            Ok(version) => {
+                status.consecutive_failures = 0;
+                status.error_kind = None;
+                status.error = None;
+                status.peer_version = Some(version.version);
+                status.user_agent = Some(version.user_agent.clone());
+                status.start_height = Some(version.start_height);
+                status.latency_ms = outcome
+                    .latency()
+                    .map(|latency| latency.as_secs_f64() * 1000.0);
+                NodeState::Up
+            }
+            Err(e) => {
+                status.failures += 1;
+                status.consecutive_failures += 1;
+                status.error_kind = outcome.error_kind();
+                status.error = Some(format!("{:#}", e));
+                status.latency_ms = None;
+                if status.consecutive_failures >= down_after.max(1) {
+                    NodeState::Down
+                } else {
+                    status.state
+                }
+            }
+        };
+        if state == status.state {
+            return None;
+        }
+        let transition = Transition {
+            node: outcome.node.clone(),
+            from: status.state,
+            to: state,
+        };
+        status.state = state;
+        status.since = Some(now);
+        Some(transition)
+    }
+}
+
+/// Settings of the monitor
+#[derive(Debug, Clone)]
+pub struct MonitorConfig {
+    /// Time between the starts of two checks of all nodes
+    pub interval: Duration,
+    /// Number of failed checks in a row after which a node is down
+    pub down_after: u32,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            down_after: 1,
+        }
+    }
+}
+
+/// Monitor which repeatedly handshakes with a set of nodes, keeps their
+/// up or down state on a [StatusBoard] and logs every state change.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::bitcoin::client_pool::PoolConfig;
+/// use p2p_handshake_bitcoin::monitor::{MonitorConfig, Monitor, NodeState};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let nodes = vec!["127.0.0.1:1".parse().unwrap()];
+///     let monitor = Monitor::new(nodes, MonitorConfig::default(), PoolConfig::default());
+///     let transitions = monitor.check().await;
+///     assert_eq!(transitions[0].to, NodeState::Down);
+///     assert_eq!(monitor.status().snapshot()[0].state, NodeState::Down);
+/// }
+/// ```
+pub struct Monitor {
+    nodes: Vec<PeerAddress>,
+    config: MonitorConfig,
+    pool_config: PoolConfig,
+    status: StatusBoard,
+}
+
+impl Monitor {
+    /// Creates a monitor of the nodes, which are all in the unknown state
+    /// until they are checked
+    pub fn new(nodes: Vec<PeerAddress>, config: MonitorConfig, pool_config: PoolConfig) -> Monitor {
+        let status = StatusBoard::default();
+        for node in &nodes {
+            status.insert(node.clone());
+        }
+        Monitor {
+            nodes,
+            config,
+            pool_config,
+            status,
+        }
+    }
+
+    /// Returns the status board, which stays up to date while the monitor runs
+    pub fn status(&self) -> StatusBoard {
+        self.status.clone()
+    }
+
+    /// Handshakes with all nodes once, updates their status and returns
+    /// the state changes
+    pub async fn check(&self) -> Vec<Transition> {
+        let mut pool = BitcoinClientPool::with_config(self.nodes.clone(), self.pool_config.clone());
+        let mut transitions = Vec::new();
+        while let Some(outcome) = pool.next_outcome().await {
+            let Some(transition) = self.status.update(&outcome, self.config.down_after) else {
+                continue;
+            };
+            match (transition.to, &outcome.result) {
+                (NodeState::Down, Err(e)) => tracing::warn!(
+                    from = %transition.from,
+                    error.message = %e,
+                    "Node {} is down",
+                    transition.node
+                ),
+                _ => tracing::info!(
+                    from = %transition.from,
+                    "Node {} is {}",
+                    transition.node,
+                    transition.to
+                ),
+            }
+            transitions.push(transition);
+        }
+        if let Some(metrics) = &self.pool_config.metrics {
+            for status in self.status.snapshot() {
+                let up = match status.state {
+                    NodeState::Up => 1,
+                    NodeState::Down => 0,
+                    NodeState::Unknown => continue,
+                };
+                metrics
+                    .node_up
+                    .with_label_values(&[&status.address.to_string()])
+                    .set(up);
+            }
+        }
+        transitions
+    }
+
+    /// Checks all nodes on every interval, until the future is dropped
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(self.config.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.check().await;
+            let snapshot = self.status.snapshot();
+            let up = snapshot
+                .iter()
+                .filter(|status| status.state == NodeState::Up)
+                .count();
+            tracing::info!("Checked {} Nodes, {} are up", snapshot.len(), up);
+        }
+    }
+}
+
+/// Serves the status of the nodes as a JSON array on `/status`, the
+/// metrics in the Prometheus text format on `/metrics` and a plain `ok`
+/// on `/health`, until the future is dropped
+pub async fn serve_status(listener: TcpListener, status: StatusBoard, metrics: Arc<Metrics>) {
+    serve(listener, move |path: &str| match path {
+        "/status" => Response::ok("application/json", status.to_json()),
+        "/metrics" => Response::ok("text/plain; version=0.0.4; charset=utf-8", metrics.encode()),
+        "/health" => Response::ok("text/plain; charset=utf-8", "ok\n".to_string()),
+        _ => Response::not_found(),
+    })
+    .await
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,311 @@
+use std::{collections::BTreeMap, io::Write};
+
+use bitcoin::p2p::ServiceFlags;
+use serde::Serialize;
+
+use crate::bitcoin::{client_pool::NodeOutcome, retry::ErrorKind, timing::Phase};
+
+/// Machine-readable formats in which handshake results can be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Pretty printed JSON array, written once all nodes are done
+    Json,
+    /// One JSON object per line, written as soon as a node is done
+    Ndjson,
+    /// Comma separated values with a header line
+    Csv,
+    /// Human-readable table with a summary, written once all nodes are done
+    Table,
+}
+
+/// Formats in which reports over all nodes, such as the census, can be
+/// written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// Pretty printed JSON object
+    Json,
+    /// Human-readable report
+    Report,
+}
+
+/// Result of the handshake with a single node, flattened for output
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeRecord {
+    pub address: String,
+    pub success: bool,
+    pub error_kind: Option<ErrorKind>,
+    pub error: Option<String>,
+    pub attempts: usize,
+    pub peer_version: Option<u32>,
+    pub user_agent: Option<String>,
+    pub services: Option<u64>,
+    pub start_height: Option<i32>,
+    pub latency_ms: Option<f64>,
+    /// Time spent resolving the host name in the last attempt
+    pub dns_ms: Option<f64>,
+    /// Time spent establishing the TCP connection in the last attempt
+    pub connect_ms: Option<f64>,
+    /// Time from the connection to the first byte from the node
+    pub first_byte_ms: Option<f64>,
+    /// Time from the first byte to the version message of the node
+    pub version_ms: Option<f64>,
+    /// Time from the version message to the verack message of the node
+    pub verack_ms: Option<f64>,
+}
+
+impl From<&NodeOutcome> for NodeRecord {
+    fn from(outcome: &NodeOutcome) -> Self {
+        let version = outcome.result.as_ref().ok();
+        let phases = outcome
+            .timings()
+            .map(|timings| timings.phases())
+            .unwrap_or_default();
+        let phase_ms = |phase: Phase| {
+            phases
+                .iter()
+                .find(|(p, _)| *p == phase)
+                .map(|(_, duration)| duration.as_secs_f64() * 1000.0)
+        };
+        NodeRecord {
+            address: outcome.node.to_string(),
+            success: version.is_some(),
+            error_kind: outcome.error_kind(),
+            error: outcome.result.as_ref().err().map(|e| format!("{:#}", e)),
+            attempts: outcome.attempts.len(),
+            peer_version: version.map(|v| v.version),
+            user_agent: version.map(|v| v.user_agent.clone()),
+            services: version.map(|v| v.services.to_u64()),
+            start_height: version.map(|v| v.start_height),
+            latency_ms: outcome
+                .latency()
+                .map(|latency| latency.as_secs_f64() * 1000.0),
+            dns_ms: phase_ms(Phase::Dns),
+            connect_ms: phase_ms(Phase::Connect),
+            first_byte_ms: phase_ms(Phase::FirstByte),
+            version_ms: phase_ms(Phase::Version),
+            verack_ms: phase_ms(Phase::Verack),
+        }
+    }
+}
+
+/// Writes node records in the selected format. Streaming formats are
+/// written record by record, the rest once [finish] is called.
+///
+/// [finish]: OutputWriter::finish
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::output::{NodeRecord, OutputFormat, OutputWriter};
+///
+/// let mut writer = OutputWriter::new(OutputFormat::Csv, Vec::new());
+/// writer.write(NodeRecord {
+///     address: "127.0.0.1:8333".to_string(),
+///     success: false,
+///     error_kind: None,
+///     error: Some("refused".to_string()),
+///     attempts: 1,
+///     peer_version: None,
+///     user_agent: None,
+///     services: None,
+///     start_height: None,
+///     latency_ms: None,
+///     dns_ms: Some(0.02),
+///     connect_ms: None,
+///     first_byte_ms: None,
+///     version_ms: None,
+///     verack_ms: None,
+/// }).unwrap();
+/// let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+/// assert_eq!(output.lines().count(), 2);
+/// ```
+pub struct OutputWriter<W: Write> {
+    format: OutputFormat,
+    writer: W,
+    records: Vec<NodeRecord>,
+    header_written: bool,
+}
+
+impl<W: Write> OutputWriter<W> {
+    /// Creates a writer of records in the provided format
+    pub fn new(format: OutputFormat, writer: W) -> OutputWriter<W> {
+        OutputWriter {
+            format,
+            writer,
+            records: Vec::new(),
+            header_written: false,
+        }
+    }
+
+    /// Writes a single record, or keeps it until [finish] for formats which
+    /// can not be streamed.
+    ///
+    /// [finish]: OutputWriter::finish
+    pub fn write(&mut self, record: NodeRecord) -> Result<(), anyhow::Error> {
+        match self.format {
+            OutputFormat::Json | OutputFormat::Table => self.records.push(record),
+            OutputFormat::Ndjson => {
+                serde_json::to_writer(&mut self.writer, &record)?;
+                self.writer.write_all(b"\n")?;
+                self.writer.flush()?;
+            }
+            OutputFormat::Csv => {
+                let mut csv = csv::WriterBuilder::new()
+                    .has_headers(!self.header_written)
+                    .from_writer(&mut self.writer);
+                csv.serialize(&record)?;
+                csv.flush()?;
+                self.header_written = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining records and returns the inner writer
+    pub fn finish(mut self) -> Result<W, anyhow::Error> {
+        match self.format {
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut self.writer, &self.records)?;
+                self.writer.write_all(b"\n")?;
+            }
+            OutputFormat::Table => write_table(&mut self.writer, &self.records)?,
+            OutputFormat::Ndjson | OutputFormat::Csv => {}
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Returns the value below which `p` percent of the sorted values fall,
+/// using the nearest-rank method.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::output::percentile;
+///
+/// let values = [10.0, 20.0, 30.0, 40.0];
+/// assert_eq!(percentile(&values, 50.0), Some(20.0));
+/// assert_eq!(percentile(&values, 99.0), Some(40.0));
+/// assert_eq!(percentile(&[], 50.0), None);
+/// ```
+pub fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// Returns names of the service flags separated by `|`, e.g. `NETWORK|WITNESS`
+pub(crate) fn service_names(services: u64) -> String {
+    let flags = ServiceFlags::from(services).to_string();
+    flags
+        .trim_start_matches("ServiceFlags(")
+        .trim_end_matches(')')
+        .to_string()
+}
+
+/// Writes the header and rows as left-aligned columns
+pub(crate) fn write_columns<W: Write>(
+    writer: &mut W,
+    header: &[&str],
+    rows: &[Vec<String>],
+) -> std::io::Result<()> {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut write_row = |cells: Vec<&str>| -> std::io::Result<()> {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(writer, "{}", cells.join("  ").trim_end())
+    };
+    write_row(header.to_vec())?;
+    for row in rows {
+        write_row(row.iter().map(String::as_str).collect())?;
+    }
+    Ok(())
+}
+
+/// Writes a table with one row per node, followed by the aggregate counts
+/// and handshake time percentiles.
+fn write_table<W: Write>(writer: &mut W, records: &[NodeRecord]) -> Result<(), anyhow::Error> {
+    let header = [
+        "NODE",
+        "STATUS",
+        "VERSION",
+        "USER AGENT",
+        "SERVICES",
+        "TIME",
+    ];
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| {
+            let status = match record.error_kind {
+                None if record.success => "ok".to_string(),
+                None => "failed".to_string(),
+                Some(kind) => kind.to_string(),
+            };
+            vec![
+                record.address.clone(),
+                status,
+                record
+                    .peer_version
+                    .map(|v| v.to_string())
+                    .unwrap_or_default(),
+                record.user_agent.clone().unwrap_or_default(),
+                record.services.map(service_names).unwrap_or_default(),
+                record
+                    .latency_ms
+                    .map(|latency| format!("{:.1} ms", latency))
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    write_columns(writer, &header, &rows)?;
+
+    let succeeded = records.iter().filter(|record| record.success).count();
+    let mut failures: BTreeMap<String, usize> = BTreeMap::new();
+    for row in rows.iter().filter(|row| row[1] != "ok") {
+        *failures.entry(row[1].clone()).or_default() += 1;
+    }
+    writeln!(writer)?;
+    write!(
+        writer,
+        "Nodes: {}, succeeded: {}, failed: {}",
+        records.len(),
+        succeeded,
+        records.len() - succeeded
+    )?;
+    if !failures.is_empty() {
+        let failures: Vec<String> = failures
+            .iter()
+            .map(|(kind, count)| format!("{} {}", kind, count))
+            .collect();
+        write!(writer, " ({})", failures.join(", "))?;
+    }
+    writeln!(writer)?;
+
+    let mut latencies: Vec<f64> = records.iter().filter_map(|r| r.latency_ms).collect();
+    latencies.sort_by(f64::total_cmp);
+    if let (Some(min), Some(max)) = (latencies.first(), latencies.last()) {
+        let p = |p| percentile(&latencies, p).unwrap_or_default();
+        writeln!(
+            writer,
+            "Handshake time: min {:.1} ms, p50 {:.1} ms, p90 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+            min,
+            p(50.0),
+            p(90.0),
+            p(99.0),
+            max
+        )?;
+    }
+    Ok(())
+}
@@ -1,13 +1,402 @@
-use clap::{command, Parser};
+use std::net::SocketAddr;
+
+use bitcoin::{p2p::ServiceFlags, Network};
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    bitcoin::{headers::Checkpoint, retry::ErrorKind},
+    output::{OutputFormat, ReportFormat},
+    telemetry::{LogFormat, LogRotation},
+};
 
 /// Arguments structure used to collect necessary information from the user
 /// for the Bitcoin Client. It is based on clap crate.
 #[derive(Parser, Debug)]
 #[command(version)]
 #[command(propagate_version = true)]
+#[command(subcommand_negates_reqs = true)]
+#[command(subcommand_precedence_over_arg = true)]
 pub struct Arguments {
-    #[clap(required = true)]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[clap(
+        required_unless_present_any = ["input", "seed", "best"],
+        help = "node addresses, or - to read them from standard input"
+    )]
     pub ip_nodes: Vec<String>,
-    #[arg(long, short, default_value_t = 500, help = "connection timeout")]
+    #[arg(
+        long,
+        short,
+        help = "file with node addresses, one per line or getnodeaddresses/getpeerinfo JSON, - for standard input"
+    )]
+    pub input: Vec<String>,
+    #[arg(
+        long,
+        short,
+        default_value_t = 500,
+        help = "timeout of connecting and of the handshake in miliseconds"
+    )]
     pub timeout: u64,
+    #[arg(
+        long,
+        short,
+        default_value_t = Network::Bitcoin,
+        help = "network whose default port is used for nodes without one"
+    )]
+    pub network: Network,
+    #[arg(long, help = "add nodes returned by the DNS seeds of the network")]
+    pub seed: bool,
+    #[arg(
+        long,
+        value_parser = parse_service_flags,
+        help = "only ask DNS seeds for nodes with these service flags, in hex (e.g. 9)"
+    )]
+    pub seed_services: Option<ServiceFlags>,
+    #[arg(
+        long,
+        global = true,
+        help = "append-only peer database file every handshake is recorded to"
+    )]
+    pub peer_db: Option<String>,
+    #[arg(
+        long,
+        requires = "peer_db",
+        help = "add the best N known-good nodes from the peer database"
+    )]
+    pub best: Option<usize>,
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "maximum number of handshake attempts per node"
+    )]
+    pub max_attempts: u32,
+    #[arg(
+        long,
+        default_value_t = 200,
+        help = "delay before the first retry in miliseconds, doubled on every next one"
+    )]
+    pub retry_delay: u64,
+    #[arg(
+        long,
+        default_value_t = 10000,
+        help = "maximum delay between retries in miliseconds"
+    )]
+    pub max_retry_delay: u64,
+    #[arg(
+        long,
+        default_value_t = 0.2,
+        value_parser = parse_jitter,
+        help = "fraction by which the retry delay is randomly varied"
+    )]
+    pub retry_jitter: f64,
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [ErrorKind::Timeout, ErrorKind::Connection, ErrorKind::Communication],
+        help = "error kinds which are retried"
+    )]
+    pub retry_on: Vec<ErrorKind>,
+    #[arg(
+        long,
+        visible_alias = "format",
+        value_enum,
+        help = "write one record per node to standard output, logs go to standard error"
+    )]
+    pub output: Option<OutputFormat>,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with = "output",
+        help = "write statistics over all nodes to standard output once they are done"
+    )]
+    pub census: Option<ReportFormat>,
+    #[arg(
+        long,
+        requires = "census",
+        help = "file mapping IP prefixes to AS numbers, one '<prefix>/<length> <asn>' per line"
+    )]
+    pub asn_map: Option<String>,
+    #[arg(
+        long,
+        help = "crawl the network by asking reachable nodes for addresses of other nodes"
+    )]
+    pub crawl: bool,
+    #[arg(
+        long,
+        default_value_t = 2,
+        help = "maximum number of getaddr hops from the provided nodes"
+    )]
+    pub crawl_depth: u32,
+    #[arg(
+        long,
+        default_value_t = 1000,
+        help = "maximum number of nodes to attempt while crawling"
+    )]
+    pub crawl_max_nodes: usize,
+    #[arg(
+        long,
+        default_value_t = 100,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "maximum number of concurrent handshakes while crawling"
+    )]
+    pub crawl_concurrency: usize,
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "maximum number of new connections per second while crawling, 0 for no limit"
+    )]
+    pub crawl_rate: u32,
+    #[arg(
+        long,
+        default_value_t = 5000,
+        help = "time to wait for addresses from a node in miliseconds"
+    )]
+    pub addr_timeout: u64,
+    #[arg(
+        long,
+        help = "file to write the reachable nodes found while crawling to"
+    )]
+    pub reachable_output: Option<String>,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["crawl", "output", "census"],
+        help = "sync the headers of every node and write how their best headers compare to standard output"
+    )]
+    pub tips: Option<ReportFormat>,
+    #[arg(
+        long,
+        default_value_t = 10000,
+        help = "time to wait for each batch of headers in miliseconds"
+    )]
+    pub headers_timeout: u64,
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "blocks a node may lag behind the consensus tip or misreport its start height by"
+    )]
+    pub tip_tolerance: u32,
+    #[arg(
+        long,
+        requires = "tips",
+        help = "start syncing headers at <HEIGHT>:<HEADER HEX> instead of the genesis block"
+    )]
+    pub tip_checkpoint: Option<Checkpoint>,
+    #[arg(
+        long,
+        help = "record the traffic with every node into <DIR>/<address>.pcap"
+    )]
+    pub pcap_dir: Option<String>,
+    #[arg(
+        long,
+        conflicts_with = "pcap_dir",
+        help = "record the traffic with all nodes into a single pcap file"
+    )]
+    pub pcap_file: Option<String>,
+    #[arg(
+        long,
+        help = "record a transcript of every session into <DIR>/<address>.<n>.ndjson"
+    )]
+    pub record_dir: Option<String>,
+    #[arg(long, value_enum, default_value_t = LogFormat::Bunyan, help = "format of the logs")]
+    pub log_format: LogFormat,
+    #[arg(
+        long,
+        default_value = "info",
+        help = "minimum level of the logs (error, warn, info, debug, trace) or filter directives like in RUST_LOG"
+    )]
+    pub log_level: String,
+    #[arg(
+        long,
+        short,
+        action = clap::ArgAction::Count,
+        conflicts_with = "log_level",
+        help = "log at debug level, or at trace level if given twice"
+    )]
+    pub verbose: u8,
+    #[arg(
+        long,
+        help = "write the logs to this file instead of a standard stream"
+    )]
+    pub log_file: Option<String>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = LogRotation::Daily,
+        requires = "log_file",
+        help = "period after which a new log file is started"
+    )]
+    pub log_rotation: LogRotation,
+    #[arg(
+        long,
+        requires = "log_file",
+        help = "number of log files to keep, the oldest ones are deleted"
+    )]
+    pub log_max_files: Option<usize>,
+    #[arg(
+        long,
+        conflicts_with = "log_file",
+        help = "write the logs to standard error, so standard output only has results"
+    )]
+    pub log_stderr: bool,
+    #[cfg(feature = "otlp")]
+    #[arg(
+        long,
+        help = "export spans to the OpenTelemetry collector at this URL, e.g. http://localhost:4318"
+    )]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Commands other than handshaking with the nodes
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Query and export the peer database
+    Peers(PeersArguments),
+    /// Keep handshaking with the nodes on an interval and track their state
+    Monitor(MonitorArguments),
+    /// Handshake with a fake node replaying a recorded session
+    Replay(ReplayArguments),
+    /// Announce a raw transaction to the nodes and serve it to them
+    Broadcast(BroadcastArguments),
+    /// Stream the transactions the nodes relay as JSON lines
+    Mempool(MempoolArguments),
+    /// Measure how much later than the first node each node announces
+    /// transactions and blocks
+    Propagation(PropagationArguments),
+}
+
+/// Arguments of the peers command
+#[derive(Args, Debug)]
+pub struct PeersArguments {
+    #[arg(long, help = "only list the best N known-good peers")]
+    pub best: Option<usize>,
+    #[arg(long, value_enum, default_value = "table", help = "format of the list")]
+    pub format: OutputFormat,
+}
+
+/// Arguments of the monitor command
+#[derive(Args, Debug)]
+pub struct MonitorArguments {
+    #[arg(
+        long,
+        default_value_t = 60,
+        help = "time between two checks of all nodes in seconds"
+    )]
+    pub interval: u64,
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "number of failed checks in a row after which a node is down"
+    )]
+    pub down_after: u32,
+    #[arg(
+        long,
+        help = "address to serve the status of the nodes on over HTTP, e.g. 127.0.0.1:9180"
+    )]
+    pub listen: Option<SocketAddr>,
+}
+
+/// Arguments of the replay command
+#[derive(Args, Debug)]
+pub struct ReplayArguments {
+    #[arg(help = "transcript recorded with --record-dir")]
+    pub transcript: String,
+    #[arg(long, help = "send the replies no earlier than the node did")]
+    pub realtime: bool,
+    #[arg(
+        long,
+        default_value_t = 5000,
+        help = "time to wait for the handshake in miliseconds"
+    )]
+    pub wait: u64,
+}
+
+/// Arguments of the broadcast command
+#[derive(Args, Debug)]
+pub struct BroadcastArguments {
+    #[arg(help = "raw transaction in hex, or - to read it from standard input")]
+    pub transaction: String,
+    #[arg(
+        long,
+        default_value_t = 8,
+        help = "number of nodes to announce the transaction to, nodes failing the handshake are replaced"
+    )]
+    pub peers: usize,
+    #[arg(
+        long,
+        default_value_t = 10000,
+        help = "time to wait for each node to request the transaction in miliseconds"
+    )]
+    pub wait: u64,
+    #[arg(
+        long,
+        help = "keep listening for reject messages about the transaction until --wait is over"
+    )]
+    pub rejects: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "report",
+        help = "format of the report"
+    )]
+    pub format: ReportFormat,
+}
+
+/// Arguments of the mempool command
+#[derive(Args, Debug)]
+pub struct MempoolArguments {
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "stop after this many seconds, 0 to run until interrupted"
+    )]
+    pub duration: u64,
+    #[arg(
+        long,
+        default_value_t = 5000,
+        help = "time a node has to send a requested transaction in miliseconds"
+    )]
+    pub fetch_timeout: u64,
+}
+
+/// Arguments of the propagation command
+#[derive(Args, Debug)]
+pub struct PropagationArguments {
+    #[arg(
+        long,
+        default_value_t = 600,
+        help = "time to record announcements for in seconds"
+    )]
+    pub duration: u64,
+    #[arg(
+        long,
+        default_value_t = 2,
+        help = "minimum number of nodes announcing an item for it to count towards the delays"
+    )]
+    pub min_peers: usize,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "report",
+        help = "format of the report"
+    )]
+    pub format: ReportFormat,
+}
+
+/// Parses the retry jitter, which must be a finite number
+fn parse_jitter(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(jitter) if jitter.is_finite() => Ok(jitter),
+        Ok(_) => Err(format!("invalid jitter {}: must be finite", value)),
+        Err(e) => Err(format!("invalid jitter {}: {}", value, e)),
+    }
+}
+
+/// Parses service flags written in hex, with or without the `0x` prefix
+fn parse_service_flags(value: &str) -> Result<ServiceFlags, String> {
+    let digits = value.trim_start_matches("0x");
+    u64::from_str_radix(digits, 16)
+        .map(ServiceFlags::from)
+        .map_err(|e| format!("invalid service flags {}: {}", value, e))
 }
@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::{client_pool::NodeOutcome, peer_address::PeerAddress, retry::ErrorKind},
+    output::{service_names, write_columns, OutputFormat},
+};
+
+/// Single handshake outcome as stored in the peer database file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Observation {
+    pub address: PeerAddress,
+    /// Unix time of the observation in seconds
+    pub time: u64,
+    pub success: bool,
+    pub error_kind: Option<ErrorKind>,
+    pub services: Option<u64>,
+    pub user_agent: Option<String>,
+    pub latency_ms: Option<f64>,
+}
+
+impl Observation {
+    /// Creates an observation of the outcome made at the current time
+    pub fn from_outcome(outcome: &NodeOutcome) -> Observation {
+        let version = outcome.result.as_ref().ok();
+        Observation {
+            address: outcome.node.clone(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            success: version.is_some(),
+            error_kind: outcome.error_kind(),
+            services: version.map(|v| v.services.to_u64()),
+            user_agent: version.map(|v| v.user_agent.clone()),
+            latency_ms: outcome
+                .latency()
+                .map(|latency| latency.as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+/// Reachability history of a single address
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PeerStats {
+    pub address: PeerAddress,
+    /// Unix time of the last attempt in seconds
+    pub last_attempt: u64,
+    /// Unix time of the last successful handshake in seconds
+    pub last_success: Option<u64>,
+    /// Number of failed attempts since the last successful one
+    pub failure_streak: u32,
+    pub attempts: u64,
+    pub successes: u64,
+    pub last_error_kind: Option<ErrorKind>,
+    /// Services advertised in the last successful handshake
+    pub services: Option<u64>,
+    /// User agent advertised in the last successful handshake
+    pub user_agent: Option<String>,
+    /// Latency of the last successful handshake
+    pub latency_ms: Option<f64>,
+}
+
+impl PeerStats {
+    fn new(address: PeerAddress) -> PeerStats {
+        PeerStats {
+            address,
+            last_attempt: 0,
+            last_success: None,
+            failure_streak: 0,
+            attempts: 0,
+            successes: 0,
+            last_error_kind: None,
+            services: None,
+            user_agent: None,
+            latency_ms: None,
+        }
+    }
+
+    fn apply(&mut self, observation: &Observation) {
+        self.last_attempt = self.last_attempt.max(observation.time);
+        self.attempts += 1;
+        if observation.success {
+            self.successes += 1;
+            self.failure_streak = 0;
+            self.last_success = Some(observation.time);
+            self.last_error_kind = None;
+            self.services = observation.services;
+            self.user_agent = observation.user_agent.clone();
+            self.latency_ms = observation.latency_ms;
+        } else {
+            self.failure_streak += 1;
+            self.last_error_kind = observation.error_kind;
+        }
+    }
+
+    /// Returns the share of successful attempts
+    pub fn success_rate(&self) -> f64 {
+        match self.attempts {
+            0 => 0.0,
+            attempts => self.successes as f64 / attempts as f64,
+        }
+    }
+
+    /// Returns whether the last attempt with the address succeeded
+    pub fn is_known_good(&self) -> bool {
+        self.last_success.is_some() && self.failure_streak == 0
+    }
+}
+
+/// Peer database kept in an append-only file, with one JSON observation
+/// per line. Opening the store folds all observations into the
+/// reachability history of each address.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::peer_store::PeerStore;
+///
+/// let path = std::env::temp_dir().join("p2p_handshake_bitcoin_doc_peers.ndjson");
+/// let store = PeerStore::open(&path).unwrap();
+/// let best = store.best(10);
+/// # std::fs::remove_file(&path).ok();
+/// ```
+pub struct PeerStore {
+    path: PathBuf,
+    file: File,
+    peers: HashMap<PeerAddress, PeerStats>,
+}
+
+impl PeerStore {
+    /// Opens the store, creating the file if it does not exist. Lines which
+    /// can not be parsed, such as one cut off by a crash, are skipped, and a
+    /// cut off last line is terminated so new observations start on a line
+    /// of their own.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<PeerStore, anyhow::Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open peer database {}", path.display()))?;
+        let mut store = PeerStore {
+            path,
+            file,
+            peers: HashMap::new(),
+        };
+        let mut contents = String::new();
+        File::open(&store.path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .with_context(|| format!("Failed to read {}", store.path.display()))?;
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Observation>(line) {
+                Ok(observation) => store.apply(&observation),
+                Err(e) => tracing::warn!(
+                    error.message = %e,
+                    "Skipping line {} of peer database {}",
+                    number + 1,
+                    store.path.display()
+                ),
+            }
+        }
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            store
+                .file
+                .write_all(b"\n")
+                .with_context(|| format!("Failed to write to {}", store.path.display()))?;
+        }
+        Ok(store)
+    }
+
+    /// Appends the outcome of a handshake to the store
+    pub fn record(&mut self, outcome: &NodeOutcome) -> Result<(), anyhow::Error> {
+        self.append(Observation::from_outcome(outcome))
+    }
+
+    /// Appends an observation to the store
+    pub fn append(&mut self, observation: Observation) -> Result<(), anyhow::Error> {
+        let mut line = serde_json::to_string(&observation)?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .with_context(|| format!("Failed to write to {}", self.path.display()))?;
+        self.apply(&observation);
+        Ok(())
+    }
+
+    /// Returns the history of the address, if it was ever attempted
+    pub fn get(&self, address: &PeerAddress) -> Option<&PeerStats> {
+        self.peers.get(address)
+    }
+
+    /// Returns the history of all addresses, most recently attempted first
+    pub fn peers(&self) -> Vec<&PeerStats> {
+        let mut peers: Vec<&PeerStats> = self.peers.values().collect();
+        peers.sort_by(|a, b| {
+            b.last_attempt
+                .cmp(&a.last_attempt)
+                .then_with(|| a.address.cmp(&b.address))
+        });
+        peers
+    }
+
+    /// Returns up to `n` addresses whose last attempt succeeded, preferring
+    /// the ones with the highest success rate and the lowest latency.
+    pub fn best(&self, n: usize) -> Vec<PeerAddress> {
+        let mut good: Vec<&PeerStats> = self
+            .peers
+            .values()
+            .filter(|peer| peer.is_known_good())
+            .collect();
+        good.sort_by(|a, b| {
+            b.success_rate()
+                .total_cmp(&a.success_rate())
+                .then_with(|| {
+                    let latency = |peer: &PeerStats| peer.latency_ms.unwrap_or(f64::MAX);
+                    latency(a).total_cmp(&latency(b))
+                })
+                .then_with(|| a.address.cmp(&b.address))
+        });
+        good.into_iter()
+            .take(n)
+            .map(|peer| peer.address.clone())
+            .collect()
+    }
+
+    fn apply(&mut self, observation: &Observation) {
+        self.peers
+            .entry(observation.address.clone())
+            .or_insert_with(|| PeerStats::new(observation.address.clone()))
+            .apply(observation);
+    }
+}
+
+/// Writes the history of the peers in the selected format
+pub fn export<W: Write>(
+    writer: &mut W,
+    peers: &[&PeerStats],
+    format: OutputFormat,
+) -> Result<(), anyhow::Error> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, peers)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Ndjson => {
+            for peer in peers {
+                serde_json::to_writer(&mut *writer, peer)?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut *writer);
+            for peer in peers {
+                csv.serialize(peer)?;
+            }
+            csv.flush()?;
+        }
+        OutputFormat::Table => {
+            let header = [
+                "NODE",
+                "LAST ATTEMPT",
+                "LAST SUCCESS",
+                "SUCCESS",
+                "STREAK",
+                "USER AGENT",
+                "SERVICES",
+                "TIME",
+            ];
+            let rows: Vec<Vec<String>> = peers
+                .iter()
+                .map(|peer| {
+                    vec![
+                        peer.address.to_string(),
+                        peer.last_attempt.to_string(),
+                        peer.last_success.map(|t| t.to_string()).unwrap_or_default(),
+                        format!("{}/{}", peer.successes, peer.attempts),
+                        peer.failure_streak.to_string(),
+                        peer.user_agent.clone().unwrap_or_default(),
+                        peer.services.map(service_names).unwrap_or_default(),
+                        peer.latency_ms
+                            .map(|latency| format!("{:.1} ms", latency))
+                            .unwrap_or_default(),
+                    ]
+                })
+                .collect();
+            write_columns(writer, &header, &rows)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
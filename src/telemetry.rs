@@ -1,7 +1,41 @@
+use std::path::Path;
+
+use anyhow::Context;
 use tracing::{subscriber::set_global_default, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::{Identity, Layered, SubscriberExt},
+    EnvFilter, Layer, Registry,
+};
+
+/// Registry with the filter of the log level applied, which the layer
+/// passed to [get_subscriber_with_layer] is added to
+pub type FilteredRegistry = Layered<EnvFilter, Registry>;
+
+/// Formats in which log records can be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Bunyan JSON, one object per line
+    #[default]
+    Bunyan,
+    /// Human-readable text, one line per record
+    Compact,
+    /// Human-readable text over multiple lines, with the source location
+    Pretty,
+    /// Plain JSON, one object per line, with the current span
+    Json,
+}
+
+/// Periods after which a new log file is started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    Daily,
+}
 
 /// Function that creates a Registry that collects logging data.
 /// It has four levels of information filter: info, error, debug and trace.
@@ -25,16 +59,166 @@ pub fn get_subscriber<Sink>(
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    get_subscriber_with_layer(name, env_filter, LogFormat::Bunyan, sink, Identity::new())
+}
+
+/// Function that creates the same Registry as [get_subscriber], formatting
+/// the records in the given format, with an additional layer that receives
+/// all spans and events passing the filter, e.g. the one returned by
+/// `otlp_layer`.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::telemetry::{get_subscriber_with_layer, LogFormat};
+/// let subscriber = get_subscriber_with_layer(
+///     "p2p_handshake_bitcoin".into(),
+///     "debug".into(),
+///     LogFormat::Compact,
+///     std::io::stderr,
+///     None::<tracing_subscriber::layer::Identity>,
+/// );
+/// ```
+pub fn get_subscriber_with_layer<Sink, L>(
+    name: String,
+    env_filter: String,
+    format: LogFormat,
+    sink: Sink,
+    layer: L,
+) -> impl Subscriber + Send + Sync
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    L: Layer<FilteredRegistry> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
-    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let formatting_layer = match format {
+        LogFormat::Bunyan => JsonStorageLayer
+            .and_then(BunyanFormattingLayer::new(name, sink))
+            .boxed(),
+        LogFormat::Compact => fmt::layer()
+            .compact()
+            .with_ansi(false)
+            .with_writer(sink)
+            .boxed(),
+        LogFormat::Pretty => fmt::layer()
+            .pretty()
+            .with_ansi(false)
+            .with_writer(sink)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_writer(sink)
+            .boxed(),
+    };
     Registry::default()
         .with(env_filter)
-        .with(JsonStorageLayer)
+        .with(layer)
         .with(formatting_layer)
 }
 
+/// Function that creates a writer appending to the log file, which starts a
+/// new file on every rotation period and deletes the oldest ones once there
+/// are more than `max_files`. Rotated files get the date appended to the
+/// file name, e.g. `handshake.log.2024-03-01`.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::telemetry::{get_subscriber, log_file_writer, LogRotation};
+/// let path = std::env::temp_dir().join("p2p_handshake_bitcoin_doc.log");
+/// let writer = log_file_writer(path.to_str().unwrap(), LogRotation::Daily, Some(7)).unwrap();
+/// let subscriber = get_subscriber("p2p_handshake_bitcoin".into(), "info".into(), writer);
+/// ```
+pub fn log_file_writer(
+    path: &str,
+    rotation: LogRotation,
+    max_files: Option<usize>,
+) -> anyhow::Result<RollingFileAppender> {
+    let path = Path::new(path);
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Log file {} has no file name", path.display()))?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let rotation = match rotation {
+        LogRotation::Never => Rotation::NEVER,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+    };
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name);
+    if let Some(max_files) = max_files {
+        builder = builder.max_log_files(max_files);
+    }
+    builder
+        .build(directory)
+        .with_context(|| format!("Failed to open log file {}", path.display()))
+}
+
+/// Function that creates a layer exporting spans to an OpenTelemetry
+/// collector over OTLP/HTTP, along with the tracer provider which has to
+/// be shut down before exiting to send the remaining spans.
+/// The endpoint is the base URL of the collector, e.g.
+/// `http://localhost:4318`, to which `/v1/traces` is appended if missing.
+/// Only plain HTTP collectors are supported.
+///
+/// #Example
+///
+/// ```
+/// use p2p_handshake_bitcoin::telemetry::{get_subscriber_with_layer, otlp_layer, LogFormat};
+/// let (layer, provider) = otlp_layer(
+///     "p2p_handshake_bitcoin".into(),
+///     "http://localhost:4318",
+/// ).unwrap();
+/// let subscriber = get_subscriber_with_layer(
+///     "p2p_handshake_bitcoin".into(),
+///     "info".into(),
+///     LogFormat::Bunyan,
+///     std::io::stdout,
+///     layer,
+/// );
+/// provider.shutdown().ok();
+/// ```
+#[cfg(feature = "otlp")]
+pub fn otlp_layer(
+    name: String,
+    endpoint: &str,
+) -> anyhow::Result<(
+    tracing_opentelemetry::OpenTelemetryLayer<FilteredRegistry, opentelemetry_sdk::trace::Tracer>,
+    opentelemetry_sdk::trace::SdkTracerProvider,
+)> {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let endpoint = endpoint.trim_end_matches('/');
+    let endpoint = if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .context("Failed to create OTLP exporter")?;
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(name.clone())
+        .build();
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer(name);
+    Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+}
+
 /// Function that initializes created subscriber.
 ///
 /// #Example
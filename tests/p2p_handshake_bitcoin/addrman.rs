@@ -0,0 +1,104 @@
+use std::net::Ipv4Addr;
+
+use p2p_handshake_bitcoin::bitcoin::{
+    addrman::{AddrMan, BUCKET_SIZE},
+    peer_address::{Host, PeerAddress},
+};
+
+fn ipv4(a: u8, b: u8, c: u8, d: u8) -> PeerAddress {
+    PeerAddress::new(Host::Ipv4(Ipv4Addr::new(a, b, c, d)), 8333)
+}
+
+#[test]
+fn single_source_can_not_flood_the_new_table() {
+    let mut addrman = AddrMan::new();
+    let flooder = ipv4(198, 51, 100, 1);
+    let mut added = 0;
+    for a in 1..=200u8 {
+        for b in 0..50u8 {
+            if addrman.add(ipv4(a, b, 0, 1), &flooder) {
+                added += 1;
+            }
+        }
+    }
+    // Addresses from one source group fit into at most 64 buckets
+    assert!(added <= 64 * BUCKET_SIZE);
+    assert!(added < 10_000);
+    assert_eq!(addrman.new_count(), added);
+
+    // Other sources use other buckets, so most of their addresses get in
+    let honest = (1..=20u8)
+        .filter(|&a| addrman.add(ipv4(100, a, 0, 1), &ipv4(203, a, 113, 1)))
+        .count();
+    assert!(honest >= 10, "{} honest addresses added", honest);
+}
+
+#[test]
+fn known_address_is_not_added_again() {
+    let mut addrman = AddrMan::new();
+    let source = ipv4(198, 51, 100, 1);
+    assert!(addrman.add(ipv4(10, 0, 0, 1), &source));
+    assert!(!addrman.add(ipv4(10, 0, 0, 1), &ipv4(203, 0, 113, 1)));
+    assert_eq!(addrman.len(), 1);
+}
+
+#[test]
+fn full_new_bucket_only_replaces_terrible_addresses() {
+    let mut addrman = AddrMan::with_capacity(1, 1, 2);
+    let source = ipv4(198, 51, 100, 1);
+    assert!(addrman.add(ipv4(10, 0, 0, 1), &source));
+    assert!(addrman.add(ipv4(10, 0, 0, 2), &source));
+    assert!(!addrman.add(ipv4(10, 0, 0, 3), &source));
+
+    for _ in 0..3 {
+        addrman.attempt(&ipv4(10, 0, 0, 1));
+    }
+    assert!(addrman.add(ipv4(10, 0, 0, 3), &source));
+    assert!(!addrman.contains(&ipv4(10, 0, 0, 1)));
+    assert_eq!(addrman.len(), 2);
+}
+
+#[test]
+fn good_address_is_promoted_and_evicts_the_oldest_tried() {
+    let mut addrman = AddrMan::with_capacity(1, 1, 2);
+    let source = ipv4(198, 51, 100, 1);
+    addrman.add(ipv4(10, 0, 0, 1), &source);
+    addrman.add(ipv4(10, 0, 0, 2), &source);
+    addrman.good(&ipv4(10, 0, 0, 1));
+    addrman.good(&ipv4(10, 0, 0, 2));
+    addrman.add(ipv4(10, 0, 0, 3), &source);
+    assert_eq!((addrman.tried_count(), addrman.new_count()), (2, 1));
+    assert!(addrman.get(&ipv4(10, 0, 0, 1)).unwrap().tried);
+
+    // Tried bucket is full, so one of the tried addresses goes back to new
+    addrman.good(&ipv4(10, 0, 0, 3));
+    assert_eq!((addrman.tried_count(), addrman.new_count()), (2, 1));
+    assert!(addrman.get(&ipv4(10, 0, 0, 3)).unwrap().tried);
+    assert_eq!(addrman.len(), 3);
+}
+
+#[test]
+fn selection_is_biased_toward_reliable_addresses() {
+    let mut addrman = AddrMan::new();
+    let source = ipv4(198, 51, 100, 1);
+    let reliable = ipv4(10, 0, 0, 1);
+    let failing = ipv4(10, 0, 0, 2);
+    addrman.add(reliable.clone(), &source);
+    addrman.add(failing.clone(), &source);
+    addrman.attempt(&failing);
+    addrman.attempt(&failing);
+
+    let selected = (0..1000)
+        .filter(|_| addrman.select() == Some(reliable.clone()))
+        .count();
+    assert!(
+        selected > 900,
+        "reliable address selected {} times",
+        selected
+    );
+    assert_eq!(
+        addrman.select_where(|a| a != &reliable),
+        Some(failing.clone())
+    );
+    assert_eq!(addrman.select_where(|_| false), None);
+}
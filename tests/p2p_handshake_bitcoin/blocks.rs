@@ -0,0 +1,183 @@
+use std::{collections::HashMap, time::Duration};
+
+use bitcoin::{
+    blockdata::constants::genesis_block, p2p::message::NetworkMessage, Block, BlockHash, Network,
+    Witness,
+};
+use p2p_handshake_bitcoin::bitcoin::{
+    blocks::{validate_block, BlockError},
+    client::{BitcoinClient, BitcoinClientError},
+    message::BitcoinMessage,
+    stream::Stream,
+};
+
+use crate::helper::{
+    segwit_block, spawn_block_node, spawn_local_node, spawn_node, witness_transaction, Reply,
+};
+
+fn regtest_block() -> Block {
+    let genesis = genesis_block(Network::Regtest).header;
+    segwit_block(
+        &genesis,
+        vec![witness_transaction(1), witness_transaction(2)],
+    )
+}
+
+async fn fetch(
+    blocks: HashMap<BlockHash, Block>,
+    hash: BlockHash,
+) -> Result<Block, BitcoinClientError> {
+    let address = spawn_block_node(Network::Regtest, blocks).await;
+    let stream = Stream::new(&address.to_string(), 500).await.unwrap();
+    let mut client = BitcoinClient::with_network(stream.rx, stream.tx, Network::Regtest);
+    client.handshake().await.unwrap();
+    client.get_block(hash, Duration::from_secs(5)).await
+}
+
+#[tokio::test]
+async fn block_is_fetched_with_its_witnesses() {
+    let block = regtest_block();
+    let hash = block.block_hash();
+
+    let fetched = fetch(HashMap::from([(hash, block.clone())]), hash)
+        .await
+        .unwrap();
+
+    assert_eq!(fetched, block);
+    assert!(!fetched.txdata[1].input[0].witness.is_empty());
+}
+
+#[tokio::test]
+async fn missing_block_is_reported_as_not_found() {
+    let hash = regtest_block().block_hash();
+
+    let result = fetch(HashMap::new(), hash).await;
+
+    assert!(matches!(result, Err(BitcoinClientError::BlockNotFound(h)) if h == hash));
+}
+
+#[tokio::test]
+async fn blocks_other_than_the_requested_one_are_skipped() {
+    let unrelated = regtest_block();
+    let block = segwit_block(&unrelated.header, vec![witness_transaction(3)]);
+    let hash = block.block_hash();
+    let version = BitcoinMessage::get_bitcoin_version_message();
+    let address = spawn_node(Network::Regtest, version, move || {
+        let unrelated = unrelated.clone();
+        let block = block.clone();
+        move |message: &NetworkMessage| match message {
+            NetworkMessage::GetData(_) => vec![
+                Reply::Send(NetworkMessage::Block(unrelated.clone())),
+                Reply::Send(NetworkMessage::Block(block.clone())),
+            ],
+            _ => Vec::new(),
+        }
+    })
+    .await;
+    let stream = Stream::new(&address.to_string(), 500).await.unwrap();
+    let mut client = BitcoinClient::with_network(stream.rx, stream.tx, Network::Regtest);
+    client.handshake().await.unwrap();
+
+    let fetched = client
+        .get_block(hash, Duration::from_secs(5))
+        .await
+        .unwrap();
+
+    assert_eq!(fetched.block_hash(), hash);
+}
+
+#[tokio::test]
+async fn block_stored_under_another_hash_is_not_accepted() {
+    let block = regtest_block();
+    let requested = genesis_block(Network::Regtest).block_hash();
+    let address = spawn_block_node(Network::Regtest, HashMap::from([(requested, block)])).await;
+    let stream = Stream::new(&address.to_string(), 500).await.unwrap();
+    let mut client = BitcoinClient::with_network(stream.rx, stream.tx, Network::Regtest);
+    client.handshake().await.unwrap();
+
+    let result = client
+        .get_block(requested, Duration::from_millis(200))
+        .await;
+
+    assert!(format!("{:?}", result.unwrap_err()).contains("Timed out waiting for block"));
+}
+
+#[tokio::test]
+async fn fetching_times_out_when_node_sends_no_block() {
+    let address = spawn_local_node().await;
+    let stream = Stream::new(&address.to_string(), 500).await.unwrap();
+    let mut client = BitcoinClient::new(stream.rx, stream.tx);
+    client.handshake().await.unwrap();
+
+    let result = client
+        .get_block(regtest_block().block_hash(), Duration::from_millis(100))
+        .await;
+
+    assert!(format!("{:?}", result.unwrap_err()).contains("Timed out waiting for block"));
+}
+
+#[test]
+fn block_with_another_hash_is_the_wrong_block() {
+    let block = regtest_block();
+    let requested = genesis_block(Network::Regtest).block_hash();
+
+    assert_eq!(
+        validate_block(&block, requested),
+        Err(BlockError::WrongBlock {
+            requested,
+            received: block.block_hash()
+        })
+    );
+}
+
+#[test]
+fn transactions_must_match_the_merkle_root() {
+    let mut block = regtest_block();
+    let hash = block.block_hash();
+    block.txdata[2].output[0].value = bitcoin::Amount::from_sat(1);
+
+    assert_eq!(
+        validate_block(&block, hash),
+        Err(BlockError::BadMerkleRoot { hash })
+    );
+}
+
+#[test]
+fn witnesses_must_match_the_witness_commitment() {
+    let mut block = regtest_block();
+    let hash = block.block_hash();
+    // Witnesses are not part of the txid, so the merkle root still matches
+    block.txdata[1].input[0].witness = Witness::from_slice(&[vec![0xff; 72]]);
+
+    assert!(block.check_merkle_root());
+    assert_eq!(
+        validate_block(&block, hash),
+        Err(BlockError::BadWitnessCommitment { hash })
+    );
+}
+
+#[test]
+fn repeated_transactions_are_rejected_although_the_merkle_root_matches() {
+    let mut block = regtest_block();
+    let hash = block.block_hash();
+    let last = block.txdata[2].clone();
+    block.txdata.push(last);
+
+    assert!(block.check_merkle_root());
+    assert_eq!(
+        validate_block(&block, hash),
+        Err(BlockError::DuplicateTransaction { hash })
+    );
+}
+
+#[test]
+fn block_without_transactions_is_rejected() {
+    let mut block = regtest_block();
+    let hash = block.block_hash();
+    block.txdata.clear();
+
+    assert_eq!(
+        validate_block(&block, hash),
+        Err(BlockError::NoTransactions { hash })
+    );
+}
@@ -0,0 +1,174 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use bitcoin::{p2p::message_network::RejectReason, Network};
+use p2p_handshake_bitcoin::{
+    bitcoin::{
+        broadcast::{
+            parse_transaction, BroadcastConfig, BroadcastReport, PeerBroadcast, TxBroadcast,
+        },
+        client::BitcoinClient,
+        client_pool::PoolConfig,
+        peer_address::PeerAddress,
+        stream::Stream,
+    },
+    output::ReportFormat,
+};
+
+use crate::helper::{spawn_tx_node, witness_transaction, TxNodeReply};
+
+async fn broadcast(nodes: &[SocketAddr], config: BroadcastConfig) -> BroadcastReport {
+    let nodes = nodes
+        .iter()
+        .map(|&address| PeerAddress::from(address))
+        .collect();
+    let pool_config = PoolConfig {
+        network: Network::Regtest,
+        ..PoolConfig::default()
+    };
+    TxBroadcast::new(config, pool_config, witness_transaction(7))
+        .run(nodes, |_| Ok(()))
+        .await
+        .unwrap()
+}
+
+fn quick() -> BroadcastConfig {
+    BroadcastConfig {
+        wait: Duration::from_millis(300),
+        ..BroadcastConfig::default()
+    }
+}
+
+fn peer(report: &BroadcastReport, node: SocketAddr) -> &PeerBroadcast {
+    report
+        .peers
+        .iter()
+        .find(|peer| peer.node == PeerAddress::from(node))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn transaction_is_served_to_nodes_which_request_it() {
+    let transaction = witness_transaction(7);
+    let (by_txid, received_by_txid) = spawn_tx_node(Network::Regtest, TxNodeReply::GetTx).await;
+    let (by_wtxid, received_by_wtxid) =
+        spawn_tx_node(Network::Regtest, TxNodeReply::GetWtx(transaction.wtxid())).await;
+    let (silent, received_by_silent) = spawn_tx_node(Network::Regtest, TxNodeReply::Ignore).await;
+    let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+
+    let report = broadcast(&[by_txid, by_wtxid, silent, unreachable], quick()).await;
+
+    assert_eq!(report.txid, transaction.txid());
+    assert_eq!(report.attempted, 4);
+    assert_eq!(report.peers.len(), 3);
+    assert_eq!(report.requested().count(), 2);
+    let request = peer(&report, by_txid).announcement.request.unwrap();
+    assert_eq!(request.kind(), "tx");
+    assert!(request.after < Duration::from_millis(300));
+    let request = peer(&report, by_wtxid).announcement.request.unwrap();
+    assert_eq!(request.kind(), "wtx");
+    assert!(peer(&report, silent).announcement.request.is_none());
+    assert!(peer(&report, silent).error.is_none());
+    assert_eq!(*received_by_txid.lock().unwrap(), vec![transaction.clone()]);
+    assert_eq!(*received_by_wtxid.lock().unwrap(), vec![transaction]);
+    assert!(received_by_silent.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn nodes_failing_the_handshake_are_replaced_up_to_the_number_of_peers() {
+    let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    let (first, _) = spawn_tx_node(Network::Regtest, TxNodeReply::GetTx).await;
+    let (second, _) = spawn_tx_node(Network::Regtest, TxNodeReply::GetTx).await;
+    let (third, received_by_third) = spawn_tx_node(Network::Regtest, TxNodeReply::GetTx).await;
+    let config = BroadcastConfig {
+        peers: 2,
+        ..quick()
+    };
+
+    let report = broadcast(&[unreachable, first, second, third], config).await;
+
+    assert_eq!(report.attempted, 3);
+    assert_eq!(report.requested().count(), 2);
+    assert!(received_by_third.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn rejects_are_collected_until_the_wait_is_over() {
+    let (node, _) = spawn_tx_node(Network::Regtest, TxNodeReply::GetAndReject).await;
+    let config = BroadcastConfig {
+        collect_rejects: true,
+        ..quick()
+    };
+
+    let report = broadcast(&[node], config).await;
+
+    let rejected: Vec<_> = report.rejected().collect();
+    assert_eq!(rejected.len(), 1);
+    let reject = &rejected[0].announcement.rejects[0];
+    assert_eq!(reject.code, RejectReason::Dust);
+    assert_eq!(reject.reason, "dust");
+}
+
+#[tokio::test]
+async fn announcement_returns_once_the_transaction_is_served() {
+    let (node, _) = spawn_tx_node(Network::Regtest, TxNodeReply::GetAndReject).await;
+    let stream = Stream::new(&node.to_string(), 500).await.unwrap();
+    let mut client = BitcoinClient::with_network(stream.rx, stream.tx, Network::Regtest);
+    client.handshake().await.unwrap();
+
+    let started = Instant::now();
+    let announcement = client
+        .announce_transaction(&witness_transaction(7), Duration::from_secs(10), false)
+        .await
+        .unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(announcement.request.is_some());
+    assert!(announcement.rejects.is_empty());
+}
+
+#[tokio::test]
+async fn report_lists_the_nodes_which_requested_the_transaction() {
+    let (requesting, _) = spawn_tx_node(Network::Regtest, TxNodeReply::GetTx).await;
+    let (silent, _) = spawn_tx_node(Network::Regtest, TxNodeReply::Ignore).await;
+    let report = broadcast(&[requesting, silent], quick()).await;
+
+    let mut text = Vec::new();
+    report.write(&mut text, ReportFormat::Report).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.starts_with(&format!("Transaction: {} (wtxid ", report.txid)));
+    assert!(text.contains("Nodes: 2, announced to: 2, requested by: 1, rejected by: 0"));
+    let rows: Vec<&str> = text.lines().skip(3).collect();
+    assert!(rows[0].starts_with("NODE"));
+    let requesting_row = rows
+        .iter()
+        .find(|row| row.starts_with(&requesting.to_string()))
+        .unwrap();
+    assert!(requesting_row.contains("requested as tx"));
+    let silent_row = rows
+        .iter()
+        .find(|row| row.starts_with(&silent.to_string()))
+        .unwrap();
+    assert!(silent_row.contains("not requested"));
+
+    let mut json = Vec::new();
+    report.write(&mut json, ReportFormat::Json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["txid"], report.txid.to_string());
+    let requesting_record = json["peers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|peer| peer["node"] == requesting.to_string())
+        .unwrap();
+    assert_eq!(requesting_record["requested"], true);
+    assert_eq!(requesting_record["inventory"], "tx");
+}
+
+#[test]
+fn transaction_must_be_valid_hex() {
+    assert!(parse_transaction("not hex").is_err());
+    assert!(parse_transaction("0200").is_err());
+}
@@ -0,0 +1,198 @@
+use std::{path::PathBuf, sync::Arc};
+
+use bitcoin::Network;
+use p2p_handshake_bitcoin::bitcoin::{
+    capture::PcapCapture,
+    client_pool::{BitcoinClientPool, PoolConfig},
+    connection::Tap,
+    peer_address::PeerAddress,
+};
+
+use crate::helper::spawn_local_node;
+
+/// TCP segment read back from a capture
+#[derive(Debug)]
+struct Segment {
+    source_port: u16,
+    destination_port: u16,
+    sequence: u32,
+    acknowledgment: u32,
+    flags: u8,
+    payload: Vec<u8>,
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "p2p_handshake_bitcoin_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for word in data.chunks(2) {
+        sum += ((word[0] as u32) << 8) | word.get(1).copied().unwrap_or(0) as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Reads the IPv4 TCP segments of a pcap file, checking the headers
+fn read_segments(path: &PathBuf) -> Vec<Segment> {
+    let data = std::fs::read(path).unwrap();
+    assert_eq!(&data[0..4], &0xa1b2c3d4u32.to_le_bytes());
+    assert_eq!(&data[20..24], &101u32.to_le_bytes());
+    let mut segments = Vec::new();
+    let mut offset = 24;
+    while offset < data.len() {
+        let length = u32::from_le_bytes(data[offset + 8..offset + 12].try_into().unwrap()) as usize;
+        let packet = &data[offset + 16..offset + 16 + length];
+        offset += 16 + length;
+
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(u16::from_be_bytes([packet[2], packet[3]]) as usize, length);
+        assert_eq!(checksum(&packet[..20]), 0, "invalid IPv4 checksum");
+        let tcp = &packet[20..];
+        let mut pseudo = packet[12..20].to_vec();
+        pseudo.extend_from_slice(&[0, 6]);
+        pseudo.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+        pseudo.extend_from_slice(tcp);
+        assert_eq!(checksum(&pseudo), 0, "invalid TCP checksum");
+
+        segments.push(Segment {
+            source_port: u16::from_be_bytes([tcp[0], tcp[1]]),
+            destination_port: u16::from_be_bytes([tcp[2], tcp[3]]),
+            sequence: u32::from_be_bytes(tcp[4..8].try_into().unwrap()),
+            acknowledgment: u32::from_be_bytes(tcp[8..12].try_into().unwrap()),
+            flags: tcp[13],
+            payload: tcp[20..].to_vec(),
+        });
+    }
+    segments
+}
+
+#[tokio::test]
+async fn handshake_is_captured_with_consecutive_sequence_numbers() {
+    let directory = temp_path("pcap_dir");
+    let address = spawn_local_node().await;
+    let node: PeerAddress = address.into();
+    let config = PoolConfig {
+        capture: Some(Arc::new(PcapCapture::per_node(&directory).unwrap())),
+        ..PoolConfig::default()
+    };
+    let outcomes = BitcoinClientPool::with_config(vec![node.clone()], config)
+        .run()
+        .await
+        .unwrap();
+    assert!(outcomes[0].result.is_ok());
+
+    let segments = read_segments(&directory.join(PcapCapture::file_name(&node)));
+    // SYN, SYN-ACK, ACK, then at least version and verack both ways
+    assert!(segments.len() >= 7, "{:?}", segments);
+    assert_eq!(segments[0].flags, 0x02);
+    assert_eq!(segments[0].destination_port, address.port());
+    assert_eq!(segments[1].flags, 0x12);
+    assert_eq!(segments[1].source_port, address.port());
+    assert_eq!(
+        segments[1].acknowledgment,
+        segments[0].sequence.wrapping_add(1)
+    );
+    assert_eq!(segments[2].flags, 0x10);
+
+    let mut next_sequence = [
+        segments[0].sequence.wrapping_add(1),
+        segments[1].sequence.wrapping_add(1),
+    ];
+    let mut sent = Vec::new();
+    let mut received = Vec::new();
+    for segment in &segments[3..] {
+        let outgoing = segment.destination_port == address.port();
+        let (own, other) = if outgoing { (0, 1) } else { (1, 0) };
+        assert_eq!(segment.sequence, next_sequence[own]);
+        assert_eq!(segment.acknowledgment, next_sequence[other]);
+        next_sequence[own] = next_sequence[own].wrapping_add(segment.payload.len() as u32);
+        if outgoing {
+            sent.extend_from_slice(&segment.payload);
+        } else {
+            received.extend_from_slice(&segment.payload);
+        }
+    }
+    let magic = Network::Bitcoin.magic().to_bytes();
+    for bytes in [&sent, &received] {
+        assert_eq!(&bytes[0..4], &magic);
+        assert_eq!(&bytes[4..11], b"version");
+        assert!(bytes.windows(6).any(|window| window == b"verack"));
+    }
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn merged_capture_has_the_connections_to_all_nodes() {
+    let path = temp_path("merged.pcap");
+    let mut nodes: Vec<PeerAddress> = Vec::new();
+    for _ in 0..2 {
+        nodes.push(spawn_local_node().await.into());
+    }
+    let config = PoolConfig {
+        capture: Some(Arc::new(PcapCapture::merged(&path).unwrap())),
+        ..PoolConfig::default()
+    };
+    BitcoinClientPool::with_config(nodes.clone(), config)
+        .run()
+        .await
+        .unwrap();
+
+    let segments = read_segments(&path);
+    let mut syn_ports: Vec<u16> = segments
+        .iter()
+        .filter(|segment| segment.flags == 0x02)
+        .map(|segment| segment.destination_port)
+        .collect();
+    syn_ports.sort();
+    let mut node_ports: Vec<u16> = nodes.iter().map(|node| node.port).collect();
+    node_ports.sort();
+    assert_eq!(syn_ports, node_ports);
+    assert!(segments.len() >= 14, "{:?}", segments);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn repeated_connections_to_a_node_are_appended_to_its_file() {
+    let directory = temp_path("pcap_repeated");
+    let capture = PcapCapture::per_node(&directory).unwrap();
+    let node: PeerAddress = "127.0.0.1:8333".parse().unwrap();
+    for port in [50000, 50001] {
+        let mut stream = capture
+            .open(
+                &node,
+                format!("127.0.0.1:{}", port).parse().unwrap(),
+                "127.0.0.1:8333".parse().unwrap(),
+            )
+            .unwrap();
+        stream.sent(b"version");
+    }
+
+    let segments = read_segments(&directory.join(PcapCapture::file_name(&node)));
+    let syn_ports: Vec<u16> = segments
+        .iter()
+        .filter(|segment| segment.flags == 0x02)
+        .map(|segment| segment.source_port)
+        .collect();
+    assert_eq!(syn_ports, vec![50000, 50001]);
+    assert_eq!(segments.len(), 8);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn per_node_file_names_have_no_separators() {
+    let node: PeerAddress = "[2001:db8::1]:8333".parse().unwrap();
+    assert_eq!(PcapCapture::file_name(&node), "2001_db8__1_8333.pcap");
+    let node: PeerAddress = "127.0.0.1:18333".parse().unwrap();
+    assert_eq!(PcapCapture::file_name(&node), "127.0.0.1_18333.pcap");
+}
@@ -0,0 +1,88 @@
+use p2p_handshake_bitcoin::{
+    bitcoin::client_pool::BitcoinClientPool,
+    census::{AsnMap, Census, CensusCollector},
+    output::ReportFormat,
+};
+
+use crate::helper::spawn_local_node;
+
+async fn local_census(asn_map: Option<AsnMap>) -> Census {
+    let mut nodes = Vec::new();
+    for _ in 0..2 {
+        nodes.push(spawn_local_node().await.to_string().parse().unwrap());
+    }
+    nodes.push("127.0.0.1:1".parse().unwrap());
+    let outcomes = BitcoinClientPool::new(nodes, 500).run().await.unwrap();
+    let mut collector = CensusCollector::new(asn_map);
+    for outcome in &outcomes {
+        collector.add(outcome);
+    }
+    collector.finish()
+}
+
+#[tokio::test]
+async fn census_aggregates_reachable_nodes() {
+    let census = local_census(None).await;
+    assert_eq!((census.attempted, census.reachable), (3, 2));
+    assert_eq!(census.protocol_versions.get(&70001), Some(&2));
+    assert_eq!(census.user_agents.get("/Satoshi:26.0.0/"), Some(&2));
+    assert!(census.services.is_empty());
+    assert_eq!(census.networks["ipv4"].attempted, 3);
+    assert_eq!(census.networks["ipv4"].reachable, 2);
+    assert_eq!(census.groups.get("127.0.0.0/16"), Some(&2));
+    assert_eq!(census.start_height.unwrap().max, 0);
+    let skew = census.timestamp_skew.unwrap();
+    assert!(skew.min >= -2 && skew.max <= 2, "{:?}", skew);
+}
+
+#[tokio::test]
+async fn census_groups_nodes_by_autonomous_system() {
+    let asn_map = AsnMap::parse("127.0.0.0/8 64500\n").unwrap();
+    let census = local_census(Some(asn_map)).await;
+    assert_eq!(census.groups.get("AS64500"), Some(&2));
+    assert_eq!(census.groups.len(), 1);
+}
+
+#[tokio::test]
+async fn census_is_written_as_json_and_report() {
+    let census = local_census(None).await;
+
+    let mut json = Vec::new();
+    census.write(&mut json, ReportFormat::Json).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(value["reachable"], 2);
+    assert_eq!(value["protocol_versions"]["70001"], 2);
+
+    let mut report = Vec::new();
+    census.write(&mut report, ReportFormat::Report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("Nodes: 3, reachable: 2"));
+    assert!(report.contains("/Satoshi:26.0.0/  2      100.0%"));
+    assert!(report.contains("Start height: min 0, p10 0, p50 0, p90 0, max 0"));
+}
+
+#[test]
+fn asn_map_uses_the_longest_prefix() {
+    let map = AsnMap::parse(
+        "2001:db8::/32 64500\n\
+         2001:db8:1::/48 AS64501 # more specific\n\
+         0.0.0.0/0 64502\n",
+    )
+    .unwrap();
+    assert_eq!(map.lookup("2001:db8:1::1".parse().unwrap()), Some(64501));
+    assert_eq!(map.lookup("2001:db8:2::1".parse().unwrap()), Some(64500));
+    assert_eq!(map.lookup("192.0.2.1".parse().unwrap()), Some(64502));
+    assert_eq!(map.lookup("2001:db9::1".parse().unwrap()), None);
+}
+
+#[test]
+fn asn_map_rejects_invalid_entries() {
+    for content in [
+        "10.0.0.0 64500",
+        "10.0.0.0/33 64500",
+        "10.0.0.0/8",
+        "10.0.0.0/8 ASX",
+    ] {
+        assert!(AsnMap::parse(content).is_err(), "{}", content);
+    }
+}
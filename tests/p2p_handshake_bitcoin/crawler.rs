@@ -0,0 +1,110 @@
+use std::{collections::HashSet, time::Duration};
+
+use p2p_handshake_bitcoin::bitcoin::{
+    client_pool::PoolConfig,
+    crawler::{CrawlConfig, Crawler},
+    peer_address::PeerAddress,
+};
+
+use crate::helper::{spawn_local_network, spawn_silent_node};
+
+fn crawl_config(max_depth: u32) -> CrawlConfig {
+    CrawlConfig {
+        max_depth,
+        connections_per_second: 0,
+        addr_timeout: Duration::from_millis(500),
+        ..CrawlConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn crawler_discovers_all_nodes_of_the_network() {
+    // 0 knows 1 and 2, 1 knows 3, 3 knows 0 and an unreachable node
+    let addresses = spawn_local_network(vec![vec![1, 2], vec![3], vec![], vec![0, 4]]).await;
+    let unreachable = addresses[4];
+    let seeds = vec![PeerAddress::from(addresses[0])];
+
+    let mut outcomes = 0;
+    let report = Crawler::new(crawl_config(3), PoolConfig::default())
+        .run(seeds, |_| {
+            outcomes += 1;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+    let reachable: HashSet<PeerAddress> = report.reachable.iter().map(|n| n.node.clone()).collect();
+    let expected: HashSet<PeerAddress> = addresses[..4].iter().map(|&a| a.into()).collect();
+    assert_eq!(reachable, expected);
+    assert!(!reachable.contains(&unreachable.into()));
+    assert_eq!(report.attempted, 5);
+    assert_eq!(report.discovered, 5);
+    assert_eq!(outcomes, 5);
+    assert_eq!(report.addrman.tried_count(), 4);
+    assert_eq!(report.addrman.get(&unreachable.into()).unwrap().attempts, 1);
+    let depth_of = |i: usize| {
+        report
+            .reachable
+            .iter()
+            .find(|n| n.node == addresses[i].into())
+            .unwrap()
+            .depth
+    };
+    assert_eq!(
+        (depth_of(0), depth_of(1), depth_of(2), depth_of(3)),
+        (0, 1, 1, 2)
+    );
+}
+
+#[tokio::test]
+async fn crawler_stops_at_maximum_depth() {
+    // Chain of nodes 0 -> 1 -> 2 -> 3
+    let addresses = spawn_local_network(vec![vec![1], vec![2], vec![3], vec![]]).await;
+    let report = Crawler::new(crawl_config(1), PoolConfig::default())
+        .run(vec![addresses[0].into()], |_| Ok(()))
+        .await
+        .unwrap();
+    assert_eq!(report.reachable.len(), 2);
+    assert_eq!(report.attempted, 2);
+}
+
+#[tokio::test]
+async fn crawler_stops_at_maximum_number_of_nodes() {
+    let addresses = spawn_local_network(vec![vec![1, 2, 3], vec![], vec![], vec![]]).await;
+    let config = CrawlConfig {
+        max_nodes: 2,
+        ..crawl_config(3)
+    };
+    let report = Crawler::new(config, PoolConfig::default())
+        .run(vec![addresses[0].into()], |_| Ok(()))
+        .await
+        .unwrap();
+    assert_eq!(report.attempted, 2);
+    assert_eq!(report.discovered, 4);
+}
+
+#[tokio::test]
+async fn silent_nodes_do_not_stall_the_crawl() {
+    let addresses = spawn_local_network(vec![vec![], vec![]]).await;
+    let silent = spawn_silent_node().await;
+    let config = CrawlConfig {
+        max_concurrent: 1,
+        ..crawl_config(0)
+    };
+    let pool_config = PoolConfig {
+        timeout: 100,
+        ..PoolConfig::default()
+    };
+    let seeds = vec![silent.into(), addresses[0].into(), addresses[1].into()];
+
+    let report = tokio::time::timeout(
+        Duration::from_secs(3),
+        Crawler::new(config, pool_config).run(seeds, |_| Ok(())),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(report.attempted, 3);
+    assert_eq!(report.reachable.len(), 2);
+}
@@ -0,0 +1,291 @@
+use std::time::Duration;
+
+use bitcoin::{
+    bip158::BlockFilter,
+    hashes::Hash,
+    p2p::{message::NetworkMessage, message_filter::CFilter},
+    Amount, FilterHash, FilterHeader, Network, OutPoint, ScriptBuf, TxOut,
+};
+use p2p_handshake_bitcoin::bitcoin::{
+    client::{BitcoinClient, BitcoinClientError},
+    filters::{FilterError, FilterHeaderChain},
+    headers::HeaderChain,
+    stream::Stream,
+};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::helper::{
+    segwit_block, segwit_chain, spawn_filter_node, witness_transaction, FilterStore,
+};
+
+type Client = BitcoinClient<OwnedReadHalf, OwnedWriteHalf>;
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Connects to a node serving the store and syncs its header chain
+async fn connect(store: FilterStore, serve: bool) -> (Client, HeaderChain) {
+    let address = spawn_filter_node(Network::Regtest, store, serve).await;
+    let stream = Stream::new(&address.to_string(), 500).await.unwrap();
+    let mut client = BitcoinClient::with_network(stream.rx, stream.tx, Network::Regtest);
+    client.handshake().await.unwrap();
+    let mut chain = HeaderChain::new(Network::Regtest);
+    client.sync_headers(&mut chain, WAIT).await.unwrap();
+    (client, chain)
+}
+
+fn script(seed: u8) -> ScriptBuf {
+    ScriptBuf::from_bytes([vec![0x00, 0x14], vec![seed; 20]].concat())
+}
+
+#[tokio::test]
+async fn filter_headers_are_synced_from_the_genesis_block() {
+    let store = FilterStore::new(segwit_chain(5));
+    let (mut client, chain) = connect(store.clone(), true).await;
+    let mut filters = FilterHeaderChain::new();
+
+    let height = client
+        .sync_filter_headers(&chain, &mut filters, WAIT)
+        .await
+        .unwrap();
+
+    assert_eq!(height, 5);
+    assert_eq!(filters.height(), Some(5));
+    for height in 0..=5 {
+        assert_eq!(
+            filters.header(height),
+            Some(store.filter_header(height as usize))
+        );
+    }
+}
+
+#[tokio::test]
+async fn filter_headers_are_synced_in_batches_and_match_the_checkpoints() {
+    let store = FilterStore::new(segwit_chain(2500));
+    let (mut client, chain) = connect(store.clone(), true).await;
+    let mut filters = FilterHeaderChain::new();
+
+    let height = client
+        .sync_filter_headers(&chain, &mut filters, WAIT)
+        .await
+        .unwrap();
+
+    assert_eq!(height, 2500);
+    assert_eq!(store.checkpoints.len(), 2);
+    assert!(filters
+        .check_checkpoints(&store.checkpoints, 0..=2500)
+        .is_ok());
+}
+
+#[tokio::test]
+async fn filter_headers_which_do_not_match_a_checkpoint_are_rejected() {
+    let mut store = FilterStore::new(segwit_chain(1200));
+    store.checkpoints[0] = FilterHeader::all_zeros();
+    let (mut client, chain) = connect(store, true).await;
+    let mut filters = FilterHeaderChain::new();
+
+    let result = client.sync_filter_headers(&chain, &mut filters, WAIT).await;
+
+    assert!(matches!(
+        result,
+        Err(BitcoinClientError::InvalidFilter(
+            FilterError::CheckpointMismatch { height: 1000 }
+        ))
+    ));
+}
+
+#[tokio::test]
+async fn filters_are_verified_against_their_filter_headers() {
+    let store = FilterStore::new(segwit_chain(5));
+    let (mut client, chain) = connect(store.clone(), true).await;
+    let mut filters = FilterHeaderChain::new();
+    client
+        .sync_filter_headers(&chain, &mut filters, WAIT)
+        .await
+        .unwrap();
+
+    let verified = client
+        .get_filters(&chain, &filters, 2, 5, WAIT)
+        .await
+        .unwrap();
+
+    let heights: Vec<u32> = verified.iter().map(|filter| filter.height).collect();
+    assert_eq!(heights, vec![2, 3, 4, 5]);
+    assert_eq!(verified[0].block_hash, store.blocks[2].block_hash());
+    assert_eq!(verified[0].filter, store.filters[2]);
+}
+
+#[tokio::test]
+async fn filters_of_other_blocks_are_skipped() {
+    let mut store = FilterStore::new(segwit_chain(5));
+    store.stray_filters = vec![CFilter {
+        filter_type: 0,
+        block_hash: store.blocks[0].block_hash(),
+        filter: store.filters[0].content.clone(),
+    }];
+    let (mut client, chain) = connect(store.clone(), true).await;
+    let mut filters = FilterHeaderChain::new();
+    client
+        .sync_filter_headers(&chain, &mut filters, WAIT)
+        .await
+        .unwrap();
+
+    let verified = client
+        .get_filters(&chain, &filters, 2, 5, WAIT)
+        .await
+        .unwrap();
+
+    let heights: Vec<u32> = verified.iter().map(|filter| filter.height).collect();
+    assert_eq!(heights, vec![2, 3, 4, 5]);
+}
+
+#[tokio::test]
+async fn filter_which_does_not_match_its_filter_header_is_rejected() {
+    let mut store = FilterStore::new(segwit_chain(5));
+    store.filters[3] = store.filters[0].clone();
+    let (mut client, chain) = connect(store.clone(), true).await;
+    let mut filters = FilterHeaderChain::new();
+    client
+        .sync_filter_headers(&chain, &mut filters, WAIT)
+        .await
+        .unwrap();
+
+    let result = client.get_filters(&chain, &filters, 0, 5, WAIT).await;
+
+    assert!(matches!(
+        result,
+        Err(BitcoinClientError::InvalidFilter(FilterError::BadFilter { hash, height: 3 }))
+            if hash == store.blocks[3].block_hash()
+    ));
+}
+
+#[tokio::test]
+async fn batch_of_filters_must_arrive_within_the_wait() {
+    let mut store = FilterStore::new(segwit_chain(5));
+    store.filter_delay = Duration::from_millis(100);
+    let (mut client, chain) = connect(store, true).await;
+    let mut filters = FilterHeaderChain::new();
+    client
+        .sync_filter_headers(&chain, &mut filters, WAIT)
+        .await
+        .unwrap();
+
+    // Each filter arrives within the wait, the whole batch does not
+    let result = client
+        .get_filters(&chain, &filters, 0, 5, Duration::from_millis(300))
+        .await;
+
+    assert!(format!("{:?}", result.unwrap_err()).contains("Timed out waiting for filters"));
+}
+
+#[tokio::test]
+async fn filters_outside_the_filter_header_chain_are_not_requested() {
+    let store = FilterStore::new(segwit_chain(5));
+    let (mut client, chain) = connect(store.clone(), true).await;
+    let mut filters = FilterHeaderChain::starting_at(3, store.filter_header(2));
+    client
+        .sync_filter_headers(&chain, &mut filters, WAIT)
+        .await
+        .unwrap();
+
+    let below = client
+        .scan_filters(&chain, &filters, &[script(1)], 0, WAIT)
+        .await;
+    let above = client.get_filters(&chain, &filters, 4, 6, WAIT).await;
+
+    assert!(matches!(
+        below,
+        Err(BitcoinClientError::InvalidFilter(
+            FilterError::OutsideChain { start: 0, stop: 5 }
+        ))
+    ));
+    assert!(matches!(
+        above,
+        Err(BitcoinClientError::InvalidFilter(
+            FilterError::OutsideChain { start: 4, stop: 6 }
+        ))
+    ));
+}
+
+#[tokio::test]
+async fn watchlist_matches_blocks_paying_to_and_spending_from_its_scripts() {
+    let mut blocks = segwit_chain(2);
+    let mut payment = witness_transaction(1);
+    payment.output[0].script_pubkey = script(1);
+    blocks.push(segwit_block(
+        &blocks.last().unwrap().header,
+        vec![payment.clone()],
+    ));
+    blocks.push(segwit_block(&blocks.last().unwrap().header, vec![]));
+    let mut spend = witness_transaction(2);
+    spend.input[0].previous_output = OutPoint::new(payment.txid(), 0);
+    spend.output = vec![TxOut {
+        value: Amount::from_sat(500),
+        script_pubkey: script(2),
+    }];
+    blocks.push(segwit_block(&blocks.last().unwrap().header, vec![spend]));
+    let (mut client, chain) = connect(FilterStore::new(blocks), true).await;
+    let mut filters = FilterHeaderChain::new();
+    client
+        .sync_filter_headers(&chain, &mut filters, WAIT)
+        .await
+        .unwrap();
+
+    let paid = client
+        .scan_filters(&chain, &filters, &[script(1)], 0, WAIT)
+        .await
+        .unwrap();
+    let unknown = client
+        .scan_filters(&chain, &filters, &[script(3)], 0, WAIT)
+        .await
+        .unwrap();
+
+    let heights: Vec<u32> = paid.iter().map(|filter| filter.height).collect();
+    assert_eq!(heights, vec![3, 5]);
+    assert!(unknown.is_empty());
+}
+
+#[tokio::test]
+async fn node_without_compact_filters_is_not_asked_for_them() {
+    let (mut client, chain) = connect(FilterStore::new(segwit_chain(5)), false).await;
+    let mut filters = FilterHeaderChain::new();
+
+    let result = client.sync_filter_headers(&chain, &mut filters, WAIT).await;
+
+    assert!(matches!(
+        result,
+        Err(BitcoinClientError::InvalidFilter(FilterError::NotSupported))
+    ));
+    assert!(client.send(NetworkMessage::Ping(1)).await.is_ok());
+}
+
+#[test]
+fn filter_headers_which_do_not_connect_to_the_tip_are_rejected() {
+    let filter_hash = FilterHash::hash(&BlockFilter::new(&[0]).content);
+    let mut filters = FilterHeaderChain::new();
+    filters
+        .extend(0, FilterHeader::all_zeros(), &[filter_hash])
+        .unwrap();
+
+    let wrong_previous = filters.extend(1, FilterHeader::all_zeros(), &[filter_hash]);
+    let wrong_height = filters.extend(2, filters.tip(), &[filter_hash]);
+
+    assert_eq!(wrong_previous, Err(FilterError::Unconnected { height: 1 }));
+    assert_eq!(wrong_height, Err(FilterError::Unconnected { height: 2 }));
+    assert_eq!(filters.height(), Some(0));
+}
+
+#[test]
+fn only_checkpoints_within_the_heights_are_checked() {
+    let filter_hash = FilterHash::hash(&BlockFilter::new(&[0]).content);
+    let mut filters = FilterHeaderChain::new();
+    filters
+        .extend(0, FilterHeader::all_zeros(), &[filter_hash; 2001])
+        .unwrap();
+    let checkpoints = [filters.header(1000).unwrap(), FilterHeader::all_zeros()];
+
+    assert_eq!(filters.check_checkpoints(&checkpoints, 0..=1999), Ok(()));
+    assert_eq!(
+        filters.check_checkpoints(&checkpoints, 1001..=2000),
+        Err(FilterError::CheckpointMismatch { height: 2000 })
+    );
+}
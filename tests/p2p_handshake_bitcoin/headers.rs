@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use bitcoin::{consensus::Params, Network, Target};
+use p2p_handshake_bitcoin::bitcoin::{
+    client::{BitcoinClient, BitcoinClientError},
+    headers::{HeaderChain, HeaderError},
+    stream::Stream,
+};
+
+use crate::helper::{
+    mine_headers, regtest_chain, spawn_chain_node, spawn_local_node, unmined_header,
+};
+
+/// Regtest parameters with the difficulty adjusted like on mainnet
+fn retargeting_params() -> Params {
+    let mut params = Params::new(Network::Regtest);
+    params.no_pow_retargeting = false;
+    params.allow_min_difficulty_blocks = false;
+    params
+}
+
+async fn sync_with(
+    chain: Vec<bitcoin::block::Header>,
+) -> (HeaderChain, Result<bitcoin::BlockHash, BitcoinClientError>) {
+    let address = spawn_chain_node(Network::Regtest, chain).await;
+    let stream = Stream::new(&address.to_string(), 500).await.unwrap();
+    let mut client = BitcoinClient::with_network(stream.rx, stream.tx, Network::Regtest);
+    client.handshake().await.unwrap();
+    let mut headers = HeaderChain::new(Network::Regtest);
+    let result = client
+        .sync_headers(&mut headers, Duration::from_secs(5))
+        .await
+        .map(|tip| tip.hash);
+    (headers, result)
+}
+
+#[tokio::test]
+async fn headers_are_synced_in_batches_to_the_tip_of_the_node() {
+    let chain = regtest_chain(2500);
+    let (headers, result) = sync_with(chain.clone()).await;
+
+    assert_eq!(result.unwrap(), chain[2500].block_hash());
+    assert_eq!(headers.height(), 2500);
+    assert_eq!(headers.hash(1234), Some(chain[1234].block_hash()));
+    assert_eq!(headers.height_of(&chain[2000].block_hash()), Some(2000));
+}
+
+#[tokio::test]
+async fn headers_are_synced_when_the_chain_fills_exactly_one_batch() {
+    let chain = regtest_chain(2000);
+    let (headers, result) = sync_with(chain.clone()).await;
+
+    assert_eq!(result.unwrap(), chain[2000].block_hash());
+    assert_eq!(headers.height(), 2000);
+}
+
+#[tokio::test]
+async fn headers_that_do_not_meet_their_target_are_rejected() {
+    let mut chain = regtest_chain(10);
+    let bad = unmined_header(&chain);
+    chain.push(bad);
+    let (headers, result) = sync_with(chain).await;
+
+    match result {
+        Err(BitcoinClientError::InvalidHeader(HeaderError::BadProofOfWork { hash, height })) => {
+            assert_eq!(hash, bad.block_hash());
+            assert_eq!(height, 11);
+        }
+        other => panic!("Unexpected result {:?}", other),
+    }
+    assert_eq!(headers.height(), 10);
+}
+
+#[tokio::test]
+async fn headers_that_do_not_link_are_rejected() {
+    let mut chain = regtest_chain(10);
+    chain.remove(5);
+    let (headers, result) = sync_with(chain).await;
+
+    assert!(matches!(
+        result,
+        Err(BitcoinClientError::InvalidHeader(
+            HeaderError::Unconnected { .. }
+        ))
+    ));
+    assert_eq!(headers.height(), 4);
+}
+
+#[tokio::test]
+async fn sync_times_out_when_node_sends_no_headers() {
+    let address = spawn_local_node().await;
+    let stream = Stream::new(&address.to_string(), 500).await.unwrap();
+    let mut client = BitcoinClient::new(stream.rx, stream.tx);
+    client.handshake().await.unwrap();
+    let mut headers = HeaderChain::new(Network::Bitcoin);
+
+    let result = client
+        .sync_headers(&mut headers, Duration::from_millis(100))
+        .await;
+
+    assert!(format!("{:?}", result.unwrap_err()).contains("Timed out waiting for headers"));
+    assert_eq!(headers.height(), 0);
+}
+
+#[test]
+fn difficulty_is_retargeted_every_interval() {
+    let params = retargeting_params();
+    let interval = params.difficulty_adjustment_interval() as usize;
+    let mut chain = HeaderChain::with_params(params.clone());
+    let mut headers = vec![*chain.header(0).unwrap()];
+    let bits = headers[0].bits;
+    // Blocks every minute make the difficulty rise by the maximum of four
+    mine_headers(&mut headers, interval - 1, 60, bits);
+    assert_eq!(chain.extend(&headers[1..]).unwrap(), interval - 1);
+
+    let mut unchanged = headers.clone();
+    mine_headers(&mut unchanged, 1, 60, bits);
+    assert!(matches!(
+        chain.extend(&unchanged[interval..]),
+        Err(HeaderError::BadDifficulty { height, .. }) if height as usize == interval
+    ));
+
+    let harder = Target::from_compact(bits)
+        .min_difficulty_transition_threshold()
+        .to_compact_lossy();
+    mine_headers(&mut headers, 2, 60, harder);
+    assert_eq!(chain.extend(&headers[interval..]).unwrap(), 2);
+    assert_eq!(chain.height() as usize, interval + 1);
+    assert!(chain.tip().work > chain.header(0).unwrap().work());
+}
+
+#[test]
+fn difficulty_does_not_drop_below_the_minimum() {
+    let params = retargeting_params();
+    let interval = params.difficulty_adjustment_interval() as usize;
+    let mut chain = HeaderChain::with_params(params);
+    let mut headers = vec![*chain.header(0).unwrap()];
+    let bits = headers[0].bits;
+    // Slow blocks would lower the difficulty, but it already is the minimum
+    mine_headers(&mut headers, interval, 6000, bits);
+    assert_eq!(chain.extend(&headers[1..]).unwrap(), interval);
+}
+
+#[test]
+fn min_difficulty_blocks_are_allowed_after_a_long_gap() {
+    let mut params = retargeting_params();
+    params.allow_min_difficulty_blocks = true;
+    let interval = params.difficulty_adjustment_interval() as usize;
+    let mut chain = HeaderChain::with_params(params);
+    let mut headers = vec![*chain.header(0).unwrap()];
+    let minimum = headers[0].bits;
+    mine_headers(&mut headers, interval - 1, 60, minimum);
+    let harder = Target::from_compact(minimum)
+        .min_difficulty_transition_threshold()
+        .to_compact_lossy();
+    mine_headers(&mut headers, 2, 60, harder);
+    // A block more than 20 minutes after the previous one has the minimum
+    // difficulty, the ones after it are back to the last real difficulty
+    mine_headers(&mut headers, 1, 1201, minimum);
+    mine_headers(&mut headers, 1, 60, harder);
+    assert_eq!(chain.extend(&headers[1..]).unwrap(), interval + 3);
+
+    let mut early = headers.clone();
+    mine_headers(&mut early, 1, 60, minimum);
+    assert!(matches!(
+        chain.extend(&early[interval + 4..]),
+        Err(HeaderError::BadDifficulty { .. })
+    ));
+}
+
+#[test]
+fn locator_steps_back_exponentially_to_genesis() {
+    let headers = regtest_chain(100);
+    let mut chain = HeaderChain::new(Network::Regtest);
+    chain.extend(&headers[1..]).unwrap();
+
+    let locator = chain.locator();
+    let heights: Vec<u32> = locator
+        .iter()
+        .map(|hash| chain.height_of(hash).unwrap())
+        .collect();
+    assert_eq!(
+        heights,
+        vec![100, 99, 98, 97, 96, 95, 94, 93, 92, 91, 89, 85, 77, 61, 29, 0]
+    );
+}
+
+#[test]
+fn known_headers_are_skipped() {
+    let headers = regtest_chain(20);
+    let mut chain = HeaderChain::new(Network::Regtest);
+    assert_eq!(chain.extend(&headers[1..10]).unwrap(), 9);
+    assert_eq!(chain.extend(&headers[5..]).unwrap(), 11);
+    assert_eq!(chain.tip().hash, headers[20].block_hash());
+}
+
+#[test]
+fn branch_keeps_the_headers_since_the_last_difficulty_adjustment() {
+    let params = retargeting_params();
+    let interval = params.difficulty_adjustment_interval() as usize;
+    let mut chain = HeaderChain::with_params(params);
+    let mut headers = vec![*chain.header(0).unwrap()];
+    let bits = headers[0].bits;
+    mine_headers(&mut headers, interval - 1, 60, bits);
+    let harder = Target::from_compact(bits)
+        .min_difficulty_transition_threshold()
+        .to_compact_lossy();
+    mine_headers(&mut headers, 3, 60, harder);
+    chain.extend(&headers[1..]).unwrap();
+
+    // The header after the branch is retargeted against the first one
+    let mut branch = chain.branch(interval as u32 - 1).unwrap();
+    assert_eq!(branch.base_height(), 0);
+    assert_eq!(branch.tip(), chain.tip_at(interval as u32 - 1).unwrap());
+    assert_eq!(branch.extend(&headers[interval..]).unwrap(), 3);
+    assert_eq!(branch.tip(), chain.tip());
+
+    let branch = chain.branch(interval as u32 + 1).unwrap();
+    assert_eq!(branch.base_height() as usize, interval);
+    assert_eq!(branch.hashes().len(), 2);
+    assert!(chain.branch(chain.height() + 1).is_none());
+}
@@ -1,10 +1,33 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
 use bitcoin::{
+    absolute::LockTime,
+    bip158::{self, BlockFilter},
+    block::{self, Header},
+    blockdata::constants::genesis_block,
     consensus::serialize,
-    p2p::message::{NetworkMessage, RawNetworkMessage},
-    Network,
+    hashes::Hash,
+    p2p::{
+        address::Address,
+        message::{NetworkMessage, RawNetworkMessage},
+        message_blockdata::Inventory,
+        message_filter::{CFCheckpt, CFHeaders, CFilter},
+        message_network::{Reject, RejectReason, VersionMessage},
+        ServiceFlags,
+    },
+    transaction, Amount, Block, BlockHash, CompactTarget, FilterHash, FilterHeader, Network,
+    OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness, Wtxid,
 };
-use p2p_handshake_bitcoin::bitcoin::message::BitcoinMessage;
+use p2p_handshake_bitcoin::bitcoin::{connection::Connection, message::BitcoinMessage};
+use tokio::net::{TcpListener, TcpStream};
 use tokio_test::io::{Builder, Mock};
+use tracing_subscriber::fmt::MakeWriter;
 
 pub struct BitcoinNodeMock {
     pub reader: Mock,
@@ -93,3 +116,670 @@ impl BitcoinWrongMessage {
         )
     }
 }
+
+/// What a node started with [spawn_node] does in reply to a message
+pub enum Reply {
+    /// Sends the message
+    Send(NetworkMessage),
+    /// Waits before the next reply
+    Wait(Duration),
+    /// Closes the connection
+    Close,
+}
+
+/// Starts a node on localhost which completes the handshake on the network
+/// with the version message, and returns its address. Every connection
+/// gets its own handler from `handler`, which is passed every message the
+/// node receives, including version and verack after they are answered.
+pub async fn spawn_node<F, H>(network: Network, version: VersionMessage, handler: F) -> SocketAddr
+where
+    F: Fn() -> H + Send + 'static,
+    H: FnMut(&NetworkMessage) -> Vec<Reply> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    serve_node(listener, network, version, handler);
+    address
+}
+
+/// Serves the connections to the listener like [spawn_node]
+fn serve_node<F, H>(listener: TcpListener, network: Network, version: VersionMessage, handler: F)
+where
+    F: Fn() -> H + Send + 'static,
+    H: FnMut(&NetworkMessage) -> Vec<Reply> + Send + 'static,
+{
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(serve_connection(
+                socket,
+                network,
+                version.clone(),
+                handler(),
+            ));
+        }
+    });
+}
+
+async fn serve_connection<H>(
+    socket: TcpStream,
+    network: Network,
+    version: VersionMessage,
+    mut handler: H,
+) where
+    H: FnMut(&NetworkMessage) -> Vec<Reply>,
+{
+    let (rx, tx) = socket.into_split();
+    let mut connection = Connection::new(rx, tx);
+    while let Ok(Some((message, _))) = connection.read::<RawNetworkMessage>().await {
+        let mut replies = match message.payload() {
+            NetworkMessage::Version(_) => {
+                vec![Reply::Send(NetworkMessage::Version(version.clone()))]
+            }
+            NetworkMessage::Verack => vec![Reply::Send(NetworkMessage::Verack)],
+            _ => Vec::new(),
+        };
+        replies.extend(handler(message.payload()));
+        for reply in replies {
+            match reply {
+                Reply::Send(payload) => {
+                    let response = BitcoinMessage::message_for(network, payload);
+                    if connection.write(&serialize(&response)).await.is_err() {
+                        return;
+                    }
+                }
+                Reply::Wait(delay) => tokio::time::sleep(delay).await,
+                Reply::Close => return,
+            }
+        }
+    }
+}
+
+/// Starts a Bitcoin node on localhost which answers the handshake with the
+/// same messages the client sends, and returns its address.
+pub async fn spawn_local_node() -> SocketAddr {
+    spawn_local_node_at("127.0.0.1:0".parse().unwrap()).await
+}
+
+/// Starts a Bitcoin node like [spawn_local_node] on the provided address
+pub async fn spawn_local_node_at(address: SocketAddr) -> SocketAddr {
+    let listener = TcpListener::bind(address).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    serve_local_node(listener, Vec::new());
+    address
+}
+
+/// Starts a node on localhost which accepts connections but never writes
+/// to them, and returns its address
+pub async fn spawn_silent_node() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+    address
+}
+
+/// Starts a simulated network of Bitcoin nodes on localhost. Node `i`
+/// answers getaddr with the addresses of nodes listed in `peers[i]`.
+/// Indices past the last node stand for addresses nothing listens on.
+/// Returns the addresses of all nodes, followed by the unreachable ones.
+pub async fn spawn_local_network(peers: Vec<Vec<usize>>) -> Vec<SocketAddr> {
+    let mut listeners = Vec::new();
+    for _ in 0..peers.len() {
+        listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+    }
+    let mut addresses: Vec<SocketAddr> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+    let unreachable = peers.iter().flatten().max().map_or(0, |&max| max + 1);
+    for _ in peers.len()..unreachable {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        addresses.push(listener.local_addr().unwrap());
+    }
+    for (listener, peers) in listeners.into_iter().zip(peers) {
+        let peers = peers.iter().map(|&i| addresses[i]).collect();
+        serve_local_node(listener, peers);
+    }
+    addresses
+}
+
+/// Serves a node which answers getaddr with the peers
+fn serve_local_node(listener: TcpListener, peers: Vec<SocketAddr>) {
+    let version = BitcoinMessage::get_bitcoin_version_message();
+    serve_node(listener, Network::Bitcoin, version, move || {
+        let peers = peers.clone();
+        move |message: &NetworkMessage| match message {
+            NetworkMessage::GetAddr => vec![Reply::Send(NetworkMessage::Addr(
+                peers
+                    .iter()
+                    .map(|peer| (0, Address::new(peer, ServiceFlags::NETWORK)))
+                    .collect(),
+            ))],
+            _ => Vec::new(),
+        }
+    });
+}
+
+/// Mines `count` headers on top of the chain, `spacing` seconds apart and
+/// with the bits, by trying nonces until the hash meets the target
+pub fn mine_headers(chain: &mut Vec<Header>, count: usize, spacing: u32, bits: CompactTarget) {
+    for _ in 0..count {
+        let last = chain.last().unwrap();
+        let mut header = Header {
+            version: block::Version::TWO,
+            prev_blockhash: last.block_hash(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: last.time + spacing,
+            bits,
+            nonce: 0,
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        chain.push(header);
+    }
+}
+
+/// Returns a header on top of the chain whose hash does not meet its target
+pub fn unmined_header(chain: &[Header]) -> Header {
+    let last = chain.last().unwrap();
+    let mut header = Header {
+        version: block::Version::TWO,
+        prev_blockhash: last.block_hash(),
+        merkle_root: TxMerkleNode::all_zeros(),
+        time: last.time + 600,
+        bits: last.bits,
+        nonce: 0,
+    };
+    while header.validate_pow(header.target()).is_ok() {
+        header.nonce += 1;
+    }
+    header
+}
+
+/// Returns the genesis header of the network followed by `count` mined
+/// headers with the minimum difficulty of regtest
+pub fn regtest_chain(count: usize) -> Vec<Header> {
+    let genesis = genesis_block(Network::Regtest).header;
+    let mut chain = vec![genesis];
+    mine_headers(&mut chain, count, 600, genesis.bits);
+    chain
+}
+
+/// Starts a node on localhost which completes the handshake on the network
+/// and answers getheaders from the chain, which starts with the genesis
+/// header, and returns its address
+pub async fn spawn_chain_node(network: Network, chain: Vec<Header>) -> SocketAddr {
+    let start_height = chain.len() as i32 - 1;
+    spawn_chain_node_claiming(network, chain, start_height).await
+}
+
+/// Starts a node like [spawn_chain_node] which claims the start height in
+/// its version message, whatever the height of its chain
+pub async fn spawn_chain_node_claiming(
+    network: Network,
+    chain: Vec<Header>,
+    start_height: i32,
+) -> SocketAddr {
+    let mut version = BitcoinMessage::get_bitcoin_version_message();
+    version.start_height = start_height;
+    let chain = Arc::new(chain);
+    spawn_node(network, version, move || {
+        let chain = chain.clone();
+        move |message: &NetworkMessage| match message {
+            NetworkMessage::GetHeaders(request) => {
+                vec![Reply::Send(headers_after(&chain, &request.locator_hashes))]
+            }
+            _ => Vec::new(),
+        }
+    })
+    .await
+}
+
+/// Returns the headers message answering getheaders with the locator from
+/// the chain, which starts with the genesis header
+fn headers_after(chain: &[Header], locator: &[BlockHash]) -> NetworkMessage {
+    let start = locator
+        .iter()
+        .find_map(|hash| chain.iter().position(|h| h.block_hash() == *hash))
+        .map_or(0, |position| position + 1);
+    NetworkMessage::Headers(chain.iter().skip(start).take(2000).copied().collect())
+}
+
+/// Returns a transaction spending a made up output, whose witness makes
+/// its wtxid differ from its txid
+pub fn witness_transaction(seed: u8) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([seed; 32]), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&[vec![seed; 72], vec![seed; 33]]),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(1000 * seed as u64),
+            script_pubkey: ScriptBuf::new(),
+        }],
+    }
+}
+
+/// Returns a block on top of the header with a coinbase, which commits to
+/// the witnesses of the block, followed by the transactions. The header is
+/// mined with the bits of the previous one.
+pub fn segwit_block(previous: &Header, transactions: Vec<Transaction>) -> Block {
+    let coinbase = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::from_bytes(previous.time.to_le_bytes().to_vec()),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&[[0u8; 32]]),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(50 * 100_000_000),
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+    let mut block = Block {
+        header: Header {
+            version: block::Version::TWO,
+            prev_blockhash: previous.block_hash(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: previous.time + 600,
+            bits: previous.bits,
+            nonce: 0,
+        },
+        txdata: [vec![coinbase], transactions].concat(),
+    };
+    // The coinbase counts as zeros in the witness root, so adding the
+    // commitment to it does not change the root
+    let witness_root = block.witness_root().unwrap();
+    let commitment = Block::compute_witness_commitment(&witness_root, &[0u8; 32]);
+    let mut script = vec![0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+    script.extend_from_slice(commitment.as_byte_array());
+    block.txdata[0].output.push(TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::from_bytes(script),
+    });
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    while block.header.validate_pow(block.header.target()).is_err() {
+        block.header.nonce += 1;
+    }
+    block
+}
+
+/// Starts a node on localhost which completes the handshake on the network
+/// and answers getdata for blocks with the block stored under the requested
+/// hash, or with notfound, and returns its address
+pub async fn spawn_block_node(network: Network, blocks: HashMap<BlockHash, Block>) -> SocketAddr {
+    let blocks = Arc::new(blocks);
+    let version = BitcoinMessage::get_bitcoin_version_message();
+    spawn_node(network, version, move || {
+        let blocks = blocks.clone();
+        move |message: &NetworkMessage| {
+            let NetworkMessage::GetData(inventory) = message else {
+                return Vec::new();
+            };
+            let mut replies = Vec::new();
+            let mut missing = Vec::new();
+            for item in inventory {
+                match item {
+                    Inventory::Block(hash) | Inventory::WitnessBlock(hash) => {
+                        match blocks.get(hash) {
+                            Some(block) => {
+                                replies.push(Reply::Send(NetworkMessage::Block(block.clone())))
+                            }
+                            None => missing.push(*item),
+                        }
+                    }
+                    _ => missing.push(*item),
+                }
+            }
+            if !missing.is_empty() {
+                replies.push(Reply::Send(NetworkMessage::NotFound(missing)));
+            }
+            replies
+        }
+    })
+    .await
+}
+
+/// How a node started with [spawn_tx_node] answers the announcement of a
+/// transaction
+#[derive(Debug, Clone, Copy)]
+pub enum TxNodeReply {
+    /// Does not request the transaction
+    Ignore,
+    /// Requests the transaction by its txid
+    GetTx,
+    /// Requests the transaction by the wtxid, as the node can not tell it
+    /// from the txid
+    GetWtx(Wtxid),
+    /// Requests the transaction by its txid and rejects it as dust
+    GetAndReject,
+}
+
+/// Starts a node on localhost which completes the handshake on the network
+/// and answers transaction announcements as told. Returns its address and
+/// the transactions it received.
+pub async fn spawn_tx_node(
+    network: Network,
+    reply: TxNodeReply,
+) -> (SocketAddr, Arc<Mutex<Vec<Transaction>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let sink = received.clone();
+    let version = BitcoinMessage::get_bitcoin_version_message();
+    let address = spawn_node(network, version, move || {
+        let sink = sink.clone();
+        move |message: &NetworkMessage| match (message, reply) {
+            (NetworkMessage::Inv(_), TxNodeReply::Ignore) => Vec::new(),
+            (NetworkMessage::Inv(inventory), _) => {
+                let requested = inventory
+                    .iter()
+                    .filter_map(|item| match (item, reply) {
+                        (Inventory::Transaction(_), TxNodeReply::GetWtx(wtxid)) => {
+                            Some(Inventory::WTx(wtxid))
+                        }
+                        (Inventory::Transaction(txid), _) => Some(Inventory::Transaction(*txid)),
+                        _ => None,
+                    })
+                    .collect();
+                vec![Reply::Send(NetworkMessage::GetData(requested))]
+            }
+            (NetworkMessage::Tx(transaction), _) => {
+                sink.lock().unwrap().push(transaction.clone());
+                match reply {
+                    TxNodeReply::GetAndReject => {
+                        vec![Reply::Send(NetworkMessage::Reject(Reject {
+                            message: "tx".into(),
+                            ccode: RejectReason::Dust,
+                            reason: "dust".into(),
+                            hash: transaction.txid().to_raw_hash(),
+                        }))]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        }
+    })
+    .await;
+    (address, received)
+}
+
+/// Starts a node on localhost which completes the handshake on the network
+/// and, if asked to relay transactions, announces the transactions after
+/// the delay. Requests for them are answered with the transactions if it
+/// serves them, or with notfound. Returns its address.
+pub async fn spawn_relay_node(
+    network: Network,
+    transactions: Vec<Transaction>,
+    delay: Duration,
+    serve: bool,
+) -> SocketAddr {
+    let transactions = Arc::new(transactions);
+    let version = BitcoinMessage::get_bitcoin_version_message();
+    spawn_node(network, version, move || {
+        let transactions = transactions.clone();
+        let mut relay = false;
+        move |message: &NetworkMessage| match message {
+            NetworkMessage::Version(version) => {
+                relay = version.relay;
+                Vec::new()
+            }
+            NetworkMessage::Verack if relay => vec![
+                Reply::Wait(delay),
+                Reply::Send(NetworkMessage::Inv(
+                    transactions
+                        .iter()
+                        .map(|transaction| Inventory::Transaction(transaction.txid()))
+                        .collect(),
+                )),
+            ],
+            NetworkMessage::GetData(inventory) => {
+                let mut replies = Vec::new();
+                let mut missing = Vec::new();
+                for item in inventory {
+                    let found = match item {
+                        Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+                            transactions.iter().find(|t| t.txid() == *txid)
+                        }
+                        _ => None,
+                    };
+                    match found {
+                        Some(transaction) if serve => {
+                            replies.push(Reply::Send(NetworkMessage::Tx(transaction.clone())))
+                        }
+                        _ => missing.push(*item),
+                    }
+                }
+                if !missing.is_empty() {
+                    replies.push(Reply::Send(NetworkMessage::NotFound(missing)));
+                }
+                replies
+            }
+            _ => Vec::new(),
+        }
+    })
+    .await
+}
+
+/// Returns the regtest genesis block followed by `count` blocks with only
+/// a coinbase
+pub fn segwit_chain(count: usize) -> Vec<Block> {
+    let mut blocks = vec![genesis_block(Network::Regtest)];
+    for _ in 0..count {
+        let block = segwit_block(&blocks.last().unwrap().header, vec![]);
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// Blocks served by a node started with [spawn_filter_node], with their
+/// basic filters, the hashes of the filters sent in cfheaders, the
+/// checkpoints sent in cfcheckpt and filters sent ahead of every batch of
+/// requested filters. Each can be changed on its own to make the node
+/// misbehave.
+#[derive(Debug, Clone)]
+pub struct FilterStore {
+    pub blocks: Vec<Block>,
+    pub filters: Vec<BlockFilter>,
+    pub filter_hashes: Vec<FilterHash>,
+    pub checkpoints: Vec<FilterHeader>,
+    pub stray_filters: Vec<CFilter>,
+    /// How long to wait before sending each filter
+    pub filter_delay: Duration,
+}
+
+impl FilterStore {
+    /// Computes the filters of the blocks, which start with the genesis
+    /// block. Outputs spent from outside the blocks have empty scripts.
+    pub fn new(blocks: Vec<Block>) -> FilterStore {
+        let outputs: HashMap<OutPoint, ScriptBuf> = blocks
+            .iter()
+            .flat_map(|block| &block.txdata)
+            .flat_map(|tx| {
+                let txid = tx.txid();
+                tx.output.iter().enumerate().map(move |(vout, output)| {
+                    (
+                        OutPoint::new(txid, vout as u32),
+                        output.script_pubkey.clone(),
+                    )
+                })
+            })
+            .collect();
+        let filters: Vec<BlockFilter> = blocks
+            .iter()
+            .map(|block| {
+                BlockFilter::new_script_filter(block, |outpoint| {
+                    Ok::<_, bip158::Error>(outputs.get(outpoint).cloned().unwrap_or_default())
+                })
+                .unwrap()
+            })
+            .collect();
+        let filter_hashes: Vec<FilterHash> = filters
+            .iter()
+            .map(|filter| FilterHash::hash(&filter.content))
+            .collect();
+        let mut store = FilterStore {
+            blocks,
+            filters,
+            filter_hashes,
+            checkpoints: Vec::new(),
+            stray_filters: Vec::new(),
+            filter_delay: Duration::ZERO,
+        };
+        store.checkpoints = (1..=(store.blocks.len() - 1) / 1000)
+            .map(|i| store.filter_header(i * 1000))
+            .collect();
+        store
+    }
+
+    /// Returns the headers of the blocks
+    pub fn headers(&self) -> Vec<Header> {
+        self.blocks.iter().map(|block| block.header).collect()
+    }
+
+    /// Returns the filter header at the height committing to the hashes
+    /// sent in cfheaders
+    pub fn filter_header(&self, height: usize) -> FilterHeader {
+        self.filter_hashes[..=height]
+            .iter()
+            .fold(FilterHeader::all_zeros(), |previous, hash| {
+                hash.filter_header(&previous)
+            })
+    }
+
+    fn height_of(&self, hash: &BlockHash) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|block| block.block_hash() == *hash)
+    }
+
+    fn reply_to(&self, message: &NetworkMessage, serve: bool) -> Vec<Reply> {
+        let payloads = match message {
+            NetworkMessage::GetHeaders(request) => {
+                let start = request
+                    .locator_hashes
+                    .iter()
+                    .find_map(|hash| self.height_of(hash))
+                    .map_or(0, |height| height + 1);
+                vec![NetworkMessage::Headers(
+                    self.headers().into_iter().skip(start).take(2000).collect(),
+                )]
+            }
+            // Nodes without filters disconnect peers asking for them
+            NetworkMessage::GetCFCheckpt(_)
+            | NetworkMessage::GetCFHeaders(_)
+            | NetworkMessage::GetCFilters(_)
+                if !serve =>
+            {
+                return vec![Reply::Close];
+            }
+            NetworkMessage::GetCFCheckpt(request) => {
+                let Some(stop) = self.height_of(&request.stop_hash) else {
+                    return Vec::new();
+                };
+                vec![NetworkMessage::CFCheckpt(CFCheckpt {
+                    filter_type: request.filter_type,
+                    stop_hash: request.stop_hash,
+                    filter_headers: self.checkpoints[..stop / 1000].to_vec(),
+                })]
+            }
+            NetworkMessage::GetCFHeaders(request) => {
+                let Some(stop) = self.height_of(&request.stop_hash) else {
+                    return Vec::new();
+                };
+                let start = request.start_height as usize;
+                let previous_filter_header = match start {
+                    0 => FilterHeader::all_zeros(),
+                    _ => self.filter_header(start - 1),
+                };
+                vec![NetworkMessage::CFHeaders(CFHeaders {
+                    filter_type: request.filter_type,
+                    stop_hash: request.stop_hash,
+                    previous_filter_header,
+                    filter_hashes: self.filter_hashes[start..=stop].to_vec(),
+                })]
+            }
+            NetworkMessage::GetCFilters(request) => {
+                let Some(stop) = self.height_of(&request.stop_hash) else {
+                    return Vec::new();
+                };
+                let requested = (request.start_height as usize..=stop).map(|height| CFilter {
+                    filter_type: request.filter_type,
+                    block_hash: self.blocks[height].block_hash(),
+                    filter: self.filters[height].content.clone(),
+                });
+                return self
+                    .stray_filters
+                    .iter()
+                    .cloned()
+                    .chain(requested)
+                    .flat_map(|filter| {
+                        [
+                            Reply::Wait(self.filter_delay),
+                            Reply::Send(NetworkMessage::CFilter(filter)),
+                        ]
+                    })
+                    .collect();
+            }
+            _ => Vec::new(),
+        };
+        payloads.into_iter().map(Reply::Send).collect()
+    }
+}
+
+/// Starts a node on localhost which completes the handshake on the network,
+/// advertising compact filters if it serves them, and answers getheaders,
+/// getcfcheckpt, getcfheaders and getcfilters from the store. Returns its
+/// address.
+pub async fn spawn_filter_node(network: Network, store: FilterStore, serve: bool) -> SocketAddr {
+    let mut version = BitcoinMessage::get_bitcoin_version_message();
+    if serve {
+        version.services |= ServiceFlags::COMPACT_FILTERS;
+    }
+    version.start_height = store.blocks.len() as i32 - 1;
+    let store = Arc::new(store);
+    spawn_node(network, version, move || {
+        let store = store.clone();
+        move |message: &NetworkMessage| store.reply_to(message, serve)
+    })
+    .await
+}
+
+/// Log sink which keeps everything written to it
+#[derive(Clone, Default)]
+pub struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Buffer {
+    type Writer = Buffer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
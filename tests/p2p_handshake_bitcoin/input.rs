@@ -0,0 +1,56 @@
+use p2p_handshake_bitcoin::input::{collect_nodes, parse_nodes, read_nodes};
+
+#[test]
+fn line_list_skips_comments_and_empty_lines() {
+    let content =
+        "# mainnet nodes\n45.9.148.241:8333\n\n  95.105.172.171  # fast one\n#46.17.99.26\n";
+    let nodes = parse_nodes(content).unwrap();
+    assert_eq!(nodes, vec!["45.9.148.241:8333", "95.105.172.171"]);
+}
+
+#[test]
+fn getnodeaddresses_output_is_parsed() {
+    let content = r#"[
+        {"time": 1709061234, "services": 1033, "address": "45.9.148.241", "port": 8333, "network": "ipv4"},
+        {"time": 1709061235, "services": 1033, "address": "2001:db8::1", "port": 8334, "network": "ipv6"}
+    ]"#;
+    let nodes = parse_nodes(content).unwrap();
+    assert_eq!(nodes, vec!["45.9.148.241:8333", "[2001:db8::1]:8334"]);
+}
+
+#[test]
+fn getpeerinfo_output_wrapped_in_rpc_response_is_parsed() {
+    let content = r#"{"result": [
+        {"id": 0, "addr": "45.9.148.241:8333", "network": "ipv4", "subver": "/Satoshi:26.0.0/"},
+        {"id": 1, "addr": "[2001:db8::1]:8333", "network": "ipv6", "subver": "/Satoshi:25.1.0/"}
+    ], "error": null, "id": "curl"}"#;
+    let nodes = parse_nodes(content).unwrap();
+    assert_eq!(nodes, vec!["45.9.148.241:8333", "[2001:db8::1]:8333"]);
+}
+
+#[test]
+fn unsupported_json_is_rejected() {
+    assert!(parse_nodes(r#"[{"ip": "45.9.148.241"}]"#).is_err());
+    assert!(parse_nodes(r#"{"error": "unauthorized"}"#).is_err());
+    assert!(parse_nodes("[{not json").is_err());
+}
+
+#[test]
+fn line_list_starting_with_an_ipv6_address_is_not_json() {
+    let nodes = parse_nodes("[2001:db8::1]:8333\n1.2.3.4\n").unwrap();
+    assert_eq!(nodes, vec!["[2001:db8::1]:8333", "1.2.3.4"]);
+}
+
+#[test]
+fn nodes_are_collected_from_arguments_and_files() {
+    let path = std::env::temp_dir().join(format!("p2p_nodes_{}.txt", std::process::id()));
+    std::fs::write(&path, "10.0.0.2\n# comment\n10.0.0.3:8333\n").unwrap();
+    let path = path.to_str().unwrap().to_string();
+
+    let nodes = collect_nodes(&["10.0.0.1".to_string()], std::slice::from_ref(&path)).unwrap();
+    assert_eq!(nodes, vec!["10.0.0.1", "10.0.0.2", "10.0.0.3:8333"]);
+    assert_eq!(read_nodes(&path).unwrap().len(), 2);
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(read_nodes(&path).is_err());
+}
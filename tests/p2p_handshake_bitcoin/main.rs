@@ -1,3 +1,26 @@
+mod addrman;
 mod bitcoin_client;
+mod blocks;
+mod broadcast;
+mod capture;
+mod census;
 mod connection;
+mod crawler;
+mod filters;
+mod headers;
 mod helper;
+mod input;
+mod mempool;
+mod metrics;
+mod monitor;
+mod output;
+mod peer_address;
+mod peer_store;
+mod propagation;
+mod retry;
+mod seed;
+mod telemetry;
+mod timing;
+mod tips;
+mod transcript;
+mod wire;
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use bitcoin::{p2p::message::NetworkMessage, Network};
+use p2p_handshake_bitcoin::bitcoin::{
+    client_pool::PoolConfig,
+    mempool::{MempoolConfig, MempoolSubscription, MempoolWatcher},
+    message::BitcoinMessage,
+    peer_address::PeerAddress,
+};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::helper::{spawn_node, spawn_relay_node, witness_transaction};
+
+/// Handler state of a connection to a node, which tells when the handshake
+/// is done and, as it is dropped with the connection, when it is closed
+struct ConnectionProbe {
+    connected: UnboundedSender<()>,
+    closed: UnboundedSender<()>,
+}
+
+impl ConnectionProbe {
+    fn received(&self, message: &NetworkMessage) {
+        if let NetworkMessage::Verack = message {
+            let _ = self.connected.send(());
+        }
+    }
+}
+
+impl Drop for ConnectionProbe {
+    fn drop(&mut self) {
+        let _ = self.closed.send(());
+    }
+}
+
+fn subscribe(nodes: &[SocketAddr]) -> MempoolSubscription {
+    let pool_config = PoolConfig {
+        network: Network::Regtest,
+        ..PoolConfig::default()
+    };
+    let nodes = nodes
+        .iter()
+        .map(|&address| PeerAddress::from(address))
+        .collect();
+    MempoolWatcher::new(MempoolConfig::default(), pool_config).subscribe(nodes)
+}
+
+async fn next_within(subscription: &mut MempoolSubscription, wait: Duration) -> Option<String> {
+    tokio::time::timeout(wait, subscription.next())
+        .await
+        .ok()
+        .flatten()
+        .map(|observed| observed.txid.to_string())
+}
+
+#[tokio::test]
+async fn transactions_announced_by_several_nodes_are_streamed_once() {
+    let shared = witness_transaction(1);
+    let own = witness_transaction(2);
+    let started = SystemTime::now();
+    let first =
+        spawn_relay_node(Network::Regtest, vec![shared.clone()], Duration::ZERO, true).await;
+    let second = spawn_relay_node(
+        Network::Regtest,
+        vec![shared.clone(), own.clone()],
+        Duration::from_millis(100),
+        true,
+    )
+    .await;
+    let mut subscription = subscribe(&[first, second]);
+
+    let observed = subscription.next().await.unwrap();
+    assert_eq!(observed.txid, shared.txid());
+    assert_eq!(observed.wtxid, shared.wtxid());
+    assert_eq!(observed.transaction, shared);
+    assert_eq!(observed.peer, PeerAddress::from(first));
+    assert!(observed.first_seen >= started);
+
+    let observed = subscription.next().await.unwrap();
+    assert_eq!(observed.txid, own.txid());
+    assert_eq!(observed.peer, PeerAddress::from(second));
+    assert_eq!(
+        next_within(&mut subscription, Duration::from_millis(300)).await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn transaction_is_fetched_from_the_next_node_when_the_first_does_not_have_it() {
+    let transaction = witness_transaction(3);
+    let without = spawn_relay_node(
+        Network::Regtest,
+        vec![transaction.clone()],
+        Duration::ZERO,
+        false,
+    )
+    .await;
+    let with = spawn_relay_node(
+        Network::Regtest,
+        vec![transaction.clone()],
+        Duration::from_millis(200),
+        true,
+    )
+    .await;
+    let mut subscription = subscribe(&[without, with]);
+
+    let observed = subscription.next().await.unwrap();
+    assert_eq!(observed.transaction, transaction);
+    assert_eq!(observed.peer, PeerAddress::from(without));
+    assert_eq!(observed.announcements, 2);
+}
+
+#[tokio::test]
+async fn transactions_of_many_nodes_are_collected() {
+    let mut nodes = Vec::new();
+    let mut expected = HashSet::new();
+    for seed in 10..15 {
+        let transaction = witness_transaction(seed);
+        expected.insert(transaction.txid().to_string());
+        nodes.push(
+            spawn_relay_node(Network::Regtest, vec![transaction], Duration::ZERO, true).await,
+        );
+    }
+    let mut subscription = subscribe(&nodes);
+
+    let mut observed = HashSet::new();
+    while observed.len() < expected.len() {
+        observed.insert(
+            next_within(&mut subscription, Duration::from_secs(5))
+                .await
+                .unwrap(),
+        );
+    }
+    assert_eq!(observed, expected);
+}
+
+#[tokio::test]
+async fn subscription_ends_once_all_nodes_are_disconnected() {
+    let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    let mut subscription = subscribe(&[unreachable]);
+
+    assert!(subscription.next().await.is_none());
+}
+
+#[tokio::test]
+async fn dropping_the_subscription_disconnects_from_the_nodes() {
+    let (connected, mut on_connected) = mpsc::unbounded_channel();
+    let (closed, mut on_closed) = mpsc::unbounded_channel();
+    let version = BitcoinMessage::get_bitcoin_version_message();
+    let node = spawn_node(Network::Regtest, version, move || {
+        let probe = ConnectionProbe {
+            connected: connected.clone(),
+            closed: closed.clone(),
+        };
+        move |message: &NetworkMessage| {
+            probe.received(message);
+            Vec::new()
+        }
+    })
+    .await;
+    let subscription = subscribe(&[node]);
+    let wait = Duration::from_secs(5);
+    tokio::time::timeout(wait, on_connected.recv())
+        .await
+        .unwrap();
+
+    drop(subscription);
+
+    assert!(tokio::time::timeout(wait, on_closed.recv())
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn observed_transaction_is_written_as_json() {
+    let transaction = witness_transaction(4);
+    let node = spawn_relay_node(
+        Network::Regtest,
+        vec![transaction.clone()],
+        Duration::ZERO,
+        true,
+    )
+    .await;
+    let mut subscription = subscribe(&[node]);
+
+    let json = subscription.next().await.unwrap().to_json();
+    assert_eq!(json["txid"], transaction.txid().to_string());
+    assert_eq!(json["wtxid"], transaction.wtxid().to_string());
+    assert_eq!(json["peer"], node.to_string());
+    assert_eq!(json["announcements"], 1);
+    assert_eq!(json["size"], transaction.total_size());
+    assert_eq!(json["vsize"], transaction.vsize());
+    assert!(json["first_seen_ms"].as_u64().unwrap() > 0);
+}
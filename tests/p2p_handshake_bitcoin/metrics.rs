@@ -0,0 +1,90 @@
+use std::{sync::Arc, time::Duration};
+
+use p2p_handshake_bitcoin::{
+    bitcoin::{
+        client_pool::{BitcoinClientPool, PoolConfig},
+        retry::{ErrorKind, RetryPolicy},
+    },
+    metrics::Metrics,
+};
+
+use crate::helper::spawn_local_node;
+
+#[tokio::test]
+async fn pool_updates_metrics_of_every_attempt() {
+    let metrics = Arc::new(Metrics::new());
+    let config = PoolConfig {
+        metrics: Some(metrics.clone()),
+        ..PoolConfig::default()
+    };
+    let nodes = vec![
+        spawn_local_node().await.into(),
+        "127.0.0.1:1".parse().unwrap(),
+    ];
+    BitcoinClientPool::with_config(nodes, config)
+        .run()
+        .await
+        .unwrap();
+
+    assert_eq!(metrics.handshakes_attempted.get(), 2);
+    assert_eq!(metrics.handshakes_succeeded.get(), 1);
+    assert_eq!(
+        metrics
+            .handshakes_failed
+            .with_label_values(&["connection"])
+            .get(),
+        1
+    );
+    assert_eq!(metrics.connect_duration.get_sample_count(), 1);
+    assert_eq!(metrics.handshake_duration.get_sample_count(), 1);
+    let phase_count = |phase: &str| {
+        metrics
+            .phase_duration
+            .with_label_values(&[phase])
+            .get_sample_count()
+    };
+    assert_eq!(phase_count("dns"), 2);
+    assert_eq!(phase_count("connect"), 1);
+    assert_eq!(phase_count("verack"), 1);
+    assert!(metrics.bytes_sent.get() > 0);
+    assert!(metrics.bytes_received.get() > 0);
+    assert_eq!(metrics.active_connections.get(), 0);
+}
+
+#[tokio::test]
+async fn nodes_waiting_to_retry_are_not_active_connections() {
+    let metrics = Arc::new(Metrics::new());
+    let config = PoolConfig {
+        metrics: Some(metrics.clone()),
+        retry_policy: RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(500),
+            jitter: 0.0,
+            retryable: vec![ErrorKind::Connection],
+            ..RetryPolicy::default()
+        },
+        ..PoolConfig::default()
+    };
+    let pool = BitcoinClientPool::with_config(vec!["127.0.0.1:1".parse().unwrap()], config);
+    let run = tokio::spawn(pool.run());
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(metrics.active_connections.get(), 0);
+
+    let outcomes = run.await.unwrap().unwrap();
+    assert_eq!(outcomes[0].attempts.len(), 2);
+}
+
+#[test]
+fn metrics_are_encoded_in_text_format() {
+    let metrics = Metrics::new();
+    metrics
+        .handshakes_failed
+        .with_label_values(&["timeout"])
+        .inc_by(3);
+    metrics.connect_duration.observe(0.02);
+    let text = metrics.encode();
+    assert!(text.contains("# TYPE p2p_handshake_failures_total counter"));
+    assert!(text.contains("p2p_handshake_failures_total{kind=\"timeout\"} 3"));
+    assert!(text.contains("p2p_handshake_connect_duration_seconds_bucket{le=\"0.025\"} 1"));
+}
@@ -0,0 +1,123 @@
+use std::{sync::Arc, time::Duration};
+
+use p2p_handshake_bitcoin::{
+    bitcoin::{client_pool::PoolConfig, peer_address::PeerAddress},
+    metrics::Metrics,
+    monitor::{serve_status, Monitor, MonitorConfig, NodeState},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::helper::{spawn_local_node, spawn_local_node_at, spawn_silent_node};
+
+fn monitor(nodes: Vec<PeerAddress>, down_after: u32) -> Monitor {
+    let config = MonitorConfig {
+        down_after,
+        ..MonitorConfig::default()
+    };
+    Monitor::new(nodes, config, PoolConfig::default())
+}
+
+#[tokio::test]
+async fn reachable_node_is_up_after_first_check() {
+    let node: PeerAddress = spawn_local_node().await.into();
+    let monitor = monitor(vec![node.clone()], 1);
+    assert_eq!(monitor.status().snapshot()[0].state, NodeState::Unknown);
+
+    let transitions = monitor.check().await;
+    assert_eq!(transitions.len(), 1);
+    assert_eq!(
+        (transitions[0].from, transitions[0].to),
+        (NodeState::Unknown, NodeState::Up)
+    );
+    assert!(monitor.check().await.is_empty());
+    let status = &monitor.status().snapshot()[0];
+    assert_eq!(status.checks, 2);
+    assert_eq!(status.user_agent.as_deref(), Some("/Satoshi:26.0.0/"));
+}
+
+#[tokio::test]
+async fn node_is_down_after_configured_number_of_failures() {
+    let node: PeerAddress = "127.0.0.1:1".parse().unwrap();
+    let monitor = monitor(vec![node], 2);
+    assert!(monitor.check().await.is_empty());
+    let transitions = monitor.check().await;
+    assert_eq!(transitions[0].to, NodeState::Down);
+    let status = &monitor.status().snapshot()[0];
+    assert_eq!(status.consecutive_failures, 2);
+    assert!(status.error.is_some());
+}
+
+#[tokio::test]
+async fn silent_node_does_not_stall_the_check() {
+    let silent: PeerAddress = spawn_silent_node().await.into();
+    let reachable: PeerAddress = spawn_local_node().await.into();
+    let monitor = monitor(vec![silent.clone(), reachable], 1);
+
+    let transitions = tokio::time::timeout(Duration::from_secs(3), monitor.check())
+        .await
+        .unwrap();
+
+    assert_eq!(transitions.len(), 2);
+    let status = monitor
+        .status()
+        .snapshot()
+        .into_iter()
+        .find(|status| status.address == silent)
+        .unwrap();
+    assert_eq!(status.state, NodeState::Down);
+}
+
+#[tokio::test]
+async fn node_coming_back_is_up_again() {
+    // Reserve a port nothing listens on until the node is started
+    let address = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let monitor = monitor(vec![address.into()], 1);
+    assert_eq!(monitor.check().await[0].to, NodeState::Down);
+
+    spawn_local_node_at(address).await;
+    let transitions = monitor.check().await;
+    assert_eq!(
+        (transitions[0].from, transitions[0].to),
+        (NodeState::Down, NodeState::Up)
+    );
+    assert_eq!(monitor.status().snapshot()[0].consecutive_failures, 0);
+}
+
+#[tokio::test]
+async fn status_and_metrics_are_served_over_http() {
+    let node: PeerAddress = spawn_local_node().await.into();
+    let metrics = Arc::new(Metrics::new());
+    let pool_config = PoolConfig {
+        metrics: Some(metrics.clone()),
+        ..PoolConfig::default()
+    };
+    let monitor = Monitor::new(vec![node.clone()], MonitorConfig::default(), pool_config);
+    monitor.check().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(serve_status(listener, monitor.status(), metrics));
+
+    let get = |path: &'static str| async move {
+        let mut socket = TcpStream::connect(address).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    let response = get("/status").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let status: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(status[0]["state"], "up");
+    let response = get("/metrics").await;
+    assert!(response.contains(&format!("p2p_handshake_node_up{{node=\"{}\"}} 1", node)));
+    assert!(get("/unknown").await.starts_with("HTTP/1.1 404"));
+}
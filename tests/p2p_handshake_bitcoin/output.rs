@@ -0,0 +1,142 @@
+use p2p_handshake_bitcoin::{
+    bitcoin::{client_pool::BitcoinClientPool, retry::ErrorKind},
+    output::{NodeRecord, OutputFormat, OutputWriter},
+};
+
+use crate::helper::spawn_local_node;
+
+fn failed_record(address: &str) -> NodeRecord {
+    NodeRecord {
+        address: address.to_string(),
+        success: false,
+        error_kind: Some(ErrorKind::Timeout),
+        error: Some("Failed to initialize TCP stream: deadline has elapsed".to_string()),
+        attempts: 2,
+        peer_version: None,
+        user_agent: None,
+        services: None,
+        start_height: None,
+        latency_ms: None,
+        dns_ms: Some(0.02),
+        connect_ms: None,
+        first_byte_ms: None,
+        version_ms: None,
+        verack_ms: None,
+    }
+}
+
+fn write_records(format: OutputFormat, records: Vec<NodeRecord>) -> String {
+    let mut writer = OutputWriter::new(format, Vec::new());
+    for record in records {
+        writer.write(record).unwrap();
+    }
+    String::from_utf8(writer.finish().unwrap()).unwrap()
+}
+
+#[test]
+fn json_output_is_a_single_array() {
+    let output = write_records(
+        OutputFormat::Json,
+        vec![
+            failed_record("10.0.0.1:8333"),
+            failed_record("10.0.0.2:8333"),
+        ],
+    );
+    let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(value.as_array().unwrap().len(), 2);
+    assert_eq!(value[0]["error_kind"], "timeout");
+    assert_eq!(value[1]["address"], "10.0.0.2:8333");
+}
+
+#[test]
+fn ndjson_output_has_one_object_per_line() {
+    let output = write_records(
+        OutputFormat::Ndjson,
+        vec![
+            failed_record("10.0.0.1:8333"),
+            failed_record("10.0.0.2:8333"),
+        ],
+    );
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["success"], false);
+    assert_eq!(lines[0]["attempts"], 2);
+}
+
+#[test]
+fn csv_output_has_single_header_and_quoted_fields() {
+    let mut record = failed_record("10.0.0.1:8333");
+    record.error = Some("refused, \"twice\"".to_string());
+    let output = write_records(
+        OutputFormat::Csv,
+        vec![record, failed_record("10.0.0.2:8333")],
+    );
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("address,success,error_kind,error,"));
+    assert!(lines[1].contains("\"refused, \"\"twice\"\"\""));
+}
+
+#[tokio::test]
+async fn successful_handshake_is_recorded_with_peer_details() {
+    let address = spawn_local_node().await;
+    let outcomes = BitcoinClientPool::new(vec![address.to_string().parse().unwrap()], 500)
+        .run()
+        .await
+        .unwrap();
+    let record = NodeRecord::from(&outcomes[0]);
+    assert!(record.success);
+    assert_eq!(record.address, address.to_string());
+    assert_eq!(record.error_kind, None);
+    assert_eq!(record.peer_version, Some(70001));
+    assert_eq!(record.user_agent.as_deref(), Some("/Satoshi:26.0.0/"));
+    assert_eq!(record.services, Some(0));
+    assert!(record.latency_ms.is_some());
+    for phase_ms in [
+        record.dns_ms,
+        record.connect_ms,
+        record.first_byte_ms,
+        record.version_ms,
+        record.verack_ms,
+    ] {
+        assert!(phase_ms.is_some(), "{:?}", record);
+    }
+}
+
+#[test]
+fn table_output_lists_nodes_and_summary() {
+    let succeeded = |address: &str, latency_ms| NodeRecord {
+        address: address.to_string(),
+        success: true,
+        error_kind: None,
+        error: None,
+        attempts: 1,
+        peer_version: Some(70016),
+        user_agent: Some("/Satoshi:26.0.0/".to_string()),
+        services: Some(1 | 8),
+        start_height: Some(830000),
+        latency_ms: Some(latency_ms),
+        dns_ms: Some(0.02),
+        connect_ms: Some(latency_ms / 2.0),
+        first_byte_ms: Some(latency_ms / 4.0),
+        version_ms: Some(0.01),
+        verack_ms: Some(latency_ms / 4.0),
+    };
+    let output = write_records(
+        OutputFormat::Table,
+        vec![
+            succeeded("10.0.0.1:8333", 10.0),
+            succeeded("10.0.0.2:8333", 30.0),
+            failed_record("10.0.0.3:8333"),
+        ],
+    );
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].starts_with("NODE"));
+    assert!(lines[1].contains("ok") && lines[1].contains("NETWORK|WITNESS"));
+    assert!(lines[3].contains("timeout"));
+    assert!(output.contains("Nodes: 3, succeeded: 2, failed: 1 (timeout 1)"));
+    assert!(output.contains("min 10.0 ms, p50 10.0 ms, p90 30.0 ms, p99 30.0 ms, max 30.0 ms"));
+}
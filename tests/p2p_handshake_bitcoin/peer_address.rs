@@ -0,0 +1,84 @@
+use std::net::Ipv4Addr;
+
+use bitcoin::Network;
+use p2p_handshake_bitcoin::bitcoin::peer_address::{Host, PeerAddress, PeerAddressError};
+
+#[test]
+fn ipv4_address_without_port_gets_network_default_port() {
+    let address = PeerAddress::parse("45.9.148.241", Network::Bitcoin).unwrap();
+    assert_eq!(address.host, Host::Ipv4(Ipv4Addr::new(45, 9, 148, 241)));
+    assert_eq!(address.port, 8333);
+
+    let address = PeerAddress::parse("45.9.148.241", Network::Regtest).unwrap();
+    assert_eq!(address.port, 18444);
+}
+
+#[test]
+fn ipv6_address_is_parsed_with_and_without_brackets() {
+    let bracketed = PeerAddress::parse("[2001:db8::1]:8335", Network::Bitcoin).unwrap();
+    assert_eq!(bracketed.host, Host::Ipv6("2001:db8::1".parse().unwrap()));
+    assert_eq!(bracketed.port, 8335);
+    assert_eq!(bracketed.to_string(), "[2001:db8::1]:8335");
+
+    let bare = PeerAddress::parse("2001:db8::1", Network::Bitcoin).unwrap();
+    assert_eq!(bare.port, 8333);
+}
+
+#[test]
+fn ipv4_mapped_ipv6_address_is_canonicalised_to_ipv4() {
+    let address = PeerAddress::parse("[::ffff:10.0.0.1]:8333", Network::Bitcoin).unwrap();
+    assert_eq!(address, "10.0.0.1:8333".parse().unwrap());
+    assert_eq!(address.host, Host::Ipv4(Ipv4Addr::new(10, 0, 0, 1)));
+    assert_eq!(address.port, 8333);
+}
+
+#[test]
+fn hostname_and_onion_addresses_are_lowercased() {
+    let address = PeerAddress::parse("Seed.Bitcoin.Sipa.BE.", Network::Bitcoin).unwrap();
+    assert_eq!(address.to_string(), "seed.bitcoin.sipa.be:8333");
+
+    let onion = "VFVH3VNJBQOQH3QHUJQSYUGTTIXFQPVBYAFJM2YZVAKJCLSE7A3FFNQD.onion:8333";
+    let address = PeerAddress::parse(onion, Network::Bitcoin).unwrap();
+    assert!(matches!(address.host, Host::Onion(_)));
+    assert_eq!(address.to_string(), onion.to_ascii_lowercase());
+}
+
+#[test]
+fn invalid_addresses_are_rejected() {
+    let parse = |input| PeerAddress::parse(input, Network::Bitcoin);
+    assert_eq!(parse(""), Err(PeerAddressError::Empty));
+    for input in ["1.2.3.4:0", "1.2.3.4:99999", "[::1]8333"] {
+        assert!(matches!(
+            parse(input),
+            Err(PeerAddressError::InvalidPort(_))
+        ));
+    }
+    for input in ["[::1", "300.1.1.1", "bad_host:8333", "short.onion"] {
+        assert!(matches!(
+            parse(input),
+            Err(PeerAddressError::InvalidHost(_))
+        ));
+    }
+}
+
+#[test]
+fn duplicates_are_removed_and_invalid_entries_reported() {
+    let inputs = [
+        "10.0.0.1",
+        "10.0.0.1:8333",
+        "[::ffff:10.0.0.1]",
+        "example.com",
+        "EXAMPLE.com:8333",
+        "not an address",
+    ];
+    let parsed = PeerAddress::parse_all(inputs, Network::Bitcoin);
+    assert_eq!(
+        parsed.valid,
+        vec![
+            "10.0.0.1:8333".parse().unwrap(),
+            "example.com:8333".parse().unwrap()
+        ]
+    );
+    assert_eq!(parsed.invalid.len(), 1);
+    assert_eq!(parsed.invalid[0].0, "not an address");
+}
@@ -0,0 +1,155 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use p2p_handshake_bitcoin::{
+    bitcoin::{
+        client_pool::{BitcoinClientPool, PoolConfig},
+        peer_address::PeerAddress,
+        retry::ErrorKind,
+    },
+    output::OutputFormat,
+    peer_store::{export, Observation, PeerStore},
+};
+
+use crate::helper::spawn_local_node;
+
+/// Returns a path in the temporary directory which does not exist yet
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "p2p_handshake_bitcoin_{}_{}.ndjson",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn observation(address: &str, time: u64, latency_ms: Option<f64>) -> Observation {
+    Observation {
+        address: address.parse().unwrap(),
+        time,
+        success: latency_ms.is_some(),
+        error_kind: latency_ms.is_none().then_some(ErrorKind::Timeout),
+        services: latency_ms.map(|_| 9),
+        user_agent: latency_ms.map(|_| "/Satoshi:26.0.0/".to_string()),
+        latency_ms,
+    }
+}
+
+#[test]
+fn reopened_store_keeps_reachability_history() {
+    let path = temp_path("history");
+    let mut store = PeerStore::open(&path).unwrap();
+    store
+        .append(observation("10.0.0.1", 100, Some(20.0)))
+        .unwrap();
+    store.append(observation("10.0.0.1", 200, None)).unwrap();
+    store.append(observation("10.0.0.1", 300, None)).unwrap();
+    drop(store);
+
+    let store = PeerStore::open(&path).unwrap();
+    let peer = store.get(&"10.0.0.1".parse().unwrap()).unwrap();
+    assert_eq!(peer.attempts, 3);
+    assert_eq!(peer.successes, 1);
+    assert_eq!(peer.failure_streak, 2);
+    assert_eq!(peer.last_attempt, 300);
+    assert_eq!(peer.last_success, Some(100));
+    assert_eq!(peer.last_error_kind, Some(ErrorKind::Timeout));
+    assert_eq!(peer.user_agent.as_deref(), Some("/Satoshi:26.0.0/"));
+    assert!(!peer.is_known_good());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn best_prefers_reliable_and_fast_peers() {
+    let path = temp_path("best");
+    let mut store = PeerStore::open(&path).unwrap();
+    store
+        .append(observation("10.0.0.1", 1, Some(50.0)))
+        .unwrap();
+    store.append(observation("10.0.0.2", 1, None)).unwrap();
+    store.append(observation("10.0.0.2", 2, Some(5.0))).unwrap();
+    store
+        .append(observation("10.0.0.3", 1, Some(10.0)))
+        .unwrap();
+    store.append(observation("10.0.0.4", 1, Some(1.0))).unwrap();
+    store.append(observation("10.0.0.4", 2, None)).unwrap();
+
+    let best: Vec<String> = store.best(10).iter().map(|a| a.to_string()).collect();
+    assert_eq!(best, ["10.0.0.3:8333", "10.0.0.1:8333", "10.0.0.2:8333"]);
+    assert_eq!(store.best(1).len(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn unreadable_lines_are_skipped() {
+    let path = temp_path("corrupt");
+    let mut store = PeerStore::open(&path).unwrap();
+    store.append(observation("10.0.0.1", 1, Some(1.0))).unwrap();
+    drop(store);
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"{\"address\":\"10.0.0.2:8333\",\"ti")
+        .unwrap();
+    drop(file);
+
+    let store = PeerStore::open(&path).unwrap();
+    assert_eq!(store.peers().len(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn observations_after_a_truncated_line_are_kept() {
+    let path = temp_path("truncated");
+    std::fs::write(&path, b"{\"address\":\"10.0.0.2:8333\",\"ti").unwrap();
+    let mut store = PeerStore::open(&path).unwrap();
+    store.append(observation("10.0.0.1", 1, Some(1.0))).unwrap();
+    drop(store);
+
+    let store = PeerStore::open(&path).unwrap();
+    assert_eq!(store.peers().len(), 1);
+    assert!(store.get(&"10.0.0.1".parse().unwrap()).is_some());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn peers_are_exported_as_csv() {
+    let path = temp_path("export");
+    let mut store = PeerStore::open(&path).unwrap();
+    store.append(observation("10.0.0.1", 1, Some(1.0))).unwrap();
+    store.append(observation("[::1]:18333", 2, None)).unwrap();
+
+    let mut output = Vec::new();
+    export(&mut output, &store.peers(), OutputFormat::Csv).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("address,last_attempt,last_success,failure_streak,"));
+    assert!(lines[1].starts_with("[::1]:18333,2,,1,"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn pool_records_outcomes_in_the_store() {
+    let path = temp_path("pool");
+    let store = Arc::new(Mutex::new(PeerStore::open(&path).unwrap()));
+    let reachable: PeerAddress = spawn_local_node().await.to_string().parse().unwrap();
+    let config = PoolConfig {
+        peer_store: Some(store.clone()),
+        ..PoolConfig::default()
+    };
+    BitcoinClientPool::with_config(vec![reachable.clone()], config)
+        .run()
+        .await
+        .unwrap();
+
+    let store = store.lock().unwrap();
+    assert_eq!(store.best(10), vec![reachable.clone()]);
+    assert_eq!(store.get(&reachable).unwrap().services, Some(0));
+    std::fs::remove_file(&path).unwrap();
+}
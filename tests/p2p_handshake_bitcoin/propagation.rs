@@ -0,0 +1,170 @@
+use std::{net::SocketAddr, time::Duration};
+
+use bitcoin::{hashes::Hash, BlockHash, Network, Txid};
+use p2p_handshake_bitcoin::{
+    bitcoin::{
+        client_pool::PoolConfig,
+        peer_address::PeerAddress,
+        propagation::{
+            AnnouncementLog, InvItem, PeerPropagation, PropagationConfig, PropagationMonitor,
+            PropagationReport,
+        },
+    },
+    output::ReportFormat,
+};
+
+use crate::helper::{spawn_relay_node, witness_transaction};
+
+fn node(port: u16) -> PeerAddress {
+    format!("127.0.0.1:{}", port).parse().unwrap()
+}
+
+fn ms(value: u64) -> Duration {
+    Duration::from_millis(value)
+}
+
+fn transaction(byte: u8) -> InvItem {
+    InvItem::Transaction(Txid::from_byte_array([byte; 32]))
+}
+
+/// Log with two transactions and a block announced by several nodes, and a
+/// transaction only the third node announced
+fn sample_log() -> AnnouncementLog {
+    let mut log = AnnouncementLog::new();
+    log.record(transaction(1), &node(1), ms(0));
+    log.record(transaction(1), &node(2), ms(100));
+    log.record(transaction(1), &node(3), ms(300));
+    log.record(transaction(2), &node(2), ms(50));
+    log.record(transaction(2), &node(1), ms(60));
+    log.record(transaction(3), &node(3), ms(10));
+    let block = InvItem::Block(BlockHash::from_byte_array([9; 32]));
+    log.record(block, &node(1), ms(1000));
+    log.record(block, &node(2), ms(2000));
+    log
+}
+
+fn peer(report: &PropagationReport, port: u16) -> &PeerPropagation {
+    report
+        .peers
+        .iter()
+        .find(|peer| peer.node == node(port))
+        .unwrap()
+}
+
+#[test]
+fn delays_are_measured_from_the_first_announcement() {
+    let report = PropagationReport::analyze(&sample_log(), 2);
+
+    assert_eq!(report.transactions, 2);
+    assert_eq!(report.blocks, 1);
+    let delays = report.transaction_delays.unwrap();
+    assert_eq!(delays.count, 3);
+    assert_eq!(delays.p50_ms, 100.0);
+    assert_eq!(delays.max_ms, 300.0);
+    assert_eq!(report.block_delays.unwrap().max_ms, 1000.0);
+
+    assert_eq!(peer(&report, 1).announced, 3);
+    assert_eq!(peer(&report, 1).first, 2);
+    assert_eq!(peer(&report, 1).transactions.unwrap().max_ms, 10.0);
+    assert_eq!(peer(&report, 2).first, 1);
+    assert_eq!(peer(&report, 2).blocks.unwrap().p50_ms, 1000.0);
+    // Transaction only the third node announced has nothing to compare with
+    assert_eq!(peer(&report, 3).announced, 2);
+    assert_eq!(peer(&report, 3).first, 0);
+    assert_eq!(peer(&report, 3).transactions.unwrap().count, 1);
+
+    let order: Vec<PeerAddress> = report.peers.iter().map(|p| p.node.clone()).collect();
+    assert_eq!(order, vec![node(1), node(2), node(3)]);
+}
+
+#[test]
+fn items_announced_by_fewer_nodes_than_the_minimum_are_not_counted() {
+    let report = PropagationReport::analyze(&sample_log(), 3);
+
+    assert_eq!(report.transactions, 1);
+    assert_eq!(report.blocks, 0);
+    assert_eq!(report.transaction_delays.unwrap().count, 2);
+    assert!(report.block_delays.is_none());
+    assert_eq!(peer(&report, 2).announced, 3);
+}
+
+#[test]
+fn later_announcements_by_the_same_node_are_ignored() {
+    let mut log = AnnouncementLog::new();
+    log.record(transaction(1), &node(1), ms(0));
+    log.record(transaction(1), &node(2), ms(500));
+    log.record(transaction(1), &node(2), ms(200));
+    log.record(transaction(1), &node(1), ms(900));
+
+    let report = PropagationReport::analyze(&log, 2);
+
+    assert_eq!(report.transaction_delays.unwrap().max_ms, 200.0);
+}
+
+#[tokio::test]
+async fn announcements_of_connected_nodes_are_recorded_for_the_duration() {
+    let transaction = witness_transaction(5);
+    let mut nodes: Vec<SocketAddr> = Vec::new();
+    for delay in [0, 150, 300] {
+        nodes.push(
+            spawn_relay_node(Network::Regtest, vec![transaction.clone()], ms(delay), true).await,
+        );
+    }
+    let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    let config = PropagationConfig {
+        duration: ms(800),
+        ..PropagationConfig::default()
+    };
+    let pool_config = PoolConfig {
+        network: Network::Regtest,
+        ..PoolConfig::default()
+    };
+    let all = nodes
+        .iter()
+        .chain([&unreachable])
+        .map(|&address| PeerAddress::from(address))
+        .collect();
+
+    let report = PropagationMonitor::new(config, pool_config)
+        .run(all, |_| Ok(()))
+        .await
+        .unwrap();
+
+    assert_eq!(report.attempted, 4);
+    assert_eq!(report.connected, 3);
+    assert_eq!(report.transactions, 1);
+    let order: Vec<PeerAddress> = report.peers.iter().map(|p| p.node.clone()).collect();
+    let expected: Vec<PeerAddress> = nodes.iter().map(|&a| PeerAddress::from(a)).collect();
+    assert_eq!(order, expected);
+    assert_eq!(report.peers[0].first, 1);
+    let slowest = report.peers[2].transactions.unwrap().max_ms;
+    assert!(slowest >= 200.0, "{}", slowest);
+}
+
+#[test]
+fn report_lists_the_delays_of_every_node() {
+    let mut report = PropagationReport::analyze(&sample_log(), 2);
+    report.attempted = 4;
+    report.connected = 3;
+
+    let mut text = Vec::new();
+    report.write(&mut text, ReportFormat::Report).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "Nodes: 4, connected: 3, announcing: 3");
+    assert_eq!(
+        lines[1],
+        "Transactions: 2, delay mean 136.7 ms, p50 100.0 ms, p90 300.0 ms, p99 300.0 ms, max 300.0 ms"
+    );
+    assert!(lines[2].starts_with("Blocks: 1, delay mean 1000.0 ms"));
+    assert!(lines[4].starts_with("NODE"));
+    assert!(lines[5].starts_with("127.0.0.1:1 "));
+
+    let mut json = Vec::new();
+    report.write(&mut json, ReportFormat::Json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["transactions"], 2);
+    assert_eq!(json["transaction_delays"]["p50_ms"], 100.0);
+    assert_eq!(json["peers"][0]["node"], "127.0.0.1:1");
+    assert_eq!(json["peers"][0]["first"], 2);
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use p2p_handshake_bitcoin::bitcoin::{
+    client::BitcoinClient,
+    client_pool::{BitcoinClientPool, PoolConfig},
+    retry::{ErrorKind, RetryPolicy},
+};
+
+use crate::helper::{spawn_silent_node, BitcoinNodeMock};
+
+#[test]
+fn retry_delay_grows_exponentially_up_to_maximum() {
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(350),
+        jitter: 0.0,
+        ..RetryPolicy::default()
+    };
+    assert_eq!(policy.delay(1), Duration::from_millis(100));
+    assert_eq!(policy.delay(2), Duration::from_millis(200));
+    assert_eq!(policy.delay(3), Duration::from_millis(350));
+}
+
+#[test]
+fn retry_delay_stays_within_jitter() {
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(100),
+        jitter: 0.5,
+        ..RetryPolicy::default()
+    };
+    for _ in 0..100 {
+        let delay = policy.delay(1);
+        assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(150));
+    }
+}
+
+#[tokio::test]
+async fn too_old_version_is_not_retryable() {
+    let bitcoin_node_mock =
+        BitcoinNodeMock::on_version_message_respond_with_malicious_version_message();
+    let mut bitcoin_client = BitcoinClient::new(bitcoin_node_mock.reader, bitcoin_node_mock.writer);
+    let error = anyhow::anyhow!(bitcoin_client.handshake().await.unwrap_err());
+    let kind = ErrorKind::of(&error);
+    assert_eq!(kind, ErrorKind::VersionTooOld);
+    assert!(!RetryPolicy::default().should_retry(1, kind));
+}
+
+#[tokio::test]
+async fn refused_connection_is_retried_until_max_attempts() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let node = listener.local_addr().unwrap().to_string().parse().unwrap();
+    drop(listener);
+
+    let config = PoolConfig {
+        retry_policy: RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        },
+        ..PoolConfig::default()
+    };
+    let outcomes = BitcoinClientPool::with_config(vec![node], config)
+        .run()
+        .await
+        .unwrap();
+    let outcome = &outcomes[0];
+    assert!(outcome.result.is_err());
+    assert_eq!(outcome.attempts.len(), 3);
+    for (i, attempt) in outcome.attempts.iter().enumerate() {
+        assert_eq!(attempt.number, i as u32 + 1);
+        assert_eq!(attempt.error.as_ref().unwrap().kind, ErrorKind::Connection);
+    }
+}
+
+#[test]
+fn nan_jitter_leaves_the_delay_unchanged() {
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(100),
+        jitter: f64::NAN,
+        ..RetryPolicy::default()
+    };
+    assert_eq!(policy.delay(1), Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn stalled_handshake_times_out_and_is_retried() {
+    let node = spawn_silent_node().await.to_string().parse().unwrap();
+
+    let config = PoolConfig {
+        timeout: 100,
+        retry_policy: RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        },
+        ..PoolConfig::default()
+    };
+    let outcomes = tokio::time::timeout(
+        Duration::from_secs(3),
+        BitcoinClientPool::with_config(vec![node], config).run(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    let outcome = &outcomes[0];
+    assert!(outcome.result.is_err());
+    assert_eq!(outcome.attempts.len(), 2);
+    for attempt in &outcome.attempts {
+        assert_eq!(attempt.error.as_ref().unwrap().kind, ErrorKind::Timeout);
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr},
+    sync::Mutex,
+};
+
+use bitcoin::{p2p::ServiceFlags, Network};
+use p2p_handshake_bitcoin::bitcoin::{
+    peer_address::PeerAddress,
+    seed::{dns_seeds, resolve_seeds, Resolver},
+};
+
+/// Resolver answering from a fixed table and remembering the queried hosts
+#[derive(Default)]
+struct StubResolver {
+    records: HashMap<String, Vec<IpAddr>>,
+    queried: Mutex<Vec<String>>,
+}
+
+impl Resolver for StubResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        self.queried.lock().unwrap().push(host.to_string());
+        self.records
+            .get(host)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such host"))
+    }
+}
+
+fn ip(last: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+}
+
+#[tokio::test]
+async fn seeds_are_resolved_deduplicated_and_failures_skipped() {
+    let seeds = dns_seeds(Network::Testnet);
+    let resolver = StubResolver {
+        records: HashMap::from([
+            (seeds[0].to_string(), vec![ip(1), ip(2)]),
+            (
+                seeds[1].to_string(),
+                vec![ip(2), "2001:db8::1".parse().unwrap()],
+            ),
+        ]),
+        ..StubResolver::default()
+    };
+    let nodes = resolve_seeds(&resolver, Network::Testnet, None).await;
+    let expected: Vec<PeerAddress> = ["10.0.0.1", "10.0.0.2", "[2001:db8::1]"]
+        .iter()
+        .map(|node| PeerAddress::parse(node, Network::Testnet).unwrap())
+        .collect();
+    assert_eq!(nodes, expected);
+    assert_eq!(nodes[0].port, 18333);
+    assert_eq!(resolver.queried.lock().unwrap().len(), seeds.len());
+}
+
+#[tokio::test]
+async fn service_flags_select_filtering_subdomain() {
+    let seeds = dns_seeds(Network::Signet);
+    let resolver = StubResolver {
+        records: HashMap::from([(format!("x9.{}", seeds[0]), vec![ip(1)])]),
+        ..StubResolver::default()
+    };
+    let services = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
+    let nodes = resolve_seeds(&resolver, Network::Signet, Some(services)).await;
+    assert_eq!(nodes.len(), 1);
+    assert!(resolver
+        .queried
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|host| host.starts_with("x9.")));
+}
@@ -0,0 +1,229 @@
+use std::path::PathBuf;
+#[cfg(feature = "otlp")]
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc,
+    thread,
+};
+
+#[cfg(feature = "otlp")]
+use p2p_handshake_bitcoin::bitcoin::client_pool::BitcoinClientPool;
+#[cfg(feature = "otlp")]
+use p2p_handshake_bitcoin::telemetry::otlp_layer;
+use p2p_handshake_bitcoin::telemetry::{
+    get_subscriber, get_subscriber_with_layer, log_file_writer, LogFormat, LogRotation,
+};
+use tracing_subscriber::layer::Identity;
+
+#[cfg(feature = "otlp")]
+use crate::helper::spawn_local_node;
+use crate::helper::Buffer;
+
+/// Logs a record at info and debug level in the format and returns the output
+fn log_with(format: LogFormat, level: &str) -> String {
+    let buffer = Buffer::default();
+    let subscriber = get_subscriber_with_layer(
+        "test".into(),
+        level.into(),
+        format,
+        buffer.clone(),
+        Identity::new(),
+    );
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("Performing handshake", node = "127.0.0.1:8333");
+        let _entered = span.enter();
+        tracing::info!(attempts = 1, "Handshake done");
+        tracing::debug!("Handshake details");
+    });
+    buffer.contents()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "p2p_handshake_bitcoin_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+#[test]
+fn bunyan_format_is_the_default() {
+    let buffer = Buffer::default();
+    let subscriber = get_subscriber("test".into(), "info".into(), buffer.clone());
+    tracing::subscriber::with_default(subscriber, || tracing::info!("Handshake done"));
+    let record: serde_json::Value = serde_json::from_str(buffer.contents().trim()).unwrap();
+    assert_eq!(record["name"], "test");
+    assert_eq!(record["msg"], "Handshake done");
+    assert_eq!(record["level"], 30);
+}
+
+#[test]
+fn compact_and_pretty_formats_are_human_readable() {
+    let compact = log_with(LogFormat::Compact, "info");
+    assert_eq!(compact.lines().count(), 1, "{}", compact);
+    assert!(compact.contains(" INFO "), "{}", compact);
+    assert!(compact.contains("Handshake done"), "{}", compact);
+    assert!(compact.contains("attempts=1"), "{}", compact);
+
+    let pretty = log_with(LogFormat::Pretty, "info");
+    assert!(pretty.lines().count() > 1, "{}", pretty);
+    assert!(pretty.contains("Handshake done"), "{}", pretty);
+    assert!(
+        pretty.contains("Performing handshake with node"),
+        "{}",
+        pretty
+    );
+}
+
+#[test]
+fn json_format_writes_one_object_per_record() {
+    let json = log_with(LogFormat::Json, "info");
+    let record: serde_json::Value = serde_json::from_str(json.trim()).unwrap();
+    assert_eq!(record["level"], "INFO");
+    assert_eq!(record["fields"]["message"], "Handshake done");
+    assert_eq!(record["fields"]["attempts"], 1);
+    assert_eq!(record["span"]["name"], "Performing handshake");
+}
+
+#[test]
+fn level_filters_the_records() {
+    let info = log_with(LogFormat::Compact, "info");
+    assert!(!info.contains("Handshake details"), "{}", info);
+    let debug = log_with(LogFormat::Compact, "debug");
+    assert_eq!(debug.lines().count(), 2, "{}", debug);
+    let warn = log_with(LogFormat::Compact, "warn");
+    assert!(warn.is_empty(), "{}", warn);
+}
+
+#[test]
+fn logs_are_written_to_the_log_file() {
+    let directory = temp_path("log_never");
+    let path = directory.join("handshake.log");
+    let writer = log_file_writer(path.to_str().unwrap(), LogRotation::Never, None).unwrap();
+    let subscriber = get_subscriber_with_layer(
+        "test".into(),
+        "info".into(),
+        LogFormat::Compact,
+        writer,
+        Identity::new(),
+    );
+    tracing::subscriber::with_default(subscriber, || tracing::info!("Handshake done"));
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(contents.contains("Handshake done"), "{}", contents);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn rotated_log_files_are_named_after_the_period() {
+    let directory = temp_path("log_daily");
+    let path = directory.join("handshake.log");
+    let writer = log_file_writer(path.to_str().unwrap(), LogRotation::Daily, Some(2)).unwrap();
+    let subscriber = get_subscriber("test".into(), "info".into(), writer);
+    tracing::subscriber::with_default(subscriber, || tracing::info!("Handshake done"));
+    let names: Vec<String> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names.len(), 1, "{:?}", names);
+    assert!(names[0].starts_with("handshake.log."), "{:?}", names);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+/// Request received by the collector stand-in
+#[cfg(feature = "otlp")]
+struct ExportRequest {
+    request_line: String,
+    body: Vec<u8>,
+}
+
+/// Accepts OTLP/HTTP exports like a collector would and passes them on.
+/// It runs on its own thread, as the exporter blocks while sending.
+#[cfg(feature = "otlp")]
+fn spawn_collector() -> (String, mpsc::Receiver<ExportRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for socket in listener.incoming() {
+            let mut socket = BufReader::new(socket.unwrap());
+            let mut request_line = String::new();
+            socket.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                socket.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            socket.read_exact(&mut body).unwrap();
+            // Passed on before responding, so the export is complete once
+            // the exporter got the response
+            let request = ExportRequest {
+                request_line: request_line.trim().to_string(),
+                body,
+            };
+            if sender.send(request).is_err() {
+                return;
+            }
+            socket
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+        }
+    });
+    (endpoint, receiver)
+}
+
+#[cfg(feature = "otlp")]
+fn contains(body: &[u8], name: &str) -> bool {
+    body.windows(name.len())
+        .any(|window| window == name.as_bytes())
+}
+
+#[cfg(feature = "otlp")]
+#[tokio::test]
+async fn handshake_spans_are_exported_to_the_collector() {
+    let (endpoint, requests) = spawn_collector();
+    let (layer, provider) = otlp_layer("p2p_handshake_bitcoin".into(), &endpoint).unwrap();
+    let subscriber = get_subscriber_with_layer(
+        "test".into(),
+        "info".into(),
+        LogFormat::Bunyan,
+        std::io::sink,
+        layer,
+    );
+    let guard = tracing::subscriber::set_default(subscriber);
+
+    let nodes = vec![spawn_local_node().await.into()];
+    let outcomes = BitcoinClientPool::new(nodes, 500).run().await.unwrap();
+    assert!(outcomes[0].result.is_ok());
+    drop(guard);
+    tokio::task::spawn_blocking(move || provider.shutdown().unwrap())
+        .await
+        .unwrap();
+
+    let mut body = Vec::new();
+    for request in requests.try_iter() {
+        assert_eq!(request.request_line, "POST /v1/traces HTTP/1.1");
+        body.extend(request.body);
+    }
+    for span in [
+        "Performing handshake",
+        "Connecting",
+        "Exchanging version",
+        "Exchanging verack",
+    ] {
+        assert!(contains(&body, span), "{} was not exported", span);
+    }
+    assert!(contains(&body, "p2p_handshake_bitcoin"));
+}
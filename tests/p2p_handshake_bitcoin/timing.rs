@@ -0,0 +1,109 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use bitcoin::{
+    consensus::serialize,
+    p2p::message::{NetworkMessage, RawNetworkMessage},
+};
+use p2p_handshake_bitcoin::bitcoin::{
+    client_pool::BitcoinClientPool,
+    connection::Connection,
+    message::BitcoinMessage,
+    timing::{Phase, PhaseTimings},
+};
+use tokio::net::TcpListener;
+
+use crate::helper::spawn_local_node;
+
+/// Starts a node which answers the version message at once, but waits
+/// before answering the verack message
+async fn spawn_slow_verack_node(delay: Duration) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (rx, tx) = socket.into_split();
+        let mut connection = Connection::new(rx, tx);
+        while let Ok(Some((message, _))) = connection.read::<RawNetworkMessage>().await {
+            let response = match message.payload() {
+                NetworkMessage::Version(_) => BitcoinMessage::version_message(),
+                NetworkMessage::Verack => {
+                    tokio::time::sleep(delay).await;
+                    BitcoinMessage::verack_message()
+                }
+                _ => continue,
+            };
+            connection.write(&serialize(&response)).await.unwrap();
+        }
+    });
+    address
+}
+
+#[test]
+fn phases_are_measured_from_the_end_of_the_previous_one() {
+    let start = Instant::now();
+    let mut timings = PhaseTimings::start_at(start);
+    timings.mark_at(Phase::Dns, start + Duration::from_millis(2));
+    timings.mark_at(Phase::Connect, start + Duration::from_millis(10));
+    timings.mark_at(Phase::Version, start + Duration::from_millis(25));
+    // Phases end only once
+    timings.mark_at(Phase::Dns, start + Duration::from_millis(30));
+
+    assert_eq!(timings.get(Phase::Dns), Some(Duration::from_millis(2)));
+    assert_eq!(timings.get(Phase::FirstByte), None);
+    assert_eq!(
+        timings.phases(),
+        vec![
+            (Phase::Dns, Duration::from_millis(2)),
+            (Phase::Connect, Duration::from_millis(8)),
+            (Phase::Version, Duration::from_millis(15)),
+        ]
+    );
+    assert_eq!(
+        timings.to_string(),
+        "dns=2.00ms connect=8.00ms version=15.00ms"
+    );
+}
+
+#[tokio::test]
+async fn successful_attempt_records_every_phase_in_order() {
+    let nodes = vec![spawn_local_node().await.into()];
+    let outcomes = BitcoinClientPool::new(nodes, 500).run().await.unwrap();
+    let attempt = &outcomes[0].attempts[0];
+
+    let mut previous = Duration::ZERO;
+    for phase in Phase::ALL {
+        let end = attempt.timings.get(phase).unwrap();
+        assert!(
+            end >= previous,
+            "{:?} ended before the previous phase",
+            phase
+        );
+        previous = end;
+    }
+    assert!(previous <= attempt.elapsed);
+}
+
+#[tokio::test]
+async fn failed_connection_records_only_the_resolution() {
+    let nodes = vec!["127.0.0.1:1".parse().unwrap()];
+    let outcomes = BitcoinClientPool::new(nodes, 500).run().await.unwrap();
+    let timings = outcomes[0].timings().unwrap();
+    assert!(timings.get(Phase::Dns).is_some());
+    assert_eq!(timings.get(Phase::Connect), None);
+    assert_eq!(timings.phases().len(), 1);
+}
+
+#[tokio::test]
+async fn slow_verack_shows_in_the_verack_phase() {
+    let delay = Duration::from_millis(200);
+    let nodes = vec![spawn_slow_verack_node(delay).await.into()];
+    let outcomes = BitcoinClientPool::new(nodes, 500).run().await.unwrap();
+    assert!(outcomes[0].result.is_ok());
+    let phases = outcomes[0].timings().unwrap().phases();
+    let (slowest, duration) = phases.iter().max_by_key(|(_, d)| *d).unwrap();
+    assert_eq!(*slowest, Phase::Verack);
+    assert!(*duration >= delay);
+}
@@ -0,0 +1,254 @@
+use std::net::SocketAddr;
+
+use bitcoin::{block::Header, consensus::Params, Network};
+use p2p_handshake_bitcoin::{
+    bitcoin::{
+        client_pool::PoolConfig,
+        headers::{Checkpoint, HeaderChain},
+        peer_address::PeerAddress,
+        tips::{TipConfig, TipReport, TipStatus, TipSurvey},
+    },
+    output::ReportFormat,
+};
+
+use crate::helper::{
+    mine_headers, regtest_chain, spawn_chain_node, spawn_chain_node_claiming, unmined_header,
+};
+
+fn regtest_pool() -> PoolConfig {
+    PoolConfig {
+        network: Network::Regtest,
+        ..PoolConfig::default()
+    }
+}
+
+async fn survey(nodes: &[SocketAddr], chain: Option<HeaderChain>) -> TipReport {
+    let nodes = nodes
+        .iter()
+        .map(|&address| PeerAddress::from(address))
+        .collect();
+    let survey = match chain {
+        Some(chain) => TipSurvey::with_chain(TipConfig::default(), regtest_pool(), chain),
+        None => TipSurvey::new(TipConfig::default(), regtest_pool()),
+    };
+    survey.run(nodes, |_| Ok(())).await.unwrap()
+}
+
+fn status_of(report: &TipReport, node: SocketAddr) -> &TipStatus {
+    &report
+        .peers
+        .iter()
+        .find(|peer| peer.node == PeerAddress::from(node))
+        .unwrap()
+        .status
+}
+
+/// Returns a chain which shares the first `shared + 1` headers of the
+/// chain and then continues with `count` headers of its own
+fn fork(chain: &[Header], shared: usize, count: usize) -> Vec<Header> {
+    let mut fork = chain[..=shared].to_vec();
+    mine_headers(&mut fork, count, 599, chain[0].bits);
+    fork
+}
+
+#[tokio::test]
+async fn nodes_are_compared_with_the_consensus_tip() {
+    let chain = regtest_chain(30);
+    let synced = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let also_synced = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let lagging = spawn_chain_node(Network::Regtest, chain[..=20].to_vec()).await;
+    let forked = spawn_chain_node(Network::Regtest, fork(&chain, 15, 20)).await;
+    let ahead = spawn_chain_node(Network::Regtest, fork(&chain, 30, 2)).await;
+    let unreachable: SocketAddr = "127.0.0.1:1".parse().unwrap();
+
+    let report = survey(
+        &[synced, also_synced, lagging, forked, ahead, unreachable],
+        None,
+    )
+    .await;
+
+    assert_eq!(report.attempted, 6);
+    assert_eq!(report.peers.len(), 5);
+    let consensus = report.consensus.unwrap();
+    assert_eq!(consensus.height, 30);
+    assert_eq!(consensus.hash, chain[30].block_hash());
+    assert_eq!(status_of(&report, synced), &TipStatus::Synced);
+    assert_eq!(status_of(&report, also_synced), &TipStatus::Synced);
+    assert_eq!(
+        status_of(&report, lagging),
+        &TipStatus::Lagging { blocks: 10 }
+    );
+    assert_eq!(
+        status_of(&report, forked),
+        &TipStatus::Forked { fork_height: 15 }
+    );
+    assert_eq!(status_of(&report, ahead), &TipStatus::Ahead { blocks: 2 });
+    assert_eq!(report.flagged().count(), 3);
+}
+
+#[tokio::test]
+async fn nodes_are_compared_with_a_consensus_tip_on_another_fork() {
+    let chain = regtest_chain(30);
+    let forked = fork(&chain, 15, 20);
+    let lagging = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let synced = spawn_chain_node(Network::Regtest, forked.clone()).await;
+    let also_synced = spawn_chain_node(Network::Regtest, forked.clone()).await;
+    let behind = spawn_chain_node(Network::Regtest, forked[..=25].to_vec()).await;
+
+    let report = survey(&[lagging, synced, also_synced, behind], None).await;
+
+    let consensus = report.consensus.unwrap();
+    assert_eq!(consensus.height, 35);
+    assert_eq!(consensus.hash, forked[35].block_hash());
+    assert_eq!(status_of(&report, synced), &TipStatus::Synced);
+    assert_eq!(status_of(&report, also_synced), &TipStatus::Synced);
+    assert_eq!(
+        status_of(&report, behind),
+        &TipStatus::Lagging { blocks: 10 }
+    );
+    assert_eq!(
+        status_of(&report, lagging),
+        &TipStatus::Forked { fork_height: 15 }
+    );
+}
+
+#[tokio::test]
+async fn nodes_within_the_tolerance_are_synced() {
+    let chain = regtest_chain(10);
+    let synced = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let also_synced = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let one_behind = spawn_chain_node(Network::Regtest, chain[..=9].to_vec()).await;
+
+    let report = survey(&[synced, also_synced, one_behind], None).await;
+
+    assert_eq!(status_of(&report, one_behind), &TipStatus::Synced);
+    assert_eq!(report.flagged().count(), 0);
+}
+
+#[tokio::test]
+async fn nodes_misreporting_their_start_height_are_flagged() {
+    let chain = regtest_chain(10);
+    let honest = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let liar = spawn_chain_node_claiming(Network::Regtest, chain.clone(), 500).await;
+
+    let report = survey(&[honest, liar], None).await;
+
+    let flagged: Vec<_> = report.flagged().collect();
+    assert_eq!(flagged.len(), 1);
+    assert_eq!(flagged[0].node, PeerAddress::from(liar));
+    assert_eq!(flagged[0].start_height, 500);
+    assert_eq!(flagged[0].status, TipStatus::Synced);
+    assert!(flagged[0].misreports_start_height);
+}
+
+#[tokio::test]
+async fn nodes_failing_partway_are_not_flagged_for_their_start_height() {
+    let chain = regtest_chain(10);
+    let mut invalid = chain[..=4].to_vec();
+    let bad = unmined_header(&invalid);
+    invalid.push(bad);
+    let honest = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let also_honest = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let failing = spawn_chain_node_claiming(Network::Regtest, invalid, 10).await;
+
+    let report = survey(&[honest, also_honest, failing], None).await;
+
+    let peer = report
+        .peers
+        .iter()
+        .find(|peer| peer.node == PeerAddress::from(failing))
+        .unwrap();
+    assert!(matches!(peer.status, TipStatus::Failed { .. }));
+    assert!(!peer.misreports_start_height);
+}
+
+#[tokio::test]
+async fn nodes_serving_invalid_headers_do_not_count_towards_consensus() {
+    let chain = regtest_chain(10);
+    let mut invalid = chain.clone();
+    mine_headers(&mut invalid, 5, 600, chain[0].bits);
+    let bad = unmined_header(&invalid);
+    invalid.push(bad);
+    let honest = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let cheater = spawn_chain_node(Network::Regtest, invalid.clone()).await;
+    let also_cheater = spawn_chain_node(Network::Regtest, invalid).await;
+
+    let report = survey(&[honest, cheater, also_cheater], None).await;
+
+    assert_eq!(report.consensus.unwrap().hash, chain[10].block_hash());
+    match status_of(&report, cheater) {
+        TipStatus::Failed { error } => assert!(error.contains("does not meet its target")),
+        other => panic!("Unexpected status {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn survey_starts_at_a_checkpoint() {
+    let chain = regtest_chain(2030);
+    let params = Params::new(Network::Regtest);
+    let checkpoint = Checkpoint {
+        height: 2016,
+        header: chain[2016],
+    };
+    let start = HeaderChain::from_checkpoint(params, checkpoint).unwrap();
+    let node = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let other_chain = spawn_chain_node(Network::Regtest, fork(&chain, 1000, 1100)).await;
+
+    let report = survey(&[node, other_chain], Some(start)).await;
+
+    let consensus = report.consensus.unwrap();
+    assert_eq!(consensus.height, 2030);
+    assert_eq!(consensus.hash, chain[2030].block_hash());
+    assert!(matches!(
+        status_of(&report, other_chain),
+        TipStatus::Failed { .. }
+    ));
+}
+
+#[test]
+fn checkpoint_must_be_at_a_difficulty_adjustment() {
+    let chain = regtest_chain(20);
+    let checkpoint = Checkpoint {
+        height: 20,
+        header: chain[20],
+    };
+    assert!(HeaderChain::from_checkpoint(Params::new(Network::Regtest), checkpoint).is_err());
+    assert!("20:00".parse::<Checkpoint>().is_err());
+    assert!("header".parse::<Checkpoint>().is_err());
+}
+
+#[tokio::test]
+async fn report_lists_consensus_tip_and_flagged_nodes() {
+    let chain = regtest_chain(10);
+    let synced = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let also_synced = spawn_chain_node(Network::Regtest, chain.clone()).await;
+    let lagging = spawn_chain_node(Network::Regtest, chain[..=5].to_vec()).await;
+    let report = survey(&[synced, also_synced, lagging], None).await;
+
+    let mut text = Vec::new();
+    report.write(&mut text, ReportFormat::Report).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.starts_with(&format!(
+        "Consensus tip: {} at height 10, served by 2 of 3 nodes\n",
+        chain[10].block_hash()
+    )));
+    assert!(text.contains("Nodes: 3, serving headers: 3, flagged: 1"));
+    let rows: Vec<&str> = text.lines().skip(3).collect();
+    assert!(rows[0].starts_with("NODE"));
+    assert!(rows[1].starts_with(&lagging.to_string()));
+    assert!(rows[1].ends_with("lagging by 5 blocks"));
+
+    let mut json = Vec::new();
+    report.write(&mut json, ReportFormat::Json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["consensus_height"], 10);
+    assert_eq!(json["peers"].as_array().unwrap().len(), 3);
+    let lagging_record = json["peers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|peer| peer["node"] == lagging.to_string())
+        .unwrap();
+    assert_eq!(lagging_record["status"], "lagging");
+    assert_eq!(lagging_record["blocks"], 5);
+}
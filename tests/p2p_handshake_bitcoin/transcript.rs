@@ -0,0 +1,144 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use p2p_handshake_bitcoin::bitcoin::{
+    client::BitcoinClient,
+    client_pool::{BitcoinClientPool, PoolConfig},
+    peer_address::PeerAddress,
+    retry::ErrorKind,
+    transcript::{Direction, ReplayPeer, SessionRecorder, Transcript, TranscriptEntry},
+};
+
+use crate::helper::spawn_local_node;
+
+/// Transcript of a handshake with a node answering like [spawn_local_node]
+const HANDSHAKE: &str = "tests/transcripts/handshake.ndjson";
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "p2p_handshake_bitcoin_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+fn entry(direction: Direction, time_us: u64, data: &[u8]) -> TranscriptEntry {
+    TranscriptEntry {
+        direction,
+        time_us,
+        data: data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+#[tokio::test]
+async fn recorded_session_replays_to_the_same_result() {
+    let directory = temp_path("sessions");
+    let node: PeerAddress = spawn_local_node().await.into();
+    let config = PoolConfig {
+        recorder: Some(Arc::new(SessionRecorder::new(&directory).unwrap())),
+        ..PoolConfig::default()
+    };
+    let outcomes = BitcoinClientPool::with_config(vec![node.clone()], config)
+        .run()
+        .await
+        .unwrap();
+    let recorded_version = outcomes[0].result.as_ref().unwrap();
+
+    let transcript =
+        Transcript::read(directory.join(SessionRecorder::file_name(&node, 1))).unwrap();
+    let directions: Vec<Direction> = transcript
+        .entries
+        .iter()
+        .map(|entry| entry.direction)
+        .collect();
+    assert_eq!(directions[0], Direction::Sent);
+    assert!(directions.contains(&Direction::Received));
+    let times: Vec<u64> = transcript.entries.iter().map(|e| e.time_us).collect();
+    assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+
+    let peer = ReplayPeer::new(&transcript).unwrap();
+    let progress = peer.progress();
+    let mut client = BitcoinClient::new(peer.rx, peer.tx);
+    let version = client.handshake().await.unwrap();
+    assert_eq!(version, *recorded_version);
+    assert!(progress.is_finished());
+    assert_eq!(
+        progress.written().len(),
+        transcript.bytes(Direction::Sent).len()
+    );
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn node_closing_after_version_fails_the_handshake() {
+    let mut transcript = Transcript::read(HANDSHAKE).unwrap();
+    let last_reply = transcript
+        .entries
+        .iter()
+        .rposition(|entry| entry.direction == Direction::Received)
+        .unwrap();
+    transcript.entries.truncate(last_reply);
+    transcript.entries.push(entry(Direction::Closed, 2000, &[]));
+
+    let peer = ReplayPeer::new(&transcript).unwrap();
+    let mut client = BitcoinClient::new(peer.rx, peer.tx);
+    let error = anyhow::Error::from(client.handshake().await.unwrap_err());
+    assert!(format!("{:#}", error).contains("verack"), "{:#}", error);
+}
+
+#[tokio::test]
+async fn silent_node_keeps_the_client_waiting() {
+    let mut transcript = Transcript::read(HANDSHAKE).unwrap();
+    transcript
+        .entries
+        .retain(|entry| entry.direction == Direction::Sent);
+
+    let peer = ReplayPeer::new(&transcript).unwrap();
+    let mut client = BitcoinClient::new(peer.rx, peer.tx);
+    let result = tokio::time::timeout(Duration::from_millis(100), client.handshake()).await;
+    let error = anyhow::Error::from(result.unwrap_err());
+    assert_eq!(ErrorKind::of(&error), ErrorKind::Timeout);
+}
+
+#[tokio::test]
+async fn realtime_replay_keeps_the_recorded_delays() {
+    let mut transcript = Transcript::read(HANDSHAKE).unwrap();
+    for entry in &mut transcript.entries {
+        if entry.direction == Direction::Received {
+            entry.time_us += 150_000;
+        }
+    }
+    let start = Instant::now();
+    let peer = ReplayPeer::new(&transcript).unwrap().realtime();
+    let mut client = BitcoinClient::new(peer.rx, peer.tx);
+    client.handshake().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn every_connection_gets_its_own_transcript() {
+    let directory = temp_path("connections");
+    let recorder = SessionRecorder::new(&directory).unwrap();
+    let node: PeerAddress = "[2001:db8::1]:8333".parse().unwrap();
+    recorder.open(&node).unwrap();
+    recorder.open(&node).unwrap();
+    assert!(directory.join("2001_db8__1_8333.1.ndjson").exists());
+    assert!(directory.join("2001_db8__1_8333.2.ndjson").exists());
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn invalid_transcripts_are_rejected() {
+    for content in [
+        "{\"direction\":\"sent\",\"time_us\":0}",
+        "{\"direction\":\"sideways\",\"time_us\":0,\"data\":\"\"}",
+        "{\"direction\":\"sent\",\"time_us\":0,\"data\":\"f9b\"}",
+    ] {
+        assert!(Transcript::parse(content).is_err(), "{}", content);
+    }
+}
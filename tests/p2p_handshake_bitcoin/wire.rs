@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use bitcoin::{consensus::serialize, p2p::message::RawNetworkMessage};
+use p2p_handshake_bitcoin::{
+    bitcoin::{
+        connection::Connection,
+        message::BitcoinMessage,
+        wire::{hex_dump, FrameHeader, DUMP_LIMIT},
+    },
+    telemetry::get_subscriber,
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::helper::{BitcoinNodeMock, Buffer};
+
+/// Returns a buffer and a guard which sends the logs of the current thread,
+/// at the level, to the buffer as long as the guard lives
+fn capture_logs(level: &str) -> (Buffer, tracing::subscriber::DefaultGuard) {
+    let buffer = Buffer::default();
+    let subscriber = get_subscriber("test".into(), level.into(), buffer.clone());
+    let guard = tracing::subscriber::set_default(subscriber);
+    (buffer, guard)
+}
+
+#[tokio::test]
+async fn frames_are_traced_in_both_directions() {
+    let (buffer, _guard) = capture_logs("trace");
+    let bitcoin_mock_node = BitcoinNodeMock::on_version_message_respond_with_verack_message();
+    let mut connection = Connection::new(bitcoin_mock_node.reader, bitcoin_mock_node.writer);
+
+    let version = serialize(&BitcoinMessage::version_message());
+    connection.write(&version).await.unwrap();
+    connection.read::<RawNetworkMessage>().await.unwrap();
+
+    let logs = buffer.contents();
+    let events: Vec<&str> = logs
+        .lines()
+        .filter(|l| l.contains("frame.command"))
+        .collect();
+    assert_eq!(events.len(), 2);
+
+    assert!(events[0].contains(r#""frame.direction":"sent""#));
+    assert!(events[0].contains(r#""frame.command":"version""#));
+    assert!(events[0].contains(&format!(r#""frame.length":{}"#, version.len() - 24)));
+    assert!(events[0].contains(r#""frame.checksum_valid":true"#));
+    assert!(events[0].contains("user_agent="));
+    assert!(events[0].contains(&hex_dump(&version, DUMP_LIMIT)));
+
+    let verack = serialize(&BitcoinMessage::verack_message());
+    assert!(events[1].contains(r#""frame.direction":"received""#));
+    assert!(events[1].contains(r#""frame.command":"verack""#));
+    assert!(events[1].contains(r#""frame.length":0"#));
+    assert!(events[1].contains(r#""frame.checksum_valid":true"#));
+    assert!(events[1].contains(&format!(
+        r#""frame.dump":"{}""#,
+        hex_dump(&verack, DUMP_LIMIT)
+    )));
+}
+
+#[tokio::test]
+async fn frames_are_not_traced_above_trace_level() {
+    let (buffer, _guard) = capture_logs("debug");
+    let bitcoin_mock_node = BitcoinNodeMock::on_version_message_respond_with_verack_message();
+    let mut connection = Connection::new(bitcoin_mock_node.reader, bitcoin_mock_node.writer);
+
+    let version = serialize(&BitcoinMessage::version_message());
+    connection.write(&version).await.unwrap();
+    connection.read::<RawNetworkMessage>().await.unwrap();
+
+    assert!(!buffer.contents().contains("frame.command"));
+}
+
+#[tokio::test]
+async fn frame_with_bad_checksum_is_traced_when_it_arrives() {
+    let (buffer, _guard) = capture_logs("trace");
+    let mut verack = serialize(&BitcoinMessage::verack_message());
+    verack[20] ^= 0xff;
+    let (mut node, client) = tokio::io::duplex(1024);
+    let (reader, writer) = tokio::io::split(client);
+    let mut connection = Connection::new(reader, writer);
+    node.write_all(&verack).await.unwrap();
+
+    let read = tokio::time::timeout(
+        Duration::from_millis(100),
+        connection.read::<RawNetworkMessage>(),
+    )
+    .await;
+
+    assert!(read.is_err());
+    let logs = buffer.contents();
+    assert!(logs.contains(r#""frame.command":"verack""#));
+    assert!(logs.contains(r#""frame.checksum_valid":false"#));
+    assert!(logs.contains("undecodable"));
+
+    drop(node);
+    assert!(connection.read::<RawNetworkMessage>().await.is_err());
+    let traced = buffer.contents().matches("frame.command").count();
+    assert_eq!(traced, 1);
+}
+
+#[tokio::test]
+async fn garbage_is_traced_as_incomplete_frame() {
+    let (buffer, _guard) = capture_logs("trace");
+    let bitcoin_mock_node = BitcoinNodeMock::bad_u8_slice_response_on_version_message();
+    let mut connection = Connection::new(bitcoin_mock_node.reader, bitcoin_mock_node.writer);
+
+    let version = serialize(&BitcoinMessage::version_message());
+    connection.write(&version).await.unwrap();
+    assert!(connection.read::<RawNetworkMessage>().await.is_err());
+
+    let logs = buffer.contents();
+    assert!(logs.contains("Incomplete frame received"));
+    assert!(logs.contains(r#""frame.dump":"010203""#));
+}
+
+#[test]
+fn header_is_parsed_from_raw_bytes() {
+    let version = serialize(&BitcoinMessage::version_message());
+    let header = FrameHeader::parse(&version).unwrap();
+    assert_eq!(header.magic, [0xf9, 0xbe, 0xb4, 0xd9]);
+    assert_eq!(header.command, "version");
+    assert_eq!(header.length as usize, version.len() - 24);
+    assert!(header.checksum_matches(&version[24..]));
+    assert!(!header.checksum_matches(&version[25..]));
+    assert_eq!(FrameHeader::parse(&version[..23]), None);
+}
+
+#[test]
+fn hex_dump_is_bounded() {
+    let data = vec![0xab; DUMP_LIMIT + 10];
+    let dump = hex_dump(&data, DUMP_LIMIT);
+    assert!(dump.starts_with(&"ab".repeat(DUMP_LIMIT)));
+    assert!(dump.ends_with(" (+10 bytes)"));
+}